@@ -0,0 +1,11 @@
+use bytecode::{parser::Parser, instruction::Instruction, functions::Functions};
+
+pub fn parse(buffer: &str) -> (Vec<Instruction>, Functions) {
+    let bytecode = Parser::parse_code(buffer);
+    let functions = Parser::parse_functions(&bytecode).unwrap();
+    let mut variables = Parser::parse_variables(&bytecode).unwrap();
+    let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
+    let instructions = Parser::parse_instructions(&bytecode, &functions, &mut variables, &labels).unwrap();
+
+    (instructions, functions)
+}
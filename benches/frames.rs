@@ -0,0 +1,90 @@
+mod common;
+
+use std::{hint::black_box, io, time::{Duration, Instant}};
+
+use bytecode::{
+    vm::VirtualMachine,
+    limits::Limits,
+    verifier::Verifier,
+};
+
+use common::parse;
+
+const ITERATIONS: u32 = 20;
+
+const DEPTH: usize = 10_000;
+
+const REPEATS: usize = 20;
+
+const CALLS: &str = "
+FUNC SQUARE
+    LOAD_VAL 12
+    WRITE_VAR 'x'
+    READ_VAR 'x'
+    READ_VAR 'x'
+    MULTIPLY
+    RETURN_VAL
+
+LOAD_VAL 0
+WRITE_VAR 'i'
+
+LABEL LOOP
+    CALL SQUARE
+    DROP
+
+    READ_VAR 'i'
+    LOAD_VAL 1
+    ADD
+    WRITE_VAR 'i'
+
+    READ_VAR 'i'
+    LOAD_VAL 200000
+    JUMP_IF_SM LOOP
+";
+
+// Calls can't pass arguments, so a recursion with a base case is unrolled into a chain of
+// functions, each calling the next one until the last one returns.
+fn call_chain(depth: usize, repeats: usize) -> String {
+    let mut buffer = String::new();
+
+    for level in 0..depth {
+        buffer.push_str(&format!(
+            "FUNC LEVEL{}\nLOAD_VAL 1\nWRITE_VAR 'a'\nLOAD_VAL 2\nWRITE_VAR 'b'\nREAD_VAR 'a'\nREAD_VAR 'b'\nADD\n",
+            level
+        ));
+        if level + 1 < depth {
+            buffer.push_str(&format!("CALL LEVEL{}\nADD\n", level + 1));
+        }
+        buffer.push_str("RETURN_VAL\n");
+    }
+    for _ in 0..repeats {
+        buffer.push_str("CALL LEVEL0\nDROP\n");
+    }
+
+    buffer
+}
+
+fn measure(buffer: &str, limits: Limits) -> Duration {
+    let (instructions, functions) = parse(buffer);
+    let mut best = Duration::MAX;
+
+    for _ in 0..ITERATIONS {
+        let program = Verifier::verify(instructions.clone(), &functions).unwrap();
+        let mut vm = VirtualMachine::new(limits.clone());
+        vm.set_output(Some(Box::new(io::sink())));
+
+        let started = Instant::now();
+        black_box(vm.run_verified(program).unwrap());
+        best = best.min(started.elapsed());
+    }
+
+    best
+}
+
+fn main() {
+    let recursion = measure(&call_chain(DEPTH, REPEATS), Limits { max_call_depth: DEPTH, ..Limits::default() });
+    let calls = measure(CALLS, Limits::default());
+
+    println!("{} chains of {} nested calls: {:>10.2?}", REPEATS, DEPTH, recursion);
+    println!("200000 calls in a loop:        {:>10.2?}", calls);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::{hint::black_box, io, time::{Duration, Instant}};
+
+use bytecode::{
+    vm::VirtualMachine,
+    instruction::Instruction,
+    functions::Functions,
+    optimizer::Optimizer,
+    verifier::Verifier,
+};
+
+use common::parse;
+
+const ITERATIONS: u32 = 20;
+
+const LOOP: &str = "
+LOAD_VAL 0
+WRITE_VAR 'x'
+LOAD_VAL 0
+WRITE_VAR 'sum'
+
+LABEL LOOP
+    READ_VAR 'sum'
+    READ_VAR 'x'
+    ADD
+    WRITE_VAR 'sum'
+
+    READ_VAR 'x'
+    LOAD_VAL 1
+    ADD
+    WRITE_VAR 'x'
+
+    READ_VAR 'x'
+    LOAD_VAL 1000000
+    JUMP_IF_SM LOOP
+
+PRINT 'sum'
+";
+
+fn measure(instructions: &[Instruction], functions: &Functions) -> Duration {
+    let mut best = Duration::MAX;
+
+    for _ in 0..ITERATIONS {
+        let program = Verifier::verify(instructions.to_vec(), functions).unwrap();
+        let mut vm = VirtualMachine::default();
+        vm.set_output(Some(Box::new(io::sink())));
+
+        let started = Instant::now();
+        black_box(vm.run_verified(program).unwrap());
+        best = best.min(started.elapsed());
+    }
+
+    best
+}
+
+fn main() {
+    let (instructions, mut functions) = parse(LOOP);
+    let (lowered, _) = Optimizer::lower(instructions, &mut functions);
+    let plain = measure(&lowered, &functions);
+
+    let (fused, _) = Optimizer::fuse(lowered.clone(), &mut functions);
+    let superinstructions = measure(&fused, &functions);
+
+    println!("plain:             {:>10.2?} ({} instructions)", plain, lowered.len());
+    println!("superinstructions: {:>10.2?} ({} instructions)", superinstructions, fused.len());
+    println!("speedup:           {:>9.2}x", plain.as_secs_f64() / superinstructions.as_secs_f64());
+}
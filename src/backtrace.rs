@@ -0,0 +1,120 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use crate::{vm::Pointer, functions::Functions, labels::Labels, optimizer::Relocation};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DebugInfo {
+    pub functions: Functions,
+    pub lines: Vec<usize>,
+    pub labels: Labels,
+}
+
+impl DebugInfo {
+    pub fn new(functions: Functions, lines: Vec<usize>) -> Self {
+        Self { functions, lines, labels: Labels::new() }
+    }
+
+    pub fn with_labels(mut self, labels: Labels) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn relocate(&mut self, relocation: &Relocation, functions: &Functions) {
+        self.functions = functions.clone();
+        self.lines = relocation.relocate_values(&self.lines);
+
+        for ip in self.labels.pointers_mut() {
+            *ip = relocation.get(*ip);
+        }
+    }
+
+    pub fn function_of(&self, ip: Pointer) -> &str {
+        self.functions.scope_of(ip)
+    }
+
+    pub fn line_of(&self, ip: Pointer) -> Option<usize> {
+        self.lines.get(ip).copied()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BacktraceFrame {
+    pub ip: Pointer,
+    pub function: Option<String>,
+    pub line: Option<usize>,
+}
+
+impl BacktraceFrame {
+    pub fn new(ip: Pointer, debug_info: Option<&DebugInfo>) -> Self {
+        Self {
+            ip,
+            function: debug_info.map(|debug_info| debug_info.function_of(ip).to_string()),
+            line: debug_info.and_then(|debug_info| debug_info.line_of(ip)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backtrace(Vec<BacktraceFrame>);
+
+impl Backtrace {
+    pub fn new(frames: Vec<BacktraceFrame>) -> Self {
+        Self(frames)
+    }
+
+    pub fn frames(&self) -> &[BacktraceFrame] {
+        &self.0
+    }
+
+    pub fn ips(&self) -> Vec<Pointer> {
+        self.0
+            .iter()
+            .map(|frame| frame.ip)
+            .collect()
+    }
+}
+
+impl Display for Backtrace {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "stack backtrace:")?;
+
+        self.0
+            .iter()
+            .enumerate()
+            .try_for_each(|(depth, frame)| {
+                write!(f, "\n{:>4}: {}", depth, frame.function.as_deref().unwrap_or("<unknown>"))?;
+                write!(f, "\n             at instruction #{}", frame.ip)?;
+
+                match frame.line {
+                    Some(line) => write!(f, ", line {}", line),
+                    None => Ok(()),
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let mut functions = Functions::new();
+        functions.insert("TEST", (10, 13)).unwrap();
+        let debug_info = DebugInfo::new(functions, (1..=20).collect());
+
+        let backtrace = Backtrace::new(vec![
+            BacktraceFrame::new(12, Some(&debug_info)),
+            BacktraceFrame::new(4, Some(&debug_info)),
+            BacktraceFrame::new(2, None),
+        ]);
+
+        assert_eq!(
+            backtrace.to_string(),
+            "stack backtrace:\n   \
+            0: TEST\n             at instruction #12, line 13\n   \
+            1: MAIN\n             at instruction #4, line 5\n   \
+            2: <unknown>\n             at instruction #2"
+        );
+    }
+}
@@ -0,0 +1,396 @@
+use std::{collections::{BTreeSet, HashMap}, fmt::Write};
+
+use crate::{vm::Pointer, instruction::Instruction, functions::{Functions, MAIN_FUNCTION}, labels::Labels};
+
+pub type BlockId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub start_ip: Pointer,
+    pub end_ip: Pointer,
+    pub function: String,
+    pub label: Option<String>,
+    pub successors: Vec<BlockId>,
+    pub predecessors: Vec<BlockId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlFlowGraph {
+    blocks: Vec<BasicBlock>,
+    block_ids: Vec<BlockId>,
+    entries: Vec<(String, BlockId)>,
+}
+
+impl ControlFlowGraph {
+    pub fn new(program: &[Instruction], functions: &Functions, labels: &Labels) -> Self {
+        let mut leaders = BTreeSet::new();
+
+        if !program.is_empty() {
+            leaders.insert(0);
+        }
+        for (_, _, ip) in labels.iter() {
+            leaders.insert(ip);
+        }
+        for (_, (start_ip, end_ip)) in functions.iter() {
+            leaders.extend([*start_ip, start_ip + 1, end_ip + 1]);
+        }
+        for (ip, instruction) in program.iter().enumerate() {
+            let successors = instruction.successors(ip);
+
+            if successors != [ip + 1] {
+                leaders.extend(successors);
+                leaders.insert(ip + 1);
+            }
+        }
+        leaders.retain(|ip| *ip < program.len());
+
+        let leaders = leaders.into_iter().collect::<Vec<_>>();
+        let mut block_ids = vec![0; program.len()];
+        let mut blocks = vec![];
+
+        for (block_id, start_ip) in leaders.iter().enumerate() {
+            let end_ip = leaders
+                .get(block_id + 1)
+                .map_or(program.len(), |next_ip| *next_ip) - 1;
+
+            block_ids[*start_ip..=end_ip].fill(block_id);
+
+            // The FUNC jump belongs to the code around the function, which it skips.
+            let function = functions
+                .iter()
+                .find(|(_, (func_start_ip, func_end_ip))| func_start_ip < start_ip && start_ip <= func_end_ip)
+                .map_or(MAIN_FUNCTION, |(func_name, _)| func_name.as_str());
+            let label = labels
+                .iter()
+                .find(|(_, _, ip)| ip == start_ip)
+                .map(|(_, label_name, _)| label_name.to_string());
+
+            blocks.push(BasicBlock {
+                start_ip: *start_ip,
+                end_ip,
+                function: function.to_string(),
+                label,
+                successors: vec![],
+                predecessors: vec![],
+            });
+        }
+
+        for block_id in 0..blocks.len() {
+            let end_ip = blocks[block_id].end_ip;
+
+            for target_ip in program[end_ip].successors(end_ip) {
+                let Some(target_id) = block_ids.get(target_ip).copied() else {
+                    continue;
+                };
+
+                if !blocks[block_id].successors.contains(&target_id) {
+                    blocks[block_id].successors.push(target_id);
+                    blocks[target_id].predecessors.push(block_id);
+                }
+            }
+        }
+
+        let mut entries = functions
+            .iter()
+            .filter(|(_, (start_ip, end_ip))| start_ip < end_ip)
+            .map(|(func_name, (start_ip, _))| (func_name.to_string(), block_ids[start_ip + 1]))
+            .collect::<Vec<_>>();
+        if !program.is_empty() {
+            entries.push((MAIN_FUNCTION.to_string(), 0));
+        }
+        entries.sort_by_key(|(_, block_id)| *block_id);
+
+        ControlFlowGraph { blocks, block_ids, entries }
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    pub fn block(&self, block_id: BlockId) -> &BasicBlock {
+        &self.blocks[block_id]
+    }
+
+    pub fn block_of(&self, ip: Pointer) -> Option<BlockId> {
+        self.block_ids.get(ip).copied()
+    }
+
+    pub fn successors(&self, block_id: BlockId) -> &[BlockId] {
+        &self.blocks[block_id].successors
+    }
+
+    pub fn predecessors(&self, block_id: BlockId) -> &[BlockId] {
+        &self.blocks[block_id].predecessors
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = (&str, BlockId)> {
+        self.entries
+            .iter()
+            .map(|(func_name, block_id)| (func_name.as_str(), *block_id))
+    }
+
+    pub fn entry(&self, function: &str) -> Option<BlockId> {
+        self.functions()
+            .find(|(func_name, _)| *func_name == function)
+            .map(|(_, block_id)| block_id)
+    }
+
+    pub fn function_blocks(&self, function: &str) -> Vec<BlockId> {
+        (0..self.blocks.len())
+            .filter(|block_id| self.blocks[*block_id].function == function)
+            .collect()
+    }
+
+    pub fn dominators(&self, function: &str) -> Option<DominatorTree> {
+        let entry = self.entry(function)?;
+        let order = self.reverse_postorder(entry);
+        let mut positions = HashMap::new();
+
+        for (position, block_id) in order.iter().enumerate() {
+            positions.insert(*block_id, position);
+        }
+
+        let mut idoms = HashMap::from([(entry, entry)]);
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for block_id in order.iter().skip(1) {
+                let mut processed = self.predecessors(*block_id)
+                    .iter()
+                    .filter(|pred_id| idoms.contains_key(*pred_id));
+
+                let Some(first_pred) = processed.next() else {
+                    continue;
+                };
+                let idom = processed.fold(*first_pred, |idom, pred_id| {
+                    ControlFlowGraph::intersect(&idoms, &positions, *pred_id, idom)
+                });
+
+                if idoms.insert(*block_id, idom) != Some(idom) {
+                    changed = true;
+                }
+            }
+        }
+
+        idoms.remove(&entry);
+
+        Some(DominatorTree { entry, idoms })
+    }
+
+    pub fn dot(&self, function: &str, program: &[Instruction]) -> Option<String> {
+        self.entry(function)?;
+
+        let mut dot = String::new();
+
+        writeln!(dot, "digraph {} {{", dot_string(function)).unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for block_id in self.function_blocks(function) {
+            let block = &self.blocks[block_id];
+            let mut label = String::new();
+
+            if let Some(label_name) = &block.label {
+                write!(label, "{}:\\l", label_name).unwrap();
+            }
+            for (ip, instruction) in program.iter().enumerate().take(block.end_ip + 1).skip(block.start_ip) {
+                write!(label, "{:>4}  {}\\l", ip, instruction).unwrap();
+            }
+
+            writeln!(dot, "    b{} [label={}];", block_id, dot_string(&label)).unwrap();
+        }
+
+        for block_id in self.function_blocks(function) {
+            let block = &self.blocks[block_id];
+            let branch = program[block.end_ip]
+                .branch_target()
+                .and_then(|target_ip| self.block_of(target_ip));
+
+            for successor_id in block.successors.iter() {
+                match branch {
+                    Some(target_id) if target_id == *successor_id => {
+                        writeln!(dot, "    b{} -> b{} [label=\"true\"];", block_id, successor_id).unwrap();
+                    },
+                    Some(_) => writeln!(dot, "    b{} -> b{} [label=\"false\"];", block_id, successor_id).unwrap(),
+                    None => writeln!(dot, "    b{} -> b{};", block_id, successor_id).unwrap(),
+                }
+            }
+        }
+
+        writeln!(dot, "}}").unwrap();
+
+        Some(dot)
+    }
+
+    fn reverse_postorder(&self, entry: BlockId) -> Vec<BlockId> {
+        let function = &self.blocks[entry].function;
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = vec![];
+        let mut stack = vec![(entry, 0)];
+        visited[entry] = true;
+
+        while let Some((block_id, next)) = stack.pop() {
+            match self.successors(block_id).get(next) {
+                Some(successor_id) => {
+                    stack.push((block_id, next + 1));
+
+                    if !visited[*successor_id] && self.blocks[*successor_id].function == *function {
+                        visited[*successor_id] = true;
+                        stack.push((*successor_id, 0));
+                    }
+                },
+                None => order.push(block_id),
+            }
+        }
+
+        order.reverse();
+        order
+    }
+
+    fn intersect(
+        idoms: &HashMap<BlockId, BlockId>,
+        positions: &HashMap<BlockId, usize>,
+        mut lhs: BlockId,
+        mut rhs: BlockId,
+    ) -> BlockId {
+        while lhs != rhs {
+            while positions[&lhs] > positions[&rhs] {
+                lhs = idoms[&lhs];
+            }
+            while positions[&rhs] > positions[&lhs] {
+                rhs = idoms[&rhs];
+            }
+        }
+
+        lhs
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DominatorTree {
+    entry: BlockId,
+    idoms: HashMap<BlockId, BlockId>,
+}
+
+impl DominatorTree {
+    pub fn entry(&self) -> BlockId {
+        self.entry
+    }
+
+    pub fn immediate_dominator(&self, block_id: BlockId) -> Option<BlockId> {
+        self.idoms.get(&block_id).copied()
+    }
+
+    pub fn dominates(&self, dominator_id: BlockId, mut block_id: BlockId) -> bool {
+        if block_id != self.entry && !self.idoms.contains_key(&block_id) {
+            return false;
+        }
+
+        loop {
+            if block_id == dominator_id {
+                return true;
+            }
+
+            match self.immediate_dominator(block_id) {
+                Some(idom) => block_id = idom,
+                None => return false,
+            }
+        }
+    }
+
+    pub fn children(&self, block_id: BlockId) -> Vec<BlockId> {
+        let mut children = self.idoms
+            .iter()
+            .filter(|(_, idom)| **idom == block_id)
+            .map(|(child_id, _)| *child_id)
+            .collect::<Vec<_>>();
+        children.sort();
+
+        children
+    }
+}
+
+fn dot_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn graph(buffer: &str) -> (ControlFlowGraph, Vec<Instruction>) {
+        let bytecode = Parser::parse_code(buffer);
+        let functions = Parser::parse_functions(&bytecode).unwrap();
+        let mut variables = Parser::parse_variables(&bytecode).unwrap();
+        let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
+        let instructions = Parser::parse_instructions(&bytecode, &functions, &mut variables, &labels).unwrap();
+
+        (ControlFlowGraph::new(&instructions, &functions, &labels), instructions)
+    }
+
+    const LOOP: &str = "\
+        FUNC ABS\nLOAD_VAL 0\nJUMP_IF_GR POSITIVE\nNEG\nLABEL POSITIVE\nRETURN_VAL\n\
+        LOAD_VAL 0\nWRITE_VAR 'x'\nLABEL LOOP\nREAD_VAR 'x'\nLOAD_VAL 10\nJUMP_IF_GREQ END\n\
+        READ_VAR 'x'\nLOAD_VAL 1\nADD\nWRITE_VAR 'x'\nJUMP LOOP\nLABEL END\nPRINT 'x'";
+
+    #[test]
+    fn new() {
+        let (graph, _) = graph(LOOP);
+
+        let ranges = graph.blocks()
+            .iter()
+            .map(|block| (block.start_ip, block.end_ip, block.function.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            ranges,
+            vec![
+                (0, 0, MAIN_FUNCTION), (1, 2, "ABS"), (3, 3, "ABS"), (4, 5, "ABS"),
+                (6, 7, MAIN_FUNCTION), (8, 11, MAIN_FUNCTION), (12, 16, MAIN_FUNCTION), (17, 18, MAIN_FUNCTION),
+            ]
+        );
+        assert_eq!(graph.successors(0), &[4]);
+        assert_eq!(graph.successors(1), &[2, 3]);
+        assert_eq!(graph.predecessors(5), &[4, 6]);
+        assert_eq!(graph.block(5).label.as_deref(), Some("LOOP"));
+        assert_eq!(graph.block_of(14), Some(6));
+        assert_eq!(graph.functions().collect::<Vec<_>>(), vec![(MAIN_FUNCTION, 0), ("ABS", 1)]);
+    }
+
+    #[test]
+    fn dominators() {
+        let (graph, _) = graph(LOOP);
+
+        let main = graph.dominators(MAIN_FUNCTION).unwrap();
+        let abs = graph.dominators("ABS").unwrap();
+
+        assert_eq!(main.immediate_dominator(5), Some(4));
+        assert_eq!(main.immediate_dominator(7), Some(5));
+        assert_eq!(main.children(5), vec![6, 7]);
+        assert!(main.dominates(0, 7));
+        assert!(!main.dominates(6, 7));
+        assert_eq!(abs.immediate_dominator(3), Some(1));
+        assert!(!abs.dominates(2, 3));
+        assert!(graph.dominators("MISSING").is_none());
+    }
+
+    #[test]
+    fn dot() {
+        let (graph, program) = graph("LOAD_VAL 1\nJUMP_IF_TRUE END\nPRINT \"x\"\nLABEL END");
+
+        assert_eq!(
+            graph.dot(MAIN_FUNCTION, &program).unwrap(),
+            "digraph \"MAIN\" {\n    \
+                node [shape=box, fontname=\"monospace\"];\n    \
+                b0 [label=\"   0  LOAD_VAL 1\\l   1  JUMP_IF_TRUE 3\\l\"];\n    \
+                b1 [label=\"   2  PRINT x #0\\l\"];\n    \
+                b2 [label=\"END:\\l   3  NOP\\l\"];\n    \
+                b0 -> b1 [label=\"false\"];\n    \
+                b0 -> b2 [label=\"true\"];\n    \
+                b1 -> b2;\n\
+            }\n"
+        );
+    }
+}
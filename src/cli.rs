@@ -0,0 +1,414 @@
+use std::{str::FromStr, slice::Iter, time::Duration};
+
+use bytecode::{limits::Limits, tracer::{TraceFormat, TraceFilter}};
+
+pub const STDIN_PATH: &str = "-";
+
+pub const JIT_THRESHOLD: usize = 100;
+
+pub const USAGE: &str = "\
+Usage:
+    bytecode run [--optimize] [--optimize-report] [--engine stack|register] [--jit] [--jit-threshold N] [--trace | --trace-json] [--trace-function NAME] [--trace-range START:END]
+                 [--trace-output FILE] [--profile] [--profile-folded FILE]
+                 [--coverage] [--coverage-lcov FILE] [--max-steps N] [--max-memory BYTES] [--max-call-depth N]
+                 [--max-operand-depth N] [--max-output BYTES] [--max-time MS] <file>
+    bytecode check <file>
+    bytecode disasm <file>
+    bytecode cfg <file> [-o <directory>]
+    bytecode fmt <file> [-o <output>]
+    bytecode compile <file> -o <output>
+    bytecode assemble <file> -o <output>
+    bytecode link <object>... -o <output>
+    bytecode <file>
+
+Use '-' as <file> to read the program from the standard input.";
+
+#[derive(Debug, PartialEq)]
+pub struct TraceOptions {
+    pub format: TraceFormat,
+    pub filter: TraceFilter,
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Engine {
+    #[default]
+    Stack,
+    Register,
+}
+
+impl FromStr for Engine {
+    type Err = ();
+
+    fn from_str(engine: &str) -> Result<Self, Self::Err> {
+        match engine {
+            "stack" => Ok(Engine::Stack),
+            "register" => Ok(Engine::Register),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Default)]
+pub struct RunOptions {
+    pub optimize: bool,
+    pub optimize_report: bool,
+    pub engine: Engine,
+    pub jit: Option<usize>,
+    pub trace: Option<TraceOptions>,
+    pub profile: bool,
+    pub profile_folded: Option<String>,
+    pub coverage: bool,
+    pub coverage_lcov: Option<String>,
+    pub max_steps: Option<usize>,
+    pub limits: Limits,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run { input: String, options: Box<RunOptions> },
+    Check { input: String },
+    Disasm { input: String },
+    Cfg { input: String, output: Option<String> },
+    Fmt { input: String, output: Option<String> },
+    Compile { input: String, output: String },
+    Assemble { input: String, output: String },
+    Link { inputs: Vec<String>, output: String },
+    Help,
+}
+
+impl Command {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let (command, args) = match args.split_first() {
+            Some((command, args)) => (command.as_str(), args),
+            None => return Err("Missing command.".to_string()),
+        };
+
+        match command {
+            "run" => Self::parse_run(args),
+            "check" => Ok(Self::Check { input: Self::single_input(args)? }),
+            "disasm" => Ok(Self::Disasm { input: Self::single_input(args)? }),
+            "fmt" | "cfg" => {
+                let (inputs, output) = Self::split_output(args)?;
+                let input = Self::single_input(&inputs)?;
+
+                match command {
+                    "fmt" => Ok(Self::Fmt { input, output }),
+                    _ => Ok(Self::Cfg { input, output }),
+                }
+            },
+            "compile" | "assemble" => {
+                let (inputs, output) = Self::split_output(args)?;
+                let input = Self::single_input(&inputs)?;
+                let output = output.ok_or("Missing output file, use -o <output>.")?;
+
+                match command {
+                    "compile" => Ok(Self::Compile { input, output }),
+                    _ => Ok(Self::Assemble { input, output }),
+                }
+            },
+            "link" => {
+                let (inputs, output) = Self::split_output(args)?;
+                let output = output.ok_or("Missing output file, use -o <output>.")?;
+
+                if inputs.is_empty() {
+                    return Err("Missing input files.".to_string());
+                }
+
+                Ok(Self::Link { inputs, output })
+            },
+            "help" | "-h" | "--help" => Ok(Self::Help),
+            _ if command.starts_with('-') && command != STDIN_PATH => {
+                Err(format!("Unknown option '{}'.", command))
+            },
+            _ => Self::parse_run(&[command.to_string()]).and_then(|run| match args {
+                [] => Ok(run),
+                _ => Err(format!("Unexpected argument '{}'.", args[0])),
+            }),
+        }
+    }
+
+    fn parse_run(args: &[String]) -> Result<Self, String> {
+        let mut options = RunOptions::default();
+        let mut trace_format = None;
+        let mut trace_filter = TraceFilter::default();
+        let mut trace_output = None;
+        let mut inputs = vec![];
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--optimize" | "-O" => options.optimize = true,
+                "--optimize-report" => {
+                    options.optimize = true;
+                    options.optimize_report = true;
+                },
+                "--engine" => options.engine = Self::option_value(arg, &mut args)?,
+                "--jit" => options.jit = options.jit.or(Some(JIT_THRESHOLD)),
+                "--jit-threshold" => options.jit = Some(Self::option_value(arg, &mut args)?),
+                "--trace" => trace_format = Some(TraceFormat::Text),
+                "--trace-json" => trace_format = Some(TraceFormat::JsonLines),
+                "--trace-function" => trace_filter.function = Some(Self::option_value(arg, &mut args)?),
+                "--trace-range" => {
+                    let range: String = Self::option_value(arg, &mut args)?;
+                    let (start_ip, end_ip) = range
+                        .split_once(':')
+                        .and_then(|(start_ip, end_ip)| Some((start_ip.parse().ok()?, end_ip.parse().ok()?)))
+                        .ok_or(format!("Invalid value '{}' for '{}'.", range, arg))?;
+
+                    trace_filter.range = Some((start_ip, end_ip));
+                },
+                "--trace-output" => trace_output = Some(Self::option_value(arg, &mut args)?),
+                "--profile" => options.profile = true,
+                "--profile-folded" => options.profile_folded = Some(Self::option_value(arg, &mut args)?),
+                "--coverage" => options.coverage = true,
+                "--coverage-lcov" => options.coverage_lcov = Some(Self::option_value(arg, &mut args)?),
+                "--max-steps" => options.max_steps = Some(Self::option_value(arg, &mut args)?),
+                "--max-memory" => options.limits.max_memory = Some(Self::option_value(arg, &mut args)?),
+                "--max-call-depth" => options.limits.max_call_depth = Self::option_value(arg, &mut args)?,
+                "--max-operand-depth" => options.limits.max_operand_depth = Self::option_value(arg, &mut args)?,
+                "--max-output" => options.limits.max_output = Some(Self::option_value(arg, &mut args)?),
+                "--max-time" => {
+                    options.limits.max_time = Some(Duration::from_millis(Self::option_value(arg, &mut args)?));
+                },
+                _ => inputs.push(arg.to_string()),
+            }
+        }
+
+        let is_traced = trace_format.is_some()
+            || trace_filter != TraceFilter::default()
+            || trace_output.is_some();
+        options.trace = is_traced.then(|| TraceOptions {
+            format: trace_format.unwrap_or(TraceFormat::Text),
+            filter: trace_filter,
+            output: trace_output,
+        });
+
+        let is_instrumented = options.trace.is_some()
+            || options.profile
+            || options.profile_folded.is_some()
+            || options.coverage
+            || options.coverage_lcov.is_some();
+        if options.engine == Engine::Register && is_instrumented {
+            return Err("The register engine doesn't support tracing, profiling or coverage.".to_string());
+        }
+        if options.jit.is_some() && !cfg!(feature = "jit") {
+            return Err("The JIT isn't available, build with '--features jit'.".to_string());
+        }
+        if options.jit.is_some() && options.engine == Engine::Register {
+            return Err("The JIT only runs on the stack engine.".to_string());
+        }
+
+        Ok(Self::Run { input: Self::single_input(&inputs)?, options: Box::new(options) })
+    }
+
+    fn option_value<T: FromStr>(option: &str, args: &mut Iter<String>) -> Result<T, String> {
+        let value = args
+            .next()
+            .ok_or(format!("Missing value for '{}'.", option))?;
+
+        value
+            .parse()
+            .map_err(|_| format!("Invalid value '{}' for '{}'.", value, option))
+    }
+
+    fn single_input(args: &[String]) -> Result<String, String> {
+        match args {
+            [input] if input == STDIN_PATH || !input.starts_with('-') => Ok(input.to_string()),
+            [arg] => Err(format!("Unknown option '{}'.", arg)),
+            [] => Err("Missing input file.".to_string()),
+            [_, arg, ..] => Err(format!("Unexpected argument '{}'.", arg)),
+        }
+    }
+
+    fn split_output(args: &[String]) -> Result<(Vec<String>, Option<String>), String> {
+        let mut inputs = vec![];
+        let mut output = None;
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" => {
+                    let path = args.next().ok_or("Missing value for '-o'.")?;
+                    output = Some(path.to_string());
+                },
+                _ => inputs.push(arg.to_string()),
+            }
+        }
+
+        Ok((inputs, output))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parse_run() {
+        assert_eq!(
+            Command::parse(&args("run --trace --max-steps 100 prog.bytecode")).unwrap(),
+            Command::Run {
+                input: "prog.bytecode".to_string(),
+                options: Box::new(RunOptions {
+                    trace: Some(TraceOptions {
+                        format: TraceFormat::Text,
+                        filter: TraceFilter::default(),
+                        output: None,
+                    }),
+                    max_steps: Some(100),
+                    ..RunOptions::default()
+                }),
+            }
+        );
+        assert_eq!(
+            Command::parse(&args("prog.bytecode")).unwrap(),
+            Command::Run { input: "prog.bytecode".to_string(), options: Box::new(RunOptions::default()) }
+        );
+        assert_eq!(
+            Command::parse(&args("run -")).unwrap(),
+            Command::Run { input: STDIN_PATH.to_string(), options: Box::new(RunOptions::default()) }
+        );
+    }
+
+    #[test]
+    fn parse_run_limits() {
+        let command = Command::parse(
+            &args("run --max-memory 4096 --max-call-depth 16 --max-output 100 --max-time 250 -")
+        );
+
+        let expected_limits = Limits {
+            max_memory: Some(4096),
+            max_call_depth: 16,
+            max_output: Some(100),
+            max_time: Some(Duration::from_millis(250)),
+            ..Limits::default()
+        };
+
+        assert_eq!(
+            command.unwrap(),
+            Command::Run {
+                input: STDIN_PATH.to_string(),
+                options: Box::new(RunOptions { limits: expected_limits, ..RunOptions::default() }),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_run_trace() {
+        let command = Command::parse(
+            &args("run --trace-json --trace-function TEST --trace-range 2:10 --trace-output trace.jsonl -")
+        );
+
+        let expected_trace = TraceOptions {
+            format: TraceFormat::JsonLines,
+            filter: TraceFilter { function: Some("TEST".to_string()), range: Some((2, 10)) },
+            output: Some("trace.jsonl".to_string()),
+        };
+
+        assert_eq!(
+            command.unwrap(),
+            Command::Run {
+                input: STDIN_PATH.to_string(),
+                options: Box::new(RunOptions { trace: Some(expected_trace), ..RunOptions::default() }),
+            }
+        );
+        assert!(Command::parse(&args("run --trace-range 10 -")).is_err());
+    }
+
+    #[test]
+    fn parse_run_profile_and_coverage() {
+        assert_eq!(
+            Command::parse(&args("run -O --profile --profile-folded prog.folded prog.bytecode")).unwrap(),
+            Command::Run {
+                input: "prog.bytecode".to_string(),
+                options: Box::new(RunOptions {
+                    optimize: true,
+                    profile: true,
+                    profile_folded: Some("prog.folded".to_string()),
+                    ..RunOptions::default()
+                }),
+            }
+        );
+        assert_eq!(
+            Command::parse(&args("run --optimize-report prog.bytecode")).unwrap(),
+            Command::Run {
+                input: "prog.bytecode".to_string(),
+                options: Box::new(RunOptions { optimize: true, optimize_report: true, ..RunOptions::default() }),
+            }
+        );
+        assert_eq!(
+            Command::parse(&args("run --engine register prog.bytecode")).unwrap(),
+            Command::Run {
+                input: "prog.bytecode".to_string(),
+                options: Box::new(RunOptions { engine: Engine::Register, ..RunOptions::default() }),
+            }
+        );
+        assert_eq!(
+            Command::parse(&args("run --coverage --coverage-lcov prog.lcov prog.bytecode")).unwrap(),
+            Command::Run {
+                input: "prog.bytecode".to_string(),
+                options: Box::new(RunOptions {
+                    coverage: true,
+                    coverage_lcov: Some("prog.lcov".to_string()),
+                    ..RunOptions::default()
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_run_jit() {
+        let command = Command::parse(&args("run --jit-threshold 5 prog.bytecode"));
+
+        match cfg!(feature = "jit") {
+            true => assert_eq!(
+                command.unwrap(),
+                Command::Run {
+                    input: "prog.bytecode".to_string(),
+                    options: Box::new(RunOptions { jit: Some(5), ..RunOptions::default() }),
+                }
+            ),
+            false => assert!(command.is_err()),
+        }
+    }
+
+    #[test]
+    fn parse_output_commands() {
+        assert_eq!(
+            Command::parse(&args("fmt prog.bytecode")).unwrap(),
+            Command::Fmt { input: "prog.bytecode".to_string(), output: None }
+        );
+        assert_eq!(
+            Command::parse(&args("cfg prog.bytecode -o graphs")).unwrap(),
+            Command::Cfg { input: "prog.bytecode".to_string(), output: Some("graphs".to_string()) }
+        );
+        assert_eq!(
+            Command::parse(&args("compile prog.bytecode -o prog")).unwrap(),
+            Command::Compile { input: "prog.bytecode".to_string(), output: "prog".to_string() }
+        );
+        assert_eq!(
+            Command::parse(&args("link a.o -o prog b.o")).unwrap(),
+            Command::Link { inputs: args("a.o b.o"), output: "prog".to_string() }
+        );
+    }
+
+    #[test]
+    fn parse_should_return_error_for_invalid_usage() {
+        assert!(Command::parse(&args("")).is_err());
+        assert!(Command::parse(&args("run")).is_err());
+        assert!(Command::parse(&args("run --max-steps")).is_err());
+        assert!(Command::parse(&args("run --max-steps ten prog.bytecode")).is_err());
+        assert!(Command::parse(&args("run --verbose prog.bytecode")).is_err());
+        assert!(Command::parse(&args("run --engine jit prog.bytecode")).is_err());
+        assert!(Command::parse(&args("run --engine register --profile prog.bytecode")).is_err());
+        assert!(Command::parse(&args("run --engine register --jit prog.bytecode")).is_err());
+        assert!(Command::parse(&args("check a.bytecode b.bytecode")).is_err());
+        assert!(Command::parse(&args("compile prog.bytecode")).is_err());
+        assert!(Command::parse(&args("link -o prog")).is_err());
+    }
+}
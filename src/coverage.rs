@@ -0,0 +1,233 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{vm::Pointer, instruction::Instruction, backtrace::DebugInfo, functions::MAIN_FUNCTION};
+
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    hits: Vec<usize>,
+    branches: BTreeMap<Pointer, (usize, usize)>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, ip: Pointer) {
+        if ip >= self.hits.len() {
+            self.hits.resize(ip + 1, 0);
+        }
+        self.hits[ip] += 1;
+    }
+
+    pub fn record_branch(&mut self, ip: Pointer, taken: bool) {
+        let (taken_count, not_taken_count) = self.branches.entry(ip).or_default();
+
+        match taken {
+            true => *taken_count += 1,
+            false => *not_taken_count += 1,
+        }
+    }
+
+    pub fn hits(&self, ip: Pointer) -> usize {
+        self.hits.get(ip).copied().unwrap_or(0)
+    }
+
+    pub fn branch(&self, ip: Pointer) -> Option<(usize, usize)> {
+        self.branches.get(&ip).copied()
+    }
+
+    pub fn uncovered_functions<'a>(&self, debug_info: &'a DebugInfo) -> Vec<(&'a str, Pointer)> {
+        let mut functions = debug_info.functions
+            .iter()
+            .filter(|(_, (start_ip, _))| self.hits(start_ip + 1) == 0)
+            .map(|(func_name, (start_ip, _))| (func_name.as_str(), *start_ip))
+            .collect::<Vec<_>>();
+        functions.sort_by_key(|(_, start_ip)| *start_ip);
+
+        functions
+    }
+
+    pub fn uncovered_labels(&self, debug_info: &DebugInfo) -> Vec<(String, Pointer)> {
+        let mut labels = debug_info.labels
+            .iter()
+            .filter(|(_, _, ip)| self.hits(*ip) == 0)
+            .map(|(scope, label_name, ip)| match scope {
+                Some(scope) if scope != MAIN_FUNCTION => (format!("{}:{}", scope, label_name), ip),
+                _ => (label_name.to_string(), ip),
+            })
+            .collect::<Vec<_>>();
+        labels.sort_by_key(|(_, ip)| *ip);
+
+        labels
+    }
+
+    pub fn summary(&self, program: &[Instruction], debug_info: &DebugInfo) -> String {
+        let covered_ips = (0..program.len())
+            .filter(|ip| self.hits(*ip) > 0)
+            .count();
+        let branches = program
+            .iter()
+            .filter(|instruction| instruction.branch_target().is_some())
+            .count() * 2;
+        let covered_branches = self.branches
+            .values()
+            .map(|(taken, not_taken)| (*taken > 0) as usize + (*not_taken > 0) as usize)
+            .sum::<usize>();
+
+        let mut summary = String::new();
+
+        writeln!(summary, "Instructions: {}", Coverage::ratio(covered_ips, program.len())).unwrap();
+        writeln!(summary, "Branches: {}", Coverage::ratio(covered_branches, branches)).unwrap();
+
+        for (func_name, start_ip) in self.uncovered_functions(debug_info) {
+            writeln!(summary, "Uncovered function: {}{}", func_name, Coverage::location(debug_info, start_ip)).unwrap();
+        }
+        for (label_name, ip) in self.uncovered_labels(debug_info) {
+            writeln!(summary, "Uncovered label: {}{}", label_name, Coverage::location(debug_info, ip)).unwrap();
+        }
+
+        summary
+    }
+
+    pub fn lcov(&self, program: &[Instruction], debug_info: &DebugInfo, source_path: &str) -> String {
+        let mut lcov = String::new();
+
+        writeln!(lcov, "TN:").unwrap();
+        writeln!(lcov, "SF:{}", source_path).unwrap();
+
+        let mut functions = debug_info.functions.iter().collect::<Vec<_>>();
+        functions.sort_by_key(|(_, (start_ip, _))| *start_ip);
+
+        for (func_name, (start_ip, _)) in functions.iter() {
+            if let Some(line) = debug_info.line_of(*start_ip) {
+                writeln!(lcov, "FN:{},{}", line, func_name).unwrap();
+            }
+        }
+        for (func_name, (start_ip, _)) in functions.iter() {
+            writeln!(lcov, "FNDA:{},{}", self.hits(start_ip + 1), func_name).unwrap();
+        }
+        writeln!(lcov, "FNF:{}", functions.len()).unwrap();
+        writeln!(
+            lcov,
+            "FNH:{}",
+            functions.iter().filter(|(_, (start_ip, _))| self.hits(start_ip + 1) > 0).count()
+        ).unwrap();
+
+        let mut branches_found = 0;
+        let mut branches_hit = 0;
+
+        for (ip, instruction) in program.iter().enumerate() {
+            let Some(line) = instruction.branch_target().and(debug_info.line_of(ip)) else {
+                continue;
+            };
+            let outcomes = match (self.hits(ip), self.branch(ip).unwrap_or_default()) {
+                (0, _) => ["-".to_string(), "-".to_string()],
+                (_, (taken, not_taken)) => [taken.to_string(), not_taken.to_string()],
+            };
+
+            for (branch, outcome) in outcomes.iter().enumerate() {
+                writeln!(lcov, "BRDA:{},{},{},{}", line, ip, branch, outcome).unwrap();
+                branches_found += 1;
+                branches_hit += (outcome != "-" && outcome != "0") as usize;
+            }
+        }
+        writeln!(lcov, "BRF:{}", branches_found).unwrap();
+        writeln!(lcov, "BRH:{}", branches_hit).unwrap();
+
+        let mut lines_found = 0;
+        let mut lines_hit = 0;
+
+        for ip in 0..program.len() {
+            if let Some(line) = debug_info.line_of(ip) {
+                writeln!(lcov, "DA:{},{}", line, self.hits(ip)).unwrap();
+                lines_found += 1;
+                lines_hit += (self.hits(ip) > 0) as usize;
+            }
+        }
+        writeln!(lcov, "LF:{}", lines_found).unwrap();
+        writeln!(lcov, "LH:{}", lines_hit).unwrap();
+        writeln!(lcov, "end_of_record").unwrap();
+
+        lcov
+    }
+
+    fn ratio(covered: usize, total: usize) -> String {
+        match total {
+            0 => "0/0".to_string(),
+            _ => format!("{}/{} ({:.1}%)", covered, total, covered as f64 * 100.0 / total as f64),
+        }
+    }
+
+    fn location(debug_info: &DebugInfo, ip: Pointer) -> String {
+        match debug_info.line_of(ip) {
+            Some(line) => format!(" (line {})", line),
+            None => format!(" (instruction #{})", ip),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{functions::Functions, labels::Labels};
+
+    fn program() -> (Vec<Instruction>, DebugInfo) {
+        let program = vec![
+            Instruction::Jump(3),           // FUNC TEST
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::ReturnValue,       // RETURN_VAL
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::JumpIfZero(6),     // JUMP_IF_ZERO END
+            Instruction::Ignore,            // LABEL SKIPPED
+            Instruction::Ignore,            // LABEL END
+        ];
+
+        let mut functions = Functions::new();
+        functions.insert("TEST", (0, 2)).unwrap();
+        let labels = vec![
+            (MAIN_FUNCTION.to_string(), "SKIPPED".to_string(), 5),
+            (MAIN_FUNCTION.to_string(), "END".to_string(), 6),
+        ].into_iter().collect::<Labels>();
+
+        (program, DebugInfo::new(functions, vec![1, 2, 3, 5, 6, 8, 9]).with_labels(labels))
+    }
+
+    fn coverage() -> Coverage {
+        let mut coverage = Coverage::new();
+
+        for ip in [0, 3, 4, 6] {
+            coverage.record(ip);
+        }
+        coverage.record_branch(4, true);
+
+        coverage
+    }
+
+    #[test]
+    fn summary() {
+        let (program, debug_info) = program();
+
+        assert_eq!(
+            coverage().summary(&program, &debug_info),
+            "Instructions: 4/7 (57.1%)\n\
+            Branches: 1/2 (50.0%)\n\
+            Uncovered function: TEST (line 1)\n\
+            Uncovered label: SKIPPED (line 8)\n"
+        );
+    }
+
+    #[test]
+    fn lcov() {
+        let (program, debug_info) = program();
+
+        assert_eq!(
+            coverage().lcov(&program, &debug_info, "prog.bytecode"),
+            "TN:\nSF:prog.bytecode\n\
+            FN:1,TEST\nFNDA:0,TEST\nFNF:1\nFNH:0\n\
+            BRDA:6,4,0,1\nBRDA:6,4,1,0\nBRF:2\nBRH:1\n\
+            DA:1,1\nDA:2,0\nDA:3,0\nDA:5,1\nDA:6,1\nDA:8,0\nDA:9,1\nLF:7\nLH:4\n\
+            end_of_record\n"
+        );
+    }
+}
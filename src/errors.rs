@@ -1,4 +1,6 @@
-use std::{error::Error, fmt::{Display, Formatter, Debug}, fmt::Result as FmtResult};
+use std::{error::Error, fmt::{Display, Formatter, Debug}, fmt::Result as FmtResult, time::Duration};
+
+use crate::{vm::Pointer, backtrace::Backtrace};
 
 pub enum ParseError {
     DuplicatedFunction(String),
@@ -8,12 +10,51 @@ pub enum ParseError {
     LabelNotFound(String),
     FunctionNeverReturned(String),
     ReturnOutsideFunction(String),
+    TailCallOutsideFunction(String),
+    JumpOutsideFunction(String),
     InvalidInstruction(String),
 }
 
 pub enum RuntimeError {
-    EmptyStack,
-    WrongStackIndex,
+    EmptyStack(Pointer),
+    UninitializedVariable(Pointer),
+    DivisionByZero,
+    ArithmeticOverflow,
+    InvalidShiftCount(isize),
+    NegativeExponent(isize),
+    OutOfFuel(Pointer),
+    StackOverflow(Pointer),
+    MemoryLimitExceeded(usize),
+    OutputLimitExceeded(usize),
+    TimeLimitExceeded(Duration),
+    TraceFailed(String),
+    OutputFailed(String),
+}
+
+pub enum VerifyError {
+    StackUnderflow(Pointer),
+    InconsistentStackDepth(Pointer, usize, usize),
+    JumpOutOfRange(Pointer, Pointer),
+    UnsetVariable(Pointer),
+    InvalidCallTarget(Pointer, Pointer),
+    InvalidTailCall(Pointer),
+}
+
+pub struct Trap {
+    pub error: RuntimeError,
+    pub backtrace: Backtrace,
+}
+
+pub enum LinkError {
+    DuplicatedSymbol(String),
+    SymbolNotFound(String),
+    InvalidObject(String),
+}
+
+pub enum JitError {
+    UnsupportedInstruction(Pointer),
+    InconsistentStackDepth(Pointer),
+    MemoryMapFailed,
 }
 
 impl ParseError {
@@ -35,6 +76,12 @@ impl ParseError {
             Self::ReturnOutsideFunction(instr_num) => format!(
                 "Return can only be used within a function (Line #{}).", instr_num
             ),
+            Self::TailCallOutsideFunction(instr_num) => format!(
+                "Tail call can only be used within a function (Line #{}).", instr_num
+            ),
+            Self::JumpOutsideFunction(label_name) => format!(
+                "Jump to label '{}' crosses a function boundary.", label_name
+            ),
             Self::FunctionNeverReturned(func_name) => format!(
                 "Missing return statement in the function '{}'.", func_name
             ),
@@ -46,10 +93,104 @@ impl ParseError {
 }
 
 impl RuntimeError {
-    fn message(&self) -> &str {
+    fn message(&self) -> String {
+        match self {
+            Self::EmptyStack(ip) => format!(
+                "Stack is empty, nothing to pop/peek (Instruction #{}).", ip
+            ),
+            Self::UninitializedVariable(ip) => format!(
+                "Variable is read before it has been written (Instruction #{}).", ip
+            ),
+            Self::DivisionByZero => "Attempt to divide by zero.".to_string(),
+            Self::ArithmeticOverflow => "Arithmetic operation overflowed.".to_string(),
+            Self::InvalidShiftCount(count) => format!(
+                "Shift count {} is out of range (0..{}).", count, isize::BITS
+            ),
+            Self::NegativeExponent(exp) => format!(
+                "Attempt to raise to a negative power {}.", exp
+            ),
+            Self::OutOfFuel(ip) => format!(
+                "Program ran out of fuel before executing instruction {}.", ip
+            ),
+            Self::StackOverflow(ip) => format!(
+                "Stack overflow (Instruction #{}).", ip
+            ),
+            Self::MemoryLimitExceeded(bytes) => format!(
+                "Program exceeded the memory limit of {} bytes.", bytes
+            ),
+            Self::OutputLimitExceeded(bytes) => format!(
+                "Program exceeded the output limit of {} bytes.", bytes
+            ),
+            Self::TimeLimitExceeded(time) => format!(
+                "Program exceeded the time limit of {:?}.", time
+            ),
+            Self::TraceFailed(reason) => format!(
+                "Couldn't write execution trace: {}.", reason
+            ),
+            Self::OutputFailed(reason) => format!(
+                "Couldn't write program output: {}.", reason
+            ),
+        }
+    }
+}
+
+impl VerifyError {
+    fn message(&self) -> String {
+        match self {
+            Self::StackUnderflow(ip) => format!(
+                "Operand stack may underflow (Instruction #{}).", ip
+            ),
+            Self::InconsistentStackDepth(ip, depth, other_depth) => format!(
+                "Operand stack depth is {} or {} depending on the path (Instruction #{}).", depth, other_depth, ip
+            ),
+            Self::JumpOutOfRange(ip, target_ip) => format!(
+                "Jump target {} is out of range (Instruction #{}).", target_ip, ip
+            ),
+            Self::UnsetVariable(ip) => format!(
+                "Variable may be accessed before it has been written (Instruction #{}).", ip
+            ),
+            Self::InvalidCallTarget(ip, target_ip) => format!(
+                "Call target {} is not the start of a function (Instruction #{}).", target_ip, ip
+            ),
+            Self::InvalidTailCall(ip) => format!(
+                "Tail call must be inside a function that returns the same way as the called one (Instruction #{}).", ip
+            ),
+        }
+    }
+}
+
+impl Trap {
+    fn message(&self) -> String {
+        format!("{}\n{}", self.error, self.backtrace)
+    }
+}
+
+impl LinkError {
+    fn message(&self) -> String {
+        match self {
+            Self::DuplicatedSymbol(symbol) => format!(
+                "Symbol '{}' is exported by more than one object.", symbol
+            ),
+            Self::SymbolNotFound(symbol) => format!(
+                "Undefined reference to symbol '{}'.", symbol
+            ),
+            Self::InvalidObject(reason) => format!(
+                "Invalid object file: {}.", reason
+            ),
+        }
+    }
+}
+
+impl JitError {
+    fn message(&self) -> String {
         match self {
-            Self::EmptyStack => "Stack is empty, nothing to pop/peek.",
-            Self::WrongStackIndex => "Element with provided index doesn't exist in the stack.",
+            Self::UnsupportedInstruction(ip) => format!(
+                "Instruction can't be compiled (Instruction #{}).", ip
+            ),
+            Self::InconsistentStackDepth(ip) => format!(
+                "Operand stack depth isn't known at compile time (Instruction #{}).", ip
+            ),
+            Self::MemoryMapFailed => "Couldn't map executable memory.".to_string(),
         }
     }
 }
@@ -66,6 +207,30 @@ impl Display for RuntimeError {
     }
 }
 
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Display for JitError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.message())
+    }
+}
+
 impl Debug for ParseError {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         write!(f, "{}", self.message())
@@ -78,6 +243,38 @@ impl Debug for RuntimeError {
     }
 }
 
+impl Debug for VerifyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Debug for Trap {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Debug for LinkError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Debug for JitError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.message())
+    }
+}
+
 impl Error  for ParseError {}
 
 impl Error  for RuntimeError {}
+
+impl Error  for VerifyError {}
+
+impl Error  for Trap {}
+
+impl Error  for LinkError {}
+
+impl Error  for JitError {}
@@ -0,0 +1,84 @@
+pub const INDENT: &str = "    ";
+
+pub struct Formatter;
+
+impl Formatter {
+    pub fn format(buffer: &str) -> String {
+        let mut formatted = String::new();
+        let mut in_function = false;
+        let mut label_indent = None;
+        let mut pending_blank_line = false;
+
+        for line in buffer.lines() {
+            let tokens = line.split_whitespace().collect::<Vec<_>>();
+
+            if tokens.is_empty() {
+                pending_blank_line = !formatted.is_empty();
+                continue;
+            }
+
+            if pending_blank_line {
+                formatted.push('\n');
+                pending_blank_line = false;
+            }
+
+            let indent = line.len() - line.trim_start().len();
+            let is_label_body = !matches!(tokens[0], "LABEL" | "FUNC")
+                && label_indent.is_some_and(|label_indent| indent > label_indent);
+
+            if in_function {
+                formatted.push_str(INDENT);
+            }
+            if is_label_body {
+                formatted.push_str(INDENT);
+            }
+            formatted.push_str(&tokens.join(" "));
+            formatted.push('\n');
+
+            match tokens.as_slice() {
+                ["FUNC", _] => {
+                    in_function = true;
+                    label_indent = None;
+                },
+                ["RETURN"] | ["RETURN_VAL"] if in_function => {
+                    in_function = false;
+                    label_indent = None;
+                },
+                ["LABEL", _] => label_indent = Some(indent),
+                _ => {},
+            }
+        }
+
+        formatted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format() {
+        let buffer = "\n\n  LOAD_VAL   5\nWRITE_VAR 'x'\n\n\n\nFUNC TEST\nLOAD_VAL 1\n\n      RETURN_VAL\nCALL  TEST\n\n";
+
+        let expected = "LOAD_VAL 5\nWRITE_VAR 'x'\n\nFUNC TEST\n    LOAD_VAL 1\n\n    RETURN_VAL\nCALL TEST\n";
+
+        assert_eq!(Formatter::format(buffer), expected);
+    }
+
+    #[test]
+    fn format_keeps_label_bodies_indented() {
+        let buffer = "LABEL LOOP\n  READ_VAR 'x'\n\t  JUMP_IF_SM   LOOP\nLABEL END\nPRINT 'x'\n";
+
+        let expected = "LABEL LOOP\n    READ_VAR 'x'\n    JUMP_IF_SM LOOP\nLABEL END\nPRINT 'x'\n";
+
+        assert_eq!(Formatter::format(buffer), expected);
+    }
+
+    #[test]
+    fn format_is_idempotent() {
+        let buffer = "FUNC TEST\n    LABEL LOOP\n    RETURN\n\nCALL TEST\n";
+
+        assert_eq!(Formatter::format(buffer), buffer);
+    }
+}
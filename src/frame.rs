@@ -1,57 +1,27 @@
-use crate::{vm::Pointer, stack::Stack, errors::RuntimeError};
+use crate::vm::Pointer;
 
-const OPERAND_STACK_DEFAULT_CAPACITY: usize = 62;
-
-#[derive(Debug, PartialEq)]
-pub struct Frame<T> {
+// A frame owns a window of the value stack: its locals start at `base`, its operands follow the
+// `window` slots reserved for them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
     pub ip: Pointer,
-    operand_stack: Stack<T>,
-    locals: Stack<T>,
+    pub base: usize,
+    pub locals: usize,
+    pub window: usize,
 }
 
-impl<T: Copy> Frame<T> {
-    pub fn new(ip: Pointer) -> Self {
+impl Frame {
+    pub fn new(ip: Pointer, base: usize, window: usize) -> Self {
         Self {
             ip,
-            operand_stack: Stack::with_capacity(OPERAND_STACK_DEFAULT_CAPACITY),
-            locals: Stack::new(),
+            base,
+            locals: 0,
+            window,
         }
     }
 
-    pub fn push_value(&mut self, value: T) {
-        self.operand_stack.push(value);
-    }
-
-    pub fn pop_value(&mut self) -> Result<T, RuntimeError> {
-        self.operand_stack.pop()
-    }
-
-    pub fn peek_value(&self) -> Result<&T, RuntimeError> {
-        self.operand_stack.peek()
-    }
-
-    pub fn set_local(&mut self, local_idx: usize, value: T) {
-        if local_idx == self.locals.len() {
-            self.locals.push(value);
-        }
-        else if local_idx < self.locals.len() {
-            *self.locals.get_mut(local_idx).unwrap() = value;
-        }
-        else {
-            panic!("Invalid local variable address.")
-        }
-    }
-
-    pub fn get_local(&self, local_idx: usize) -> Result<&T, RuntimeError> {
-        self.locals.get(local_idx)
-    }
-
-    pub fn get_operand_stack(&self) -> &Stack<T> {
-        &self.operand_stack
-    }
-
-    pub fn get_operand_stack_mut(&mut self) -> &mut Stack<T> {
-        &mut self.operand_stack
+    pub fn operands_base(&self) -> usize {
+        self.base + self.window
     }
 }
 
@@ -61,74 +31,10 @@ mod test {
 
     #[test]
     fn new() {
-        let frame: Frame<isize> = Frame::new(5);
+        let frame = Frame::new(5, 2, 3);
 
         assert_eq!(frame.ip, 5);
-        assert!(frame.operand_stack.is_empty());
-        assert!(frame.locals.is_empty());
-    }
-
-    #[test]
-    fn push_value() {
-        let mut frame: Frame<isize> = Frame::new(5);
-
-        frame.push_value(10);
-
-        assert!(!frame.operand_stack.is_empty());
-    }
-
-    #[test]
-    fn pop_value() {
-        let mut frame: Frame<isize> = Frame::new(5);
-
-        frame.push_value(10);
-
-        assert_eq!(frame.pop_value().unwrap(), 10);
-    }
-
-    #[test]
-    fn peek_value() {
-        let mut frame: Frame<isize> = Frame::new(5);
-
-        frame.push_value(10);
-
-        assert_eq!(*frame.peek_value().unwrap(), 10);
-    }
-
-    #[test]
-    fn set_get_local() {
-        let mut frame: Frame<isize> = Frame::new(5);
-
-        frame.set_local(0, 10);
-
-        assert_eq!(frame.get_local(0).unwrap(), &10);
-    }
-
-    #[test]
-    #[should_panic(expected = "Invalid local variable address.")]
-    fn set_local_wrong_index() {
-        let mut frame: Frame<isize> = Frame::new(5);
-
-        frame.set_local(10, 10);
-    }
-
-    #[test]
-    fn get_operand_stack() {
-        let frame: Frame<isize> = Frame::new(5);
-
-        let operand_stack = frame.get_operand_stack();
-
-        assert!(operand_stack.is_empty());
-    }
-
-    #[test]
-    fn get_operand_stack_mut() {
-        let mut frame: Frame<isize> = Frame::new(5);
-
-        let operand_stack = frame.get_operand_stack_mut();
-
-        operand_stack.push(10);
-
-        assert_eq!(operand_stack.pop().unwrap(), 10);
+        assert_eq!(frame.locals, 0);
+        assert_eq!(frame.operands_base(), 5);
     }
 }
@@ -2,7 +2,9 @@ use std::collections::HashMap;
 
 use crate::{vm::Pointer, errors::ParseError};
 
-#[derive(Debug, PartialEq)]
+pub const MAIN_FUNCTION: &str = "MAIN";
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Functions(HashMap<String, (Pointer, Pointer)>);
 
 impl Functions {
@@ -22,6 +24,31 @@ impl Functions {
     pub fn get(&self, func_name: &str) -> Result<&(Pointer, Pointer), ParseError> {
         self.0.get(func_name).ok_or(ParseError::FunctionNotFound(func_name.to_string()))
     }
+
+    pub fn remove(&mut self, func_name: &str) -> Option<(Pointer, Pointer)> {
+        self.0.remove(func_name)
+    }
+
+    pub fn scope_of(&self, ip: Pointer) -> &str {
+        self.0
+            .iter()
+            .find(|(_, (start_ip, end_ip))| *start_ip <= ip && ip <= *end_ip)
+            .map_or(MAIN_FUNCTION, |(func_name, _)| func_name.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &(Pointer, Pointer))> {
+        self.0.iter()
+    }
+
+    pub fn ranges_mut(&mut self) -> impl Iterator<Item = &mut (Pointer, Pointer)> {
+        self.0.values_mut()
+    }
+}
+
+impl Default for Functions {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
@@ -68,4 +95,16 @@ mod test {
 
         assert!(functions.get("MAIN").is_err());
     }
+
+    #[test]
+    fn scope_of() {
+        let mut functions = Functions::new();
+
+        functions.insert("TEST", (3, 6)).unwrap();
+
+        assert_eq!(functions.scope_of(2), MAIN_FUNCTION);
+        assert_eq!(functions.scope_of(3), "TEST");
+        assert_eq!(functions.scope_of(6), "TEST");
+        assert_eq!(functions.scope_of(7), MAIN_FUNCTION);
+    }
 }
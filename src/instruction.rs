@@ -1,14 +1,68 @@
-use crate::{labels::Labels, variables::{Variables, VariableAddress}, vm::Pointer, functions::Functions, errors::ParseError};
+use std::fmt::{Display, Formatter, Result as FmtResult};
 
-#[derive(Debug, PartialEq)]
+use crate::{labels::Labels, variables::{Variables, VariableAddress}, vm::{Pointer, ExitStatus}, functions::Functions, errors::ParseError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Greater,
+    Smaller,
+    GreaterEqual,
+    SmallerEqual,
+}
+
+impl Comparison {
+    pub fn holds(&self, lhs: isize, rhs: isize) -> bool {
+        match self {
+            Comparison::Equal => lhs == rhs,
+            Comparison::NotEqual => lhs != rhs,
+            Comparison::Greater => lhs > rhs,
+            Comparison::Smaller => lhs < rhs,
+            Comparison::GreaterEqual => lhs >= rhs,
+            Comparison::SmallerEqual => lhs <= rhs,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Instruction {
     LoadValue(isize),
+    Duplicate,
+    Drop,
+    Swap,
+    Over,
+    Rotate,
+    Pick(usize),
     WriteVariable(VariableAddress),
     ReadVariable(VariableAddress),
+    IncrementVariable(VariableAddress, isize),
     Add,
     Sub,
     Multiply,
     Divide,
+    Modulo,
+    Negate,
+    Absolute,
+    Power,
+    Min,
+    Max,
+    ShiftLeft,
+    ShiftRight,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    Equal,
+    NotEqual,
+    Smaller,
+    SmallerEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+    Xor,
+    Not,
     Print,
     PrintVariable(String, VariableAddress),
     Jump(Pointer),
@@ -18,46 +72,453 @@ pub enum Instruction {
     JumpIfSmaller(Pointer),
     JumpIfGreaterEqual(Pointer),
     JumpIfSmallerEqual(Pointer),
+    JumpIfTrue(Pointer),
+    JumpIfZero(Pointer),
+    JumpTable(Vec<Pointer>),
+    JumpIfVariable(Comparison, VariableAddress, isize, Pointer),
     CallFunction(Pointer),
+    TailCall(Pointer),
     Return,
     ReturnValue,
+    Halt,
+    Exit(i32),
     Ignore,
 }
 
 impl Instruction {
     pub fn from(
         instr_str: &[&str],
+        scope: &str,
         functions: &Functions,
         variables: &mut Variables,
         labels: &Labels
     ) -> Result<Self, ParseError> {
         match instr_str {
-            ["LOAD_VAL", val] => Ok(Instruction::LoadValue(val.parse::<isize>().unwrap())),
-            ["WRITE_VAR", _] => Ok(Instruction::WriteVariable(variables.queue_pop_front().unwrap())),
-            ["READ_VAR", _] => Ok(Instruction::ReadVariable(variables.queue_pop_front().unwrap())),
+            ["LOAD_VAL", val] => Ok(Instruction::LoadValue(
+                val
+                    .parse::<isize>()
+                    .map_err(|_| ParseError::InvalidInstruction(instr_str.join(" ")))?
+            )),
+            ["DUP"] => Ok(Instruction::Duplicate),
+            ["DROP"] => Ok(Instruction::Drop),
+            ["SWAP"] => Ok(Instruction::Swap),
+            ["OVER"] => Ok(Instruction::Over),
+            ["ROT"] => Ok(Instruction::Rotate),
+            ["PICK", depth] => Ok(Instruction::Pick(
+                depth
+                    .parse::<usize>()
+                    .map_err(|_| ParseError::InvalidInstruction(instr_str.join(" ")))?
+            )),
+            ["WRITE_VAR", _] => Ok(Instruction::WriteVariable(variables.queue_pop_front()?)),
+            ["READ_VAR", _] => Ok(Instruction::ReadVariable(variables.queue_pop_front()?)),
             ["ADD"] => Ok(Instruction::Add),
             ["SUB"] => Ok(Instruction::Sub),
             ["MULTIPLY"] => Ok(Instruction::Multiply),
             ["DIVIDE"] => Ok(Instruction::Divide),
+            ["MOD"] => Ok(Instruction::Modulo),
+            ["NEG"] => Ok(Instruction::Negate),
+            ["ABS"] => Ok(Instruction::Absolute),
+            ["POW"] => Ok(Instruction::Power),
+            ["MIN"] => Ok(Instruction::Min),
+            ["MAX"] => Ok(Instruction::Max),
+            ["SHL"] => Ok(Instruction::ShiftLeft),
+            ["SHR"] => Ok(Instruction::ShiftRight),
+            ["BAND"] => Ok(Instruction::BitAnd),
+            ["BOR"] => Ok(Instruction::BitOr),
+            ["BXOR"] => Ok(Instruction::BitXor),
+            ["BNOT"] => Ok(Instruction::BitNot),
+            ["EQ"] => Ok(Instruction::Equal),
+            ["NE"] => Ok(Instruction::NotEqual),
+            ["LT"] => Ok(Instruction::Smaller),
+            ["LE"] => Ok(Instruction::SmallerEqual),
+            ["GT"] => Ok(Instruction::Greater),
+            ["GE"] => Ok(Instruction::GreaterEqual),
+            ["AND"] => Ok(Instruction::And),
+            ["OR"] => Ok(Instruction::Or),
+            ["XOR"] => Ok(Instruction::Xor),
+            ["NOT"] => Ok(Instruction::Not),
             ["PRINT"] => Ok(Instruction::Print),
             ["PRINT", var_name] => Ok(
                 Instruction::PrintVariable(
                     var_name.replace(&['\'', '"'][..], ""),
-                    variables.queue_pop_front().unwrap(),
+                    variables.queue_pop_front()?,
                 )
             ),
             ["LABEL", _] => Ok(Instruction::Ignore),
-            ["FUNC", func_name] => Ok(Instruction::Jump(functions.get(func_name).unwrap().1)),
-            ["CALL", func_name] => Ok(Instruction::CallFunction(functions.get(func_name).unwrap().0)),
-            ["JUMP_IF_EQ", label_name] => Ok(Instruction::JumpIfEqual(*labels.get(label_name).unwrap())),
-            ["JUMP_IF_NQ", label_name] => Ok(Instruction::JumpIfNotEqual(*labels.get(label_name).unwrap())),
-            ["JUMP_IF_GR", label_name] => Ok(Instruction::JumpIfGreater(*labels.get(label_name).unwrap())),
-            ["JUMP_IF_SM", label_name] => Ok(Instruction::JumpIfSmaller(*labels.get(label_name).unwrap())),
-            ["JUMP_IF_GREQ", label_name] => Ok(Instruction::JumpIfGreaterEqual(*labels.get(label_name).unwrap())),
-            ["JUMP_IF_SMEQ", label_name] => Ok(Instruction::JumpIfSmallerEqual(*labels.get(label_name).unwrap())),
+            ["FUNC", func_name] => Ok(Instruction::Jump(functions.get(func_name)?.1 + 1)),
+            ["CALL", func_name] => Ok(Instruction::CallFunction(functions.get(func_name)?.0 + 1)),
+            ["TAIL_CALL", func_name] => Ok(Instruction::TailCall(functions.get(func_name)?.0 + 1)),
+            ["JUMP", label_name] => Ok(Instruction::Jump(
+                Instruction::label_target(label_name, scope, functions, labels)?
+            )),
+            ["JUMP_IF_EQ", label_name] => Ok(Instruction::JumpIfEqual(
+                Instruction::label_target(label_name, scope, functions, labels)?
+            )),
+            ["JUMP_IF_NQ", label_name] => Ok(Instruction::JumpIfNotEqual(
+                Instruction::label_target(label_name, scope, functions, labels)?
+            )),
+            ["JUMP_IF_GR", label_name] => Ok(Instruction::JumpIfGreater(
+                Instruction::label_target(label_name, scope, functions, labels)?
+            )),
+            ["JUMP_IF_SM", label_name] => Ok(Instruction::JumpIfSmaller(
+                Instruction::label_target(label_name, scope, functions, labels)?
+            )),
+            ["JUMP_IF_GREQ", label_name] => Ok(Instruction::JumpIfGreaterEqual(
+                Instruction::label_target(label_name, scope, functions, labels)?
+            )),
+            ["JUMP_IF_SMEQ", label_name] => Ok(Instruction::JumpIfSmallerEqual(
+                Instruction::label_target(label_name, scope, functions, labels)?
+            )),
+            ["JUMP_IF_TRUE", label_name] => Ok(Instruction::JumpIfTrue(
+                Instruction::label_target(label_name, scope, functions, labels)?
+            )),
+            ["JUMP_IF_ZERO", label_name] => Ok(Instruction::JumpIfZero(
+                Instruction::label_target(label_name, scope, functions, labels)?
+            )),
+            ["JUMP_TABLE", label_names @ ..] if !label_names.is_empty() => Ok(Instruction::JumpTable(
+                label_names
+                    .iter()
+                    .map(|label_name| Instruction::label_target(label_name, scope, functions, labels))
+                    .collect::<Result<Vec<_>, ParseError>>()?
+            )),
+            ["HALT"] => Ok(Instruction::Halt),
+            ["EXIT", code] => Ok(Instruction::Exit(
+                code
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|code| ExitStatus::is_valid_code(*code))
+                    .ok_or(ParseError::InvalidInstruction(instr_str.join(" ")))?
+            )),
             ["RETURN"] => Ok(Instruction::Return),
             ["RETURN_VAL"] => Ok(Instruction::ReturnValue),
             invalid_instr => Err(ParseError::InvalidInstruction(invalid_instr.join(" "))),
         }
     }
+
+    fn label_target(
+        label_name: &str,
+        scope: &str,
+        functions: &Functions,
+        labels: &Labels
+    ) -> Result<Pointer, ParseError> {
+        let label_ip = *labels.get(scope, label_name)?;
+
+        if functions.scope_of(label_ip) != scope {
+            return Err(ParseError::JumpOutsideFunction(label_name.to_string()));
+        }
+
+        Ok(label_ip)
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::LoadValue(_) => "LOAD_VAL",
+            Instruction::Duplicate => "DUP",
+            Instruction::Drop => "DROP",
+            Instruction::Swap => "SWAP",
+            Instruction::Over => "OVER",
+            Instruction::Rotate => "ROT",
+            Instruction::Pick(_) => "PICK",
+            Instruction::WriteVariable(_) => "WRITE_VAR",
+            Instruction::ReadVariable(_) => "READ_VAR",
+            Instruction::IncrementVariable(_, _) => "INC_VAR",
+            Instruction::Add => "ADD",
+            Instruction::Sub => "SUB",
+            Instruction::Multiply => "MULTIPLY",
+            Instruction::Divide => "DIVIDE",
+            Instruction::Modulo => "MOD",
+            Instruction::Negate => "NEG",
+            Instruction::Absolute => "ABS",
+            Instruction::Power => "POW",
+            Instruction::Min => "MIN",
+            Instruction::Max => "MAX",
+            Instruction::ShiftLeft => "SHL",
+            Instruction::ShiftRight => "SHR",
+            Instruction::BitAnd => "BAND",
+            Instruction::BitOr => "BOR",
+            Instruction::BitXor => "BXOR",
+            Instruction::BitNot => "BNOT",
+            Instruction::Equal => "EQ",
+            Instruction::NotEqual => "NE",
+            Instruction::Smaller => "LT",
+            Instruction::SmallerEqual => "LE",
+            Instruction::Greater => "GT",
+            Instruction::GreaterEqual => "GE",
+            Instruction::And => "AND",
+            Instruction::Or => "OR",
+            Instruction::Xor => "XOR",
+            Instruction::Not => "NOT",
+            Instruction::Print => "PRINT",
+            Instruction::PrintVariable(_, _) => "PRINT",
+            Instruction::Jump(_) => "JUMP",
+            Instruction::JumpIfEqual(_) => "JUMP_IF_EQ",
+            Instruction::JumpIfNotEqual(_) => "JUMP_IF_NQ",
+            Instruction::JumpIfGreater(_) => "JUMP_IF_GR",
+            Instruction::JumpIfSmaller(_) => "JUMP_IF_SM",
+            Instruction::JumpIfGreaterEqual(_) => "JUMP_IF_GREQ",
+            Instruction::JumpIfSmallerEqual(_) => "JUMP_IF_SMEQ",
+            Instruction::JumpIfTrue(_) => "JUMP_IF_TRUE",
+            Instruction::JumpIfZero(_) => "JUMP_IF_ZERO",
+            Instruction::JumpTable(_) => "JUMP_TABLE",
+            Instruction::JumpIfVariable(comparison, _, _, _) => match comparison {
+                Comparison::Equal => "JUMP_IF_VAR_EQ",
+                Comparison::NotEqual => "JUMP_IF_VAR_NQ",
+                Comparison::Greater => "JUMP_IF_VAR_GR",
+                Comparison::Smaller => "JUMP_IF_VAR_SM",
+                Comparison::GreaterEqual => "JUMP_IF_VAR_GREQ",
+                Comparison::SmallerEqual => "JUMP_IF_VAR_SMEQ",
+            },
+            Instruction::CallFunction(_) => "CALL",
+            Instruction::TailCall(_) => "TAIL_CALL",
+            Instruction::Return => "RETURN",
+            Instruction::ReturnValue => "RETURN_VAL",
+            Instruction::Halt => "HALT",
+            Instruction::Exit(_) => "EXIT",
+            Instruction::Ignore => "NOP",
+        }
+    }
+
+    pub fn pointers(&self) -> Vec<Pointer> {
+        match self {
+            Instruction::Jump(ip) |
+            Instruction::JumpIfEqual(ip) |
+            Instruction::JumpIfNotEqual(ip) |
+            Instruction::JumpIfGreater(ip) |
+            Instruction::JumpIfSmaller(ip) |
+            Instruction::JumpIfGreaterEqual(ip) |
+            Instruction::JumpIfSmallerEqual(ip) |
+            Instruction::JumpIfTrue(ip) |
+            Instruction::JumpIfZero(ip) |
+            Instruction::JumpIfVariable(_, _, _, ip) |
+            Instruction::CallFunction(ip) |
+            Instruction::TailCall(ip) => vec![*ip],
+            Instruction::JumpTable(ips) => ips.clone(),
+            _ => vec![],
+        }
+    }
+
+    pub fn branch_target(&self) -> Option<Pointer> {
+        match self {
+            Instruction::JumpIfEqual(ip) |
+            Instruction::JumpIfNotEqual(ip) |
+            Instruction::JumpIfGreater(ip) |
+            Instruction::JumpIfSmaller(ip) |
+            Instruction::JumpIfGreaterEqual(ip) |
+            Instruction::JumpIfSmallerEqual(ip) |
+            Instruction::JumpIfTrue(ip) |
+            Instruction::JumpIfZero(ip) |
+            Instruction::JumpIfVariable(_, _, _, ip) => Some(*ip),
+            _ => None,
+        }
+    }
+
+    // A call pushes the value its function returns, which depends on the callee.
+    pub fn stack_effect(&self) -> Option<(usize, usize)> {
+        let effect = match self {
+            Instruction::LoadValue(_) |
+            Instruction::ReadVariable(_) => (0, 1),
+            Instruction::Duplicate => (1, 2),
+            Instruction::Drop |
+            Instruction::WriteVariable(_) |
+            Instruction::JumpIfTrue(_) |
+            Instruction::JumpIfZero(_) |
+            Instruction::JumpTable(_) |
+            Instruction::ReturnValue => (1, 0),
+            Instruction::Swap => (2, 2),
+            Instruction::Over => (2, 3),
+            Instruction::Rotate => (3, 3),
+            Instruction::Pick(depth) => (depth + 1, depth + 2),
+            Instruction::Add |
+            Instruction::Sub |
+            Instruction::Multiply |
+            Instruction::Divide |
+            Instruction::Modulo |
+            Instruction::Power |
+            Instruction::Min |
+            Instruction::Max |
+            Instruction::ShiftLeft |
+            Instruction::ShiftRight |
+            Instruction::BitAnd |
+            Instruction::BitOr |
+            Instruction::BitXor |
+            Instruction::Equal |
+            Instruction::NotEqual |
+            Instruction::Smaller |
+            Instruction::SmallerEqual |
+            Instruction::Greater |
+            Instruction::GreaterEqual |
+            Instruction::And |
+            Instruction::Or |
+            Instruction::Xor => (2, 1),
+            Instruction::Negate |
+            Instruction::Absolute |
+            Instruction::BitNot |
+            Instruction::Not |
+            Instruction::Print => (1, 1),
+            Instruction::JumpIfEqual(_) |
+            Instruction::JumpIfNotEqual(_) |
+            Instruction::JumpIfGreater(_) |
+            Instruction::JumpIfSmaller(_) |
+            Instruction::JumpIfGreaterEqual(_) |
+            Instruction::JumpIfSmallerEqual(_) => (2, 0),
+            Instruction::PrintVariable(_, _) |
+            Instruction::IncrementVariable(_, _) |
+            Instruction::JumpIfVariable(_, _, _, _) |
+            Instruction::Jump(_) |
+            Instruction::TailCall(_) |
+            Instruction::Return |
+            Instruction::Halt |
+            Instruction::Exit(_) |
+            Instruction::Ignore => (0, 0),
+            Instruction::CallFunction(_) => return None,
+        };
+
+        Some(effect)
+    }
+
+    pub fn successors(&self, ip: Pointer) -> Vec<Pointer> {
+        match self {
+            Instruction::Jump(target_ip) => vec![*target_ip],
+            Instruction::JumpTable(target_ips) => {
+                let mut successors = target_ips.clone();
+                successors.push(ip + 1);
+                successors
+            },
+            Instruction::TailCall(_) |
+            Instruction::Return |
+            Instruction::ReturnValue |
+            Instruction::Halt |
+            Instruction::Exit(_) => vec![],
+            _ => match self.branch_target() {
+                Some(target_ip) => vec![ip + 1, target_ip],
+                None => vec![ip + 1],
+            },
+        }
+    }
+
+    pub fn pointers_mut(&mut self) -> Vec<&mut Pointer> {
+        match self {
+            Instruction::Jump(ip) |
+            Instruction::JumpIfEqual(ip) |
+            Instruction::JumpIfNotEqual(ip) |
+            Instruction::JumpIfGreater(ip) |
+            Instruction::JumpIfSmaller(ip) |
+            Instruction::JumpIfGreaterEqual(ip) |
+            Instruction::JumpIfSmallerEqual(ip) |
+            Instruction::JumpIfTrue(ip) |
+            Instruction::JumpIfZero(ip) |
+            Instruction::JumpIfVariable(_, _, _, ip) |
+            Instruction::CallFunction(ip) |
+            Instruction::TailCall(ip) => vec![ip],
+            Instruction::JumpTable(ips) => ips.iter_mut().collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn variable(&self) -> Option<VariableAddress> {
+        match self {
+            Instruction::WriteVariable(var_idx) |
+            Instruction::ReadVariable(var_idx) |
+            Instruction::IncrementVariable(var_idx, _) |
+            Instruction::JumpIfVariable(_, var_idx, _, _) |
+            Instruction::PrintVariable(_, var_idx) => Some(*var_idx),
+            _ => None,
+        }
+    }
+
+    pub fn variable_mut(&mut self) -> Option<&mut VariableAddress> {
+        match self {
+            Instruction::WriteVariable(var_idx) |
+            Instruction::ReadVariable(var_idx) |
+            Instruction::IncrementVariable(var_idx, _) |
+            Instruction::JumpIfVariable(_, var_idx, _, _) |
+            Instruction::PrintVariable(_, var_idx) => Some(var_idx),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.mnemonic())?;
+
+        match self {
+            Instruction::LoadValue(val) => write!(f, " {}", val),
+            Instruction::Pick(depth) => write!(f, " {}", depth),
+            Instruction::Exit(code) => write!(f, " {}", code),
+            Instruction::WriteVariable(var_idx) |
+            Instruction::ReadVariable(var_idx) => write!(f, " #{}", var_idx),
+            Instruction::PrintVariable(var_name, var_idx) => write!(f, " {} #{}", var_name, var_idx),
+            Instruction::IncrementVariable(var_idx, val) => write!(f, " #{} {}", var_idx, val),
+            Instruction::JumpIfVariable(_, var_idx, val, ip) => write!(f, " #{} {} {}", var_idx, val, ip),
+            instruction => instruction
+                .pointers()
+                .iter()
+                .try_for_each(|ip| write!(f, " {}", ip)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(Instruction::LoadValue(-5).to_string(), "LOAD_VAL -5");
+        assert_eq!(Instruction::ReadVariable(1).to_string(), "READ_VAR #1");
+        assert_eq!(Instruction::PrintVariable("'x'".to_string(), 0).to_string(), "PRINT 'x' #0");
+        assert_eq!(Instruction::JumpIfSmaller(2).to_string(), "JUMP_IF_SM 2");
+        assert_eq!(Instruction::JumpTable(vec![3, 6]).to_string(), "JUMP_TABLE 3 6");
+        assert_eq!(Instruction::CallFunction(12).to_string(), "CALL 12");
+        assert_eq!(Instruction::TailCall(12).to_string(), "TAIL_CALL 12");
+        assert_eq!(Instruction::Add.to_string(), "ADD");
+        assert_eq!(Instruction::IncrementVariable(0, -1).to_string(), "INC_VAR #0 -1");
+        assert_eq!(
+            Instruction::JumpIfVariable(Comparison::SmallerEqual, 1, 10, 4).to_string(),
+            "JUMP_IF_VAR_SMEQ #1 10 4"
+        );
+    }
+
+    #[test]
+    fn successors() {
+        assert_eq!(Instruction::Add.successors(3), vec![4]);
+        assert_eq!(Instruction::JumpIfZero(7).successors(3), vec![4, 7]);
+        assert_eq!(Instruction::JumpTable(vec![5, 9]).successors(3), vec![5, 9, 4]);
+        assert!(Instruction::ReturnValue.successors(3).is_empty());
+        assert!(Instruction::TailCall(1).successors(3).is_empty());
+    }
+
+    #[test]
+    fn pointers_mut() {
+        let mut instruction = Instruction::JumpIfSmaller(2);
+
+        for ip in instruction.pointers_mut() {
+            *ip += 3;
+        }
+
+        assert_eq!(instruction, Instruction::JumpIfSmaller(5));
+        assert!(Instruction::Add.pointers_mut().is_empty());
+    }
+
+    #[test]
+    fn pointers_mut_jump_table() {
+        let mut instruction = Instruction::JumpTable(vec![1, 4]);
+
+        for ip in instruction.pointers_mut() {
+            *ip += 3;
+        }
+
+        assert_eq!(instruction, Instruction::JumpTable(vec![4, 7]));
+    }
+
+    #[test]
+    fn variable_mut() {
+        let mut instruction = Instruction::PrintVariable("'x'".to_string(), 0);
+
+        *instruction.variable_mut().unwrap() += 1;
+
+        assert_eq!(instruction, Instruction::PrintVariable("'x'".to_string(), 1));
+        assert!(Instruction::Jump(0).variable_mut().is_none());
+    }
 }
@@ -0,0 +1,737 @@
+use std::{collections::HashMap, ffi::c_void, mem, ptr};
+
+use crate::{
+    vm::{Pointer, VALUE_SIZE},
+    instruction::{Instruction, Comparison},
+    register::{RegisterProgram, UnaryOp, BinaryOp},
+    errors::JitError,
+};
+
+const PROT_READ: i32 = 0x1;
+const PROT_WRITE: i32 = 0x2;
+const PROT_EXEC: i32 = 0x4;
+const MAP_PRIVATE: i32 = 0x02;
+const MAP_ANONYMOUS: i32 = 0x20;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+// Compiled code gets the slots of its frame in rdi and the address of the returned value in rsi.
+type NativeFunction = unsafe extern "sysv64" fn(*mut isize, *mut isize) -> u32;
+
+const RETURNED: u32 = 0;
+const RETURNED_VALUE: u32 = 1;
+const DEOPTIMIZED: u32 = 2;
+
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+
+const CC_OVERFLOW: u8 = 0x0;
+const CC_ABOVE: u8 = 0x7;
+const CC_EQUAL: u8 = 0x4;
+const CC_NOT_EQUAL: u8 = 0x5;
+const CC_NOT_SIGN: u8 = 0x9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    Returned,
+    ReturnedValue(isize),
+    Interpret,
+}
+
+#[derive(Debug)]
+struct ExecutableMemory {
+    addr: *mut c_void,
+    len: usize,
+}
+
+impl ExecutableMemory {
+    fn new(code: &[u8]) -> Result<Self, JitError> {
+        // SAFETY: a fresh anonymous mapping is written only before it becomes executable.
+        unsafe {
+            let addr = mmap(ptr::null_mut(), code.len(), PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+            if addr as isize == -1 {
+                return Err(JitError::MemoryMapFailed);
+            }
+
+            let memory = Self { addr, len: code.len() };
+            ptr::copy_nonoverlapping(code.as_ptr(), addr as *mut u8, code.len());
+
+            match mprotect(addr, code.len(), PROT_READ | PROT_EXEC) {
+                0 => Ok(memory),
+                _ => Err(JitError::MemoryMapFailed),
+            }
+        }
+    }
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(&mut self) {
+        // SAFETY: the mapping is owned by this value and no compiled code runs past its owner.
+        unsafe {
+            munmap(self.addr, self.len);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Instruction(Pointer),
+    Deoptimize,
+}
+
+#[derive(Debug, Default)]
+struct Assembler {
+    code: Vec<u8>,
+    fixups: Vec<(usize, Target)>,
+}
+
+impl Assembler {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn load(&mut self, reg: u8, slot: usize) {
+        self.emit(&[0x48, 0x8B, 0x87 | reg << 3]);
+        self.emit(&((slot * VALUE_SIZE) as i32).to_le_bytes());
+    }
+
+    fn store(&mut self, slot: usize, reg: u8) {
+        self.emit(&[0x48, 0x89, 0x87 | reg << 3]);
+        self.emit(&((slot * VALUE_SIZE) as i32).to_le_bytes());
+    }
+
+    fn load_immediate(&mut self, reg: u8, val: isize) {
+        self.emit(&[0x48, 0xB8 + reg]);
+        self.emit(&(val as i64).to_le_bytes());
+    }
+
+    fn jump(&mut self, target: Target) {
+        self.emit(&[0xE9]);
+        self.fixup(target);
+    }
+
+    fn jump_if(&mut self, condition: u8, target: Target) {
+        self.emit(&[0x0F, 0x80 | condition]);
+        self.fixup(target);
+    }
+
+    fn fixup(&mut self, target: Target) {
+        self.fixups.push((self.code.len(), target));
+        self.emit(&[0; 4]);
+    }
+
+    // Short forward jumps inside a single instruction, bound once their target is emitted.
+    fn skip_if(&mut self, condition: u8) -> usize {
+        self.emit(&[0x70 | condition, 0]);
+        self.code.len() - 1
+    }
+
+    fn skip(&mut self) -> usize {
+        self.emit(&[0xEB, 0]);
+        self.code.len() - 1
+    }
+
+    fn bind(&mut self, skip: usize) {
+        self.code[skip] = (self.code.len() - skip - 1) as u8;
+    }
+
+    fn exit(&mut self, status: u32) {
+        self.emit(&[0xB8]);
+        self.emit(&status.to_le_bytes());
+        self.emit(&[0xC3]);
+    }
+
+    fn set(&mut self, condition: u8) {
+        self.emit(&[0x0F, 0x90 | condition, 0xC0]);
+        self.emit(&[0x0F, 0xB6, 0xC0]);
+    }
+
+    fn link(mut self, offsets: &HashMap<Pointer, usize>) -> Vec<u8> {
+        let deoptimize = self.code.len();
+        self.exit(DEOPTIMIZED);
+
+        for (position, target) in mem::take(&mut self.fixups) {
+            let target = match target {
+                Target::Instruction(ip) => offsets[&ip],
+                Target::Deoptimize => deoptimize,
+            };
+            let offset = target as i32 - (position + 4) as i32;
+            self.code[position..position + 4].copy_from_slice(&offset.to_le_bytes());
+        }
+
+        self.code
+    }
+}
+
+#[derive(Debug)]
+pub struct CompiledFunction {
+    code: ExecutableMemory,
+    slots: Vec<isize>,
+}
+
+impl CompiledFunction {
+    // Compiles the function whose body starts at `func_ip`, the target of its `CALL`s. Every local and operand stack position gets
+    // a fixed slot, since the verifier guarantees the same operand stack depth on every path.
+    pub fn compile(program: &[Instruction], func_ip: Pointer) -> Result<Self, JitError> {
+        let depths = CompiledFunction::depths(program, func_ip)?;
+        let window = depths
+            .keys()
+            .filter_map(|ip| program[*ip].variable())
+            .map(|var_idx| var_idx + 1)
+            .max()
+            .unwrap_or(0);
+        let max_depth = depths.values().copied().max().unwrap_or(0) + 1;
+
+        let mut ips = depths.keys().copied().collect::<Vec<_>>();
+        ips.sort_unstable();
+
+        let mut assembler = Assembler::default();
+        let mut offsets = HashMap::new();
+
+        for ip in ips {
+            offsets.insert(ip, assembler.code.len());
+            CompiledFunction::emit(&mut assembler, &program[ip], window + depths[&ip]);
+        }
+
+        Ok(Self {
+            code: ExecutableMemory::new(&assembler.link(&offsets))?,
+            slots: vec![0; window + max_depth],
+        })
+    }
+
+    pub fn call(&mut self) -> Exit {
+        let mut val = 0;
+
+        // SAFETY: the code only touches the slots it was compiled for and the returned value.
+        let status = unsafe {
+            let function = mem::transmute::<*mut c_void, NativeFunction>(self.code.addr);
+            function(self.slots.as_mut_ptr(), &mut val)
+        };
+
+        match status {
+            RETURNED => Exit::Returned,
+            RETURNED_VALUE => Exit::ReturnedValue(val),
+            _ => Exit::Interpret,
+        }
+    }
+
+    fn depths(program: &[Instruction], entry_ip: Pointer) -> Result<HashMap<Pointer, usize>, JitError> {
+        let mut depths = HashMap::new();
+        let mut worklist = vec![(entry_ip, 0)];
+
+        while let Some((ip, depth)) = worklist.pop() {
+            match depths.get(&ip) {
+                Some(known_depth) if *known_depth == depth => continue,
+                Some(_) => return Err(JitError::InconsistentStackDepth(ip)),
+                None => {},
+            }
+
+            let instruction = program
+                .get(ip)
+                .filter(|instruction| CompiledFunction::is_supported(instruction))
+                .ok_or(JitError::UnsupportedInstruction(ip))?;
+            let (pops, pushes) = instruction
+                .stack_effect()
+                .filter(|(pops, _)| *pops <= depth)
+                .ok_or(JitError::InconsistentStackDepth(ip))?;
+
+            depths.insert(ip, depth);
+            worklist.extend(instruction.successors(ip).into_iter().map(|next_ip| (next_ip, depth - pops + pushes)));
+        }
+
+        Ok(depths)
+    }
+
+    fn is_supported(instruction: &Instruction) -> bool {
+        match instruction {
+            Instruction::Power => false,
+            Instruction::LoadValue(_) |
+            Instruction::Duplicate |
+            Instruction::Drop |
+            Instruction::Swap |
+            Instruction::Over |
+            Instruction::Rotate |
+            Instruction::Pick(_) |
+            Instruction::WriteVariable(_) |
+            Instruction::ReadVariable(_) |
+            Instruction::IncrementVariable(_, _) |
+            Instruction::Jump(_) |
+            Instruction::JumpIfTrue(_) |
+            Instruction::JumpIfZero(_) |
+            Instruction::JumpIfVariable(_, _, _, _) |
+            Instruction::Return |
+            Instruction::ReturnValue |
+            Instruction::Ignore => true,
+            _ => RegisterProgram::unary_op(instruction).is_some()
+                || RegisterProgram::binary_op(instruction).is_some()
+                || CompiledFunction::jump_comparison(instruction).is_some(),
+        }
+    }
+
+    fn jump_comparison(instruction: &Instruction) -> Option<Comparison> {
+        match instruction {
+            Instruction::JumpIfEqual(_) => Some(Comparison::Equal),
+            Instruction::JumpIfNotEqual(_) => Some(Comparison::NotEqual),
+            Instruction::JumpIfGreater(_) => Some(Comparison::Greater),
+            Instruction::JumpIfSmaller(_) => Some(Comparison::Smaller),
+            Instruction::JumpIfGreaterEqual(_) => Some(Comparison::GreaterEqual),
+            Instruction::JumpIfSmallerEqual(_) => Some(Comparison::SmallerEqual),
+            _ => None,
+        }
+    }
+
+    fn condition(comparison: Comparison) -> u8 {
+        match comparison {
+            Comparison::Equal => 0x4,
+            Comparison::NotEqual => 0x5,
+            Comparison::Smaller => 0xC,
+            Comparison::GreaterEqual => 0xD,
+            Comparison::SmallerEqual => 0xE,
+            Comparison::Greater => 0xF,
+        }
+    }
+
+    // `top` is the slot right above the operand stack before the instruction runs.
+    fn emit(asm: &mut Assembler, instruction: &Instruction, top: usize) {
+        if let Some(op) = RegisterProgram::unary_op(instruction) {
+            asm.load(RAX, top - 1);
+            CompiledFunction::emit_unary(asm, op);
+            asm.store(top - 1, RAX);
+            return;
+        }
+        if let Some(op) = RegisterProgram::binary_op(instruction) {
+            asm.load(RAX, top - 2);
+            asm.load(RCX, top - 1);
+            CompiledFunction::emit_binary(asm, op);
+            asm.store(top - 2, RAX);
+            return;
+        }
+        if let (Some(comparison), Some(ip)) = (CompiledFunction::jump_comparison(instruction), instruction.branch_target()) {
+            asm.load(RAX, top - 2);
+            asm.load(RCX, top - 1);
+            asm.emit(&[0x48, 0x39, 0xC8]);
+            asm.jump_if(CompiledFunction::condition(comparison), Target::Instruction(ip));
+            return;
+        }
+
+        match instruction {
+            Instruction::LoadValue(val) => {
+                asm.load_immediate(RAX, *val);
+                asm.store(top, RAX);
+            },
+            Instruction::Duplicate => {
+                asm.load(RAX, top - 1);
+                asm.store(top, RAX);
+            },
+            Instruction::Over => {
+                asm.load(RAX, top - 2);
+                asm.store(top, RAX);
+            },
+            Instruction::Pick(depth) => {
+                asm.load(RAX, top - 1 - depth);
+                asm.store(top, RAX);
+            },
+            Instruction::Swap => {
+                asm.load(RAX, top - 2);
+                asm.load(RCX, top - 1);
+                asm.store(top - 2, RCX);
+                asm.store(top - 1, RAX);
+            },
+            Instruction::Rotate => {
+                asm.load(RAX, top - 3);
+                asm.load(RCX, top - 2);
+                asm.store(top - 3, RCX);
+                asm.load(RCX, top - 1);
+                asm.store(top - 2, RCX);
+                asm.store(top - 1, RAX);
+            },
+            Instruction::WriteVariable(var_idx) => {
+                asm.load(RAX, top - 1);
+                asm.store(*var_idx, RAX);
+            },
+            Instruction::ReadVariable(var_idx) => {
+                asm.load(RAX, *var_idx);
+                asm.store(top, RAX);
+            },
+            Instruction::IncrementVariable(var_idx, val) => {
+                asm.load(RAX, *var_idx);
+                asm.load_immediate(RCX, *val);
+                asm.emit(&[0x48, 0x01, 0xC8]);
+                asm.jump_if(CC_OVERFLOW, Target::Deoptimize);
+                asm.store(*var_idx, RAX);
+            },
+            Instruction::Jump(ip) => asm.jump(Target::Instruction(*ip)),
+            Instruction::JumpIfTrue(ip) | Instruction::JumpIfZero(ip) => {
+                asm.load(RAX, top - 1);
+                asm.emit(&[0x48, 0x85, 0xC0]);
+
+                let condition = match instruction {
+                    Instruction::JumpIfTrue(_) => CC_NOT_EQUAL,
+                    _ => CC_EQUAL,
+                };
+                asm.jump_if(condition, Target::Instruction(*ip));
+            },
+            Instruction::JumpIfVariable(comparison, var_idx, val, ip) => {
+                asm.load(RAX, *var_idx);
+                asm.load_immediate(RCX, *val);
+                asm.emit(&[0x48, 0x39, 0xC8]);
+                asm.jump_if(CompiledFunction::condition(*comparison), Target::Instruction(*ip));
+            },
+            Instruction::Return => asm.exit(RETURNED),
+            Instruction::ReturnValue => {
+                asm.load(RAX, top - 1);
+                asm.emit(&[0x48, 0x89, 0x06]);
+                asm.exit(RETURNED_VALUE);
+            },
+            _ => {},
+        }
+    }
+
+    fn emit_unary(asm: &mut Assembler, op: UnaryOp) {
+        match op {
+            UnaryOp::Negate => {
+                asm.emit(&[0x48, 0xF7, 0xD8]);
+                asm.jump_if(CC_OVERFLOW, Target::Deoptimize);
+            },
+            UnaryOp::Absolute => {
+                asm.emit(&[0x48, 0x85, 0xC0]);
+                let positive = asm.skip_if(CC_NOT_SIGN);
+                asm.emit(&[0x48, 0xF7, 0xD8]);
+                asm.jump_if(CC_OVERFLOW, Target::Deoptimize);
+                asm.bind(positive);
+            },
+            UnaryOp::BitNot => asm.emit(&[0x48, 0xF7, 0xD0]),
+            UnaryOp::Not => {
+                asm.emit(&[0x48, 0x85, 0xC0]);
+                asm.set(CC_EQUAL);
+            },
+        }
+    }
+
+    // Operations that would fail in the interpreter leave the compiled code, so the interpreter
+    // runs the call again and reports the error itself.
+    fn emit_binary(asm: &mut Assembler, op: BinaryOp) {
+        match op {
+            BinaryOp::Add => {
+                asm.emit(&[0x48, 0x01, 0xC8]);
+                asm.jump_if(CC_OVERFLOW, Target::Deoptimize);
+            },
+            BinaryOp::Sub => {
+                asm.emit(&[0x48, 0x29, 0xC8]);
+                asm.jump_if(CC_OVERFLOW, Target::Deoptimize);
+            },
+            BinaryOp::Multiply => {
+                asm.emit(&[0x48, 0x0F, 0xAF, 0xC1]);
+                asm.jump_if(CC_OVERFLOW, Target::Deoptimize);
+            },
+            BinaryOp::Divide | BinaryOp::Modulo => {
+                asm.emit(&[0x48, 0x85, 0xC9]);
+                asm.jump_if(CC_EQUAL, Target::Deoptimize);
+
+                // `idiv` traps on MIN / -1, so -1 is handled by negating.
+                asm.emit(&[0x48, 0x83, 0xF9, 0xFF]);
+                let divide = asm.skip_if(CC_NOT_EQUAL);
+                asm.emit(&[0x48, 0xF7, 0xD8]);
+                asm.jump_if(CC_OVERFLOW, Target::Deoptimize);
+                if op == BinaryOp::Modulo {
+                    asm.emit(&[0x31, 0xC0]);
+                }
+                let done = asm.skip();
+
+                asm.bind(divide);
+                asm.emit(&[0x48, 0x99, 0x48, 0xF7, 0xF9]);
+                if op == BinaryOp::Modulo {
+                    asm.emit(&[0x48, 0x89, 0xD0]);
+                }
+                asm.bind(done);
+            },
+            BinaryOp::Min => asm.emit(&[0x48, 0x39, 0xC8, 0x48, 0x0F, 0x4F, 0xC1]),
+            BinaryOp::Max => asm.emit(&[0x48, 0x39, 0xC8, 0x48, 0x0F, 0x4C, 0xC1]),
+            BinaryOp::ShiftLeft | BinaryOp::ShiftRight => {
+                asm.emit(&[0x48, 0x83, 0xF9, 0x3F]);
+                asm.jump_if(CC_ABOVE, Target::Deoptimize);
+
+                match op {
+                    BinaryOp::ShiftLeft => asm.emit(&[0x48, 0xD3, 0xE0]),
+                    _ => asm.emit(&[0x48, 0xD3, 0xF8]),
+                }
+            },
+            BinaryOp::BitAnd => asm.emit(&[0x48, 0x21, 0xC8]),
+            BinaryOp::BitOr => asm.emit(&[0x48, 0x09, 0xC8]),
+            BinaryOp::BitXor => asm.emit(&[0x48, 0x31, 0xC8]),
+            BinaryOp::Compare(comparison) => {
+                asm.emit(&[0x48, 0x39, 0xC8]);
+                asm.set(CompiledFunction::condition(comparison));
+            },
+            BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => {
+                asm.emit(&[0x48, 0x85, 0xC0, 0x0F, 0x95, 0xC0]);
+                asm.emit(&[0x48, 0x85, 0xC9, 0x0F, 0x95, 0xC1]);
+
+                match op {
+                    BinaryOp::And => asm.emit(&[0x20, 0xC8]),
+                    BinaryOp::Or => asm.emit(&[0x08, 0xC8]),
+                    _ => asm.emit(&[0x30, 0xC8]),
+                }
+                asm.emit(&[0x0F, 0xB6, 0xC0]);
+            },
+            BinaryOp::Power => {},
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Entry {
+    Counting(usize),
+    Compiled(CompiledFunction),
+    Interpreted,
+}
+
+// Counts calls per function and compiles a function once it has been called `threshold` times.
+// Functions that can't be compiled, or whose compiled code deoptimized once, stay interpreted.
+#[derive(Debug)]
+pub struct Jit {
+    threshold: usize,
+    functions: HashMap<Pointer, Entry>,
+}
+
+impl Jit {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            functions: HashMap::new(),
+        }
+    }
+
+    pub fn call(&mut self, func_ip: Pointer, program: &[Instruction]) -> Exit {
+        let entry = self.functions.entry(func_ip).or_insert(Entry::Counting(0));
+
+        if let Entry::Counting(calls) = entry {
+            *calls += 1;
+            if *calls < self.threshold {
+                return Exit::Interpret;
+            }
+
+            *entry = match CompiledFunction::compile(program, func_ip) {
+                Ok(function) => Entry::Compiled(function),
+                Err(_) => Entry::Interpreted,
+            };
+        }
+
+        let Entry::Compiled(function) = entry else {
+            return Exit::Interpret;
+        };
+
+        let exit = function.call();
+        if exit == Exit::Interpret {
+            *entry = Entry::Interpreted;
+        }
+
+        exit
+    }
+
+    pub fn compiled(&self) -> usize {
+        self.functions.values().filter(|entry| matches!(entry, Entry::Compiled(_))).count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        functions::Functions,
+        optimizer::Optimizer,
+        verifier::Verifier,
+        vm::{VirtualMachine, ExitStatus},
+        errors::Trap,
+        test_utils::{SharedBuffer, parse},
+    };
+
+    fn run(instructions: Vec<Instruction>, functions: &Functions, jit: Option<Jit>) -> (String, String, usize) {
+        let output = SharedBuffer::default();
+        let mut vm = VirtualMachine::default();
+        vm.set_output(Some(Box::new(output.clone())));
+        vm.set_jit(jit);
+
+        let result: Result<ExitStatus, Trap> = vm.run_verified(Verifier::verify(instructions, functions).unwrap());
+        let compiled = vm.jit().map_or(0, |jit| jit.compiled());
+        let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+
+        (format!("{:?}", result), output, compiled)
+    }
+
+    // Runs the program on the interpreter and with every function compiled on its first call.
+    fn run_both(buffer: &str) -> usize {
+        let (instructions, functions) = parse(buffer);
+        let (interpreted, interpreted_output, _) = run(instructions.clone(), &functions, None);
+        let (compiled, compiled_output, compiled_functions) = run(instructions, &functions, Some(Jit::new(1)));
+
+        assert_eq!(compiled, interpreted);
+        assert_eq!(compiled_output, interpreted_output);
+
+        compiled_functions
+    }
+
+    fn program(bodies: &[&str]) -> String {
+        let functions = bodies
+            .iter()
+            .enumerate()
+            .map(|(idx, body)| format!("FUNC F{}\n{}\nRETURN_VAL\n", idx, body.replace("; ", "\n")))
+            .collect::<String>();
+        let calls = (0..bodies.len())
+            .map(|idx| format!("CALL F{}\nPRINT\nCALL F{}\nPRINT\nADD\nPRINT\nDROP\n", idx, idx))
+            .collect::<String>();
+
+        functions + &calls
+    }
+
+    #[test]
+    fn compile() {
+        let (instructions, _) = parse("
+            FUNC SQUARE
+                LOAD_VAL 12
+                WRITE_VAR 'x'
+                READ_VAR 'x'
+                READ_VAR 'x'
+                MULTIPLY
+                RETURN_VAL
+            CALL SQUARE
+        ");
+        let mut function = CompiledFunction::compile(&instructions, 1).unwrap();
+
+        assert_eq!(function.call(), Exit::ReturnedValue(144));
+        assert_eq!(function.call(), Exit::ReturnedValue(144));
+    }
+
+    #[test]
+    fn compile_should_return_error_for_unsupported_instructions() {
+        let (instructions, _) = parse("
+            FUNC SHOW
+                LOAD_VAL 1
+                PRINT
+                RETURN
+            FUNC POWER
+                LOAD_VAL 2
+                LOAD_VAL 3
+                POW
+                RETURN_VAL
+            FUNC OUTER
+                CALL SHOW
+                RETURN
+            CALL OUTER
+            CALL POWER
+        ");
+
+        assert!(matches!(CompiledFunction::compile(&instructions, 1), Err(JitError::UnsupportedInstruction(2))));
+        assert!(matches!(CompiledFunction::compile(&instructions, 5), Err(JitError::UnsupportedInstruction(7))));
+        assert!(matches!(CompiledFunction::compile(&instructions, 10), Err(JitError::UnsupportedInstruction(10))));
+    }
+
+    #[test]
+    fn call_should_deoptimize_on_overflow() {
+        let (instructions, _) = parse("
+            FUNC OVERFLOW
+                LOAD_VAL 9223372036854775807
+                LOAD_VAL 1
+                ADD
+                RETURN_VAL
+            CALL OVERFLOW
+        ");
+        let mut function = CompiledFunction::compile(&instructions, 1).unwrap();
+
+        assert_eq!(function.call(), Exit::Interpret);
+    }
+
+    #[test]
+    fn jit_should_compile_after_threshold() {
+        let (instructions, _) = parse("
+            FUNC ONE
+                LOAD_VAL 1
+                RETURN_VAL
+            CALL ONE
+        ");
+        let mut jit = Jit::new(3);
+
+        assert_eq!(jit.call(1, &instructions), Exit::Interpret);
+        assert_eq!(jit.call(1, &instructions), Exit::Interpret);
+        assert_eq!(jit.compiled(), 0);
+        assert_eq!(jit.call(1, &instructions), Exit::ReturnedValue(1));
+        assert_eq!(jit.compiled(), 1);
+    }
+
+    #[test]
+    fn jit_should_keep_results_of_interpreter() {
+        let buffer = program(&[
+            "LOAD_VAL 7; LOAD_VAL 3; SUB; LOAD_VAL 6; MULTIPLY; LOAD_VAL 4; ADD",
+            "LOAD_VAL -7; LOAD_VAL 2; DIVIDE; LOAD_VAL -7; LOAD_VAL 2; MOD; ADD",
+            "LOAD_VAL 7; LOAD_VAL -1; DIVIDE; LOAD_VAL 7; LOAD_VAL -1; MOD; SUB",
+            "LOAD_VAL 5; LOAD_VAL 3; SHL; LOAD_VAL -20; LOAD_VAL 2; SHR; SUB",
+            "LOAD_VAL -5; ABS; LOAD_VAL 3; ABS; ADD; NEG; BNOT",
+            "LOAD_VAL 4; LOAD_VAL 9; MIN; LOAD_VAL 2; MAX; LOAD_VAL 6; BAND; LOAD_VAL 8; BOR; LOAD_VAL 5; BXOR",
+            "LOAD_VAL 1; LOAD_VAL 2; LT; LOAD_VAL 2; LOAD_VAL 2; LE; ADD; LOAD_VAL 3; LOAD_VAL 2; GT; ADD; \
+             LOAD_VAL 1; LOAD_VAL 2; GE; ADD; LOAD_VAL 1; LOAD_VAL 1; EQ; ADD; LOAD_VAL 1; LOAD_VAL 1; NE; ADD",
+            "LOAD_VAL 2; LOAD_VAL 0; AND; LOAD_VAL 3; OR; LOAD_VAL 1; XOR; NOT",
+            "LOAD_VAL 1; LOAD_VAL 2; LOAD_VAL 3; ROT; SWAP; OVER; PICK 3; DUP; SUB; ADD; ADD; SUB; ADD",
+            "LOAD_VAL 1; WRITE_VAR 'f'; LOAD_VAL 10; WRITE_VAR 'n'; LABEL FACTORIAL; READ_VAR 'f'; READ_VAR 'n'; \
+             MULTIPLY; WRITE_VAR 'f'; READ_VAR 'n'; LOAD_VAL 1; SUB; DUP; WRITE_VAR 'n'; JUMP_IF_TRUE FACTORIAL; \
+             READ_VAR 'f'",
+            "LOAD_VAL 0; JUMP_IF_ZERO SKIP; LOAD_VAL 5; JUMP PICKED; LABEL SKIP; LOAD_VAL 3; LABEL PICKED; \
+             LOAD_VAL 4; JUMP_IF_GREQ GREATER; LOAD_VAL 6; JUMP DONE; LABEL GREATER; LOAD_VAL 7; LABEL DONE",
+            "LOAD_VAL 0; WRITE_VAR 's'; LOAD_VAL 0; WRITE_VAR 'i'; LABEL SUM; READ_VAR 's'; READ_VAR 'i'; ADD; \
+             WRITE_VAR 's'; READ_VAR 'i'; LOAD_VAL 1; ADD; WRITE_VAR 'i'; READ_VAR 'i'; LOAD_VAL 10; \
+             JUMP_IF_SM SUM; READ_VAR 's'",
+        ]);
+
+        assert_eq!(run_both(&buffer), 12);
+
+        let (instructions, mut functions) = parse(&buffer);
+        let (instructions, _) = Optimizer::fuse(instructions, &mut functions);
+        assert!(instructions.iter().any(|instruction| matches!(instruction, Instruction::IncrementVariable(_, _))));
+        assert!(instructions.iter().any(|instruction| matches!(instruction, Instruction::JumpIfVariable(_, _, _, _))));
+
+        let (interpreted, interpreted_output, _) = run(instructions.clone(), &functions, None);
+        let (compiled, compiled_output, compiled_functions) = run(instructions, &functions, Some(Jit::new(1)));
+
+        assert_eq!(compiled, interpreted);
+        assert_eq!(compiled_output, interpreted_output);
+        assert_eq!(compiled_functions, 12);
+    }
+
+    #[test]
+    fn jit_should_keep_errors_of_interpreter() {
+        let bodies = [
+            "LOAD_VAL 9223372036854775807; LOAD_VAL 1; ADD",
+            "LOAD_VAL -9223372036854775807; LOAD_VAL 2; SUB",
+            "LOAD_VAL 9223372036854775807; LOAD_VAL 2; MULTIPLY",
+            "LOAD_VAL 1; LOAD_VAL 0; DIVIDE",
+            "LOAD_VAL 1; LOAD_VAL 0; MOD",
+            "LOAD_VAL -9223372036854775807; LOAD_VAL 1; SUB; LOAD_VAL -1; DIVIDE",
+            "LOAD_VAL -9223372036854775807; LOAD_VAL 1; SUB; LOAD_VAL -1; MOD",
+            "LOAD_VAL -9223372036854775807; LOAD_VAL 1; SUB; NEG",
+            "LOAD_VAL -9223372036854775807; LOAD_VAL 1; SUB; ABS",
+            "LOAD_VAL 1; LOAD_VAL 64; SHL",
+            "LOAD_VAL 1; LOAD_VAL -1; SHR",
+        ];
+
+        for body in bodies {
+            assert_eq!(run_both(&program(&[body])), 0);
+        }
+    }
+
+    #[test]
+    fn jit_should_keep_output_of_examples() {
+        for entry in fs::read_dir("examples").unwrap() {
+            let buffer = fs::read_to_string(entry.unwrap().path()).unwrap();
+            let (instructions, functions) = parse(&buffer);
+
+            if Verifier::verify(instructions, &functions).is_ok() {
+                run_both(&buffer);
+            }
+        }
+    }
+}
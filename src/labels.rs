@@ -2,31 +2,60 @@ use std::{collections::HashMap, iter::FromIterator};
 
 use crate::{vm::Pointer, errors::ParseError};
 
-#[derive(Debug, PartialEq)]
-pub struct Labels(HashMap<String, Pointer>);
+pub const GLOBAL_LABEL_PREFIX: char = '@';
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Labels(HashMap<(Option<String>, String), Pointer>);
 
 impl Labels {
     pub fn new() -> Labels {
         Labels(HashMap::new())
     }
 
-    pub fn insert(&mut self, label_name: &str, ip: Pointer) -> Result<(), ParseError> {
-        match self.0.insert(label_name.to_string(), ip) {
+    pub fn insert(&mut self, scope: &str, label_name: &str, ip: Pointer) -> Result<(), ParseError> {
+        match self.0.insert(Labels::key(scope, label_name), ip) {
             Some(_) => Err(ParseError::DuplicatedLabel(label_name.to_string())),
             None => Ok(()),
         }
     }
 
-    pub fn get(&self, label_name: &str) -> Result<&Pointer, ParseError> {
-        self.0.get(label_name).ok_or(ParseError::LabelNotFound(label_name.to_string()))
+    pub fn get(&self, scope: &str, label_name: &str) -> Result<&Pointer, ParseError> {
+        self.0
+            .get(&Labels::key(scope, label_name))
+            .ok_or(ParseError::LabelNotFound(label_name.to_string()))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Option<&str>, &str, Pointer)> {
+        self.0
+            .iter()
+            .map(|((scope, label_name), ip)| (scope.as_deref(), label_name.as_str(), *ip))
+    }
+
+    pub fn pointers_mut(&mut self) -> impl Iterator<Item = &mut Pointer> {
+        self.0.values_mut()
+    }
+
+    fn key(scope: &str, label_name: &str) -> (Option<String>, String) {
+        if label_name.starts_with(GLOBAL_LABEL_PREFIX) {
+            (None, label_name.to_string())
+        }
+        else {
+            (Some(scope.to_string()), label_name.to_string())
+        }
     }
 }
 
-impl FromIterator<(String, Pointer)> for Labels {
-    fn from_iter<I: IntoIterator<Item = (String, Pointer)>>(iter: I) -> Self {
+impl Default for Labels {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromIterator<(String, String, Pointer)> for Labels {
+    fn from_iter<I: IntoIterator<Item = (String, String, Pointer)>>(iter: I) -> Self {
         let mut labels = Labels::new();
-        for (label_name, ip) in iter {
-            labels.insert(&label_name, ip).unwrap();
+        for (scope, label_name, ip) in iter {
+            labels.insert(&scope, &label_name, ip).unwrap();
         }
 
         labels
@@ -48,34 +77,74 @@ mod test {
     fn insert() {
         let mut labels = Labels::new();
 
-        labels.insert("LOOP", 5).unwrap();
+        labels.insert("MAIN", "LOOP", 5).unwrap();
+
+        assert!(labels.0.contains_key(&(Some("MAIN".to_string()), "LOOP".to_string())));
+    }
+
+    #[test]
+    fn insert_global() {
+        let mut labels = Labels::new();
+
+        labels.insert("TEST", "@LOOP", 5).unwrap();
 
-        assert!(labels.0.contains_key("LOOP"));
+        assert!(labels.0.contains_key(&(None, "@LOOP".to_string())));
     }
 
     #[test]
     fn insert_should_return_error_when_label_duplicated() {
         let mut labels = Labels::new();
 
-        labels.insert("LOOP", 5).unwrap();
+        labels.insert("MAIN", "LOOP", 5).unwrap();
 
-        assert!(labels.insert("LOOP", 5).is_err());
+        assert!(labels.insert("MAIN", "LOOP", 5).is_err());
+    }
+
+    #[test]
+    fn insert_should_allow_same_label_in_different_scopes() {
+        let mut labels = Labels::new();
+
+        labels.insert("TEST1", "LOOP", 5).unwrap();
+
+        assert!(labels.insert("TEST2", "LOOP", 10).is_ok());
+    }
+
+    #[test]
+    fn insert_should_return_error_when_global_label_duplicated() {
+        let mut labels = Labels::new();
+
+        labels.insert("TEST1", "@LOOP", 5).unwrap();
+
+        assert!(labels.insert("TEST2", "@LOOP", 10).is_err());
     }
 
     #[test]
     fn get() {
         let mut labels = Labels::new();
 
-        labels.insert("LOOP", 5).unwrap();
+        labels.insert("MAIN", "LOOP", 5).unwrap();
+        labels.insert("TEST", "LOOP", 10).unwrap();
+        labels.insert("TEST", "@END", 15).unwrap();
 
-        assert_eq!(labels.get("LOOP").unwrap(), &5);
+        assert_eq!(labels.get("MAIN", "LOOP").unwrap(), &5);
+        assert_eq!(labels.get("TEST", "LOOP").unwrap(), &10);
+        assert_eq!(labels.get("MAIN", "@END").unwrap(), &15);
     }
 
     #[test]
     fn get_should_return_error_when_key_not_presented() {
         let labels = Labels::new();
 
-        assert!(labels.get("LOOP").is_err());
+        assert!(labels.get("MAIN", "LOOP").is_err());
+    }
+
+    #[test]
+    fn get_should_return_error_when_label_in_other_scope() {
+        let mut labels = Labels::new();
+
+        labels.insert("TEST", "LOOP", 5).unwrap();
+
+        assert!(labels.get("MAIN", "LOOP").is_err());
     }
 
     #[test]
@@ -85,13 +154,13 @@ mod test {
         let labels = labels_vec
             .iter()
             .enumerate()
-            .map(move |(i, y)| (y.to_string(), i))
+            .map(move |(i, y)| ("MAIN".to_string(), y.to_string(), i))
             .collect::<Labels>();
 
         println!("{:?}", labels);
 
-        assert_eq!(labels.get("LOOP1").unwrap(), &0);
-        assert_eq!(labels.get("LOOP2").unwrap(), &1);
+        assert_eq!(labels.get("MAIN", "LOOP1").unwrap(), &0);
+        assert_eq!(labels.get("MAIN", "LOOP2").unwrap(), &1);
     }
 
     #[test]
@@ -102,7 +171,7 @@ mod test {
         let _ = labels_vec
             .iter()
             .enumerate()
-            .map(move |(i, y)| (y.to_string(), i))
+            .map(move |(i, y)| ("MAIN".to_string(), y.to_string(), i))
             .collect::<Labels>();
     }
 }
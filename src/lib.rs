@@ -1,9 +1,28 @@
+#[cfg(all(feature = "jit", not(all(target_arch = "x86_64", target_os = "linux"))))]
+compile_error!("The jit feature is only supported on Linux x86-64.");
+
+pub mod backtrace;
+pub mod cfg;
+pub mod coverage;
 pub mod errors;
+pub mod formatter;
 pub mod frame;
 pub mod functions;
 pub mod instruction;
+#[cfg(feature = "jit")]
+pub mod jit;
 pub mod labels;
+pub mod limits;
+pub mod linker;
+pub mod object;
+pub mod optimizer;
 pub mod parser;
+pub mod profiler;
+pub mod register;
 pub mod stack;
+#[cfg(test)]
+mod test_utils;
+pub mod tracer;
 pub mod variables;
+pub mod verifier;
 pub mod vm;
@@ -0,0 +1,25 @@
+use std::time::Duration;
+
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+pub const DEFAULT_MAX_OPERAND_DEPTH: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    pub max_memory: Option<usize>,
+    pub max_call_depth: usize,
+    pub max_operand_depth: usize,
+    pub max_output: Option<usize>,
+    pub max_time: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_memory: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_operand_depth: DEFAULT_MAX_OPERAND_DEPTH,
+            max_output: None,
+            max_time: None,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use crate::{object::Object, functions::Functions, errors::LinkError};
+
+pub struct Linker;
+
+impl Linker {
+    pub fn link(objects: Vec<Object>) -> Result<Object, LinkError> {
+        let mut exports = Functions::new();
+        let mut base_ip = 0;
+
+        for object in objects.iter() {
+            for (func_name, (start_ip, end_ip)) in object.exports.iter() {
+                exports
+                    .insert(func_name, (start_ip + base_ip, end_ip + base_ip))
+                    .map_err(|_| LinkError::DuplicatedSymbol(func_name.to_string()))?;
+            }
+
+            base_ip += object.instructions.len();
+        }
+
+        let mut instructions = Vec::with_capacity(base_ip);
+        let mut main_locals = 0;
+
+        for object in objects {
+            let base_ip = instructions.len();
+            let imports = object.imports
+                .into_iter()
+                .collect::<HashMap<_, _>>();
+            let func_ranges = object.exports
+                .iter()
+                .map(|(_, range)| *range)
+                .collect::<Vec<_>>();
+
+            for (ip, mut instruction) in object.instructions.into_iter().enumerate() {
+                if let Some(func_name) = imports.get(&ip) {
+                    let (start_ip, _) = exports
+                        .get(func_name)
+                        .map_err(|_| LinkError::SymbolNotFound(func_name.to_string()))?;
+                    for target_ip in instruction.pointers_mut() {
+                        *target_ip = start_ip + 1;
+                    }
+                }
+                else {
+                    for target_ip in instruction.pointers_mut() {
+                        *target_ip += base_ip;
+                    }
+                }
+
+                let is_main_code = func_ranges
+                    .iter()
+                    .all(|(start_ip, end_ip)| ip < *start_ip || ip > *end_ip);
+
+                if is_main_code {
+                    if let Some(var_idx) = instruction.variable_mut() {
+                        *var_idx += main_locals;
+                    }
+                }
+
+                instructions.push(instruction);
+            }
+
+            main_locals += object.main_locals;
+        }
+
+        Ok(Object::new(instructions, exports, vec![], main_locals))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser::Parser, instruction::Instruction};
+
+    fn assemble(buffer: &str) -> Object {
+        let bytecode = Parser::parse_code(buffer);
+        let functions = Parser::parse_functions(&bytecode).unwrap();
+        let mut variables = Parser::parse_variables(&bytecode).unwrap();
+        let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
+
+        Parser::parse_object(&bytecode, &functions, &mut variables, &labels).unwrap()
+    }
+
+    #[test]
+    fn link() {
+        let main = assemble("LOAD_VAL 1\nWRITE_VAR 'x'\nCALL DOUBLE\nPRINT");
+        let lib = assemble("LOAD_VAL 2\nWRITE_VAR 'y'\nFUNC DOUBLE\nLOAD_VAL 2\nRETURN_VAL");
+
+        let program = Linker::link(vec![main, lib]).unwrap();
+
+        let expected_instructions = vec![
+            Instruction::LoadValue(1),
+            Instruction::WriteVariable(0),
+            Instruction::CallFunction(7),
+            Instruction::Print,
+            Instruction::LoadValue(2),
+            Instruction::WriteVariable(1),
+            Instruction::Jump(9),
+            Instruction::LoadValue(2),
+            Instruction::ReturnValue,
+        ];
+
+        assert_eq!(program.instructions, expected_instructions);
+        assert_eq!(program.exports.get("DOUBLE").unwrap(), &(6, 8));
+        assert!(program.imports.is_empty());
+        assert_eq!(program.main_locals, 2);
+    }
+
+    #[test]
+    fn link_tail_call() {
+        let main = assemble("FUNC NEXT\nTAIL_CALL DOUBLE\nRETURN_VAL\nCALL NEXT\nPRINT");
+        let lib = assemble("FUNC DOUBLE\nLOAD_VAL 2\nRETURN_VAL");
+
+        let program = Linker::link(vec![main, lib]).unwrap();
+
+        assert_eq!(program.instructions[1], Instruction::TailCall(6));
+        assert_eq!(program.instructions[3], Instruction::CallFunction(1));
+    }
+
+    #[test]
+    fn link_should_return_error_for_duplicated_symbols() {
+        let first = assemble("FUNC TEST\nRETURN");
+        let second = assemble("FUNC TEST\nRETURN");
+
+        assert!(Linker::link(vec![first, second]).is_err());
+    }
+
+    #[test]
+    fn link_should_return_error_for_missing_symbols() {
+        let main = assemble("CALL TEST");
+
+        assert!(Linker::link(vec![main]).is_err());
+    }
+}
@@ -1,24 +1,347 @@
-use std::{env::args, fs::File, io::Read};
+mod cli;
 
-use bytecode::{parser::Parser, vm::VirtualMachine};
+use std::{env::args, fmt::{Display, Formatter as FmtFormatter, Result as FmtResult}, fs::{self, File}, io::{self, BufWriter, Read, Write}, mem, path::Path, process::exit};
 
-fn main() -> std::io::Result<()> {
-    let args: Vec<String> = args().collect();
-    let mut f = File::open(&args[1])?;
+use bytecode::{
+    parser::Parser,
+    vm::{VirtualMachine, USAGE_ERROR_EXIT_CODE, IO_ERROR_EXIT_CODE, PARSE_ERROR_EXIT_CODE, RUNTIME_ERROR_EXIT_CODE},
+    tracer::Tracer,
+    profiler::Profiler,
+    coverage::Coverage,
+    object::Object,
+    linker::Linker,
+    formatter::Formatter,
+    optimizer::Optimizer,
+    backtrace::DebugInfo,
+    cfg::ControlFlowGraph,
+    labels::Labels,
+    verifier::Verifier,
+    register::{RegisterProgram, RegisterMachine},
+    errors::{ParseError, LinkError, VerifyError, Trap},
+};
 
-    let mut buffer = String::new();
-    f.read_to_string(&mut buffer)?;
+#[cfg(feature = "jit")]
+use bytecode::jit::Jit;
 
-    let bytecode = Parser::parse_code(&buffer);
-    let functions = Parser::parse_functions(&bytecode).unwrap();
-    let mut variables = Parser::parse_variables(&bytecode).unwrap();
-    let labels = Parser::parse_labels(&bytecode);
-    let program = Parser::parse_instructions(
-        &bytecode, &functions, &mut variables, &labels
-    ).unwrap();
+use cli::{Command, Engine, RunOptions, TraceOptions, STDIN_PATH, USAGE};
 
-    let mut vm = VirtualMachine::new();
-    vm.run(program);
+enum CliError {
+    Usage(String),
+    Io(io::Error),
+    Parse(ParseError),
+    Link(LinkError),
+    Verify(VerifyError),
+    Runtime(Trap),
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => USAGE_ERROR_EXIT_CODE,
+            CliError::Io(_) => IO_ERROR_EXIT_CODE,
+            CliError::Parse(_) | CliError::Link(_) | CliError::Verify(_) => PARSE_ERROR_EXIT_CODE,
+            CliError::Runtime(_) => RUNTIME_ERROR_EXIT_CODE,
+        }
+    }
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut FmtFormatter) -> FmtResult {
+        match self {
+            CliError::Usage(err) => write!(f, "{}\n\n{}", err, USAGE),
+            CliError::Io(err) => write!(f, "I/O error: {}", err),
+            CliError::Parse(err) => write!(f, "Parse error: {}", err),
+            CliError::Link(err) => write!(f, "Link error: {}", err),
+            CliError::Verify(err) => write!(f, "Verify error: {}", err),
+            CliError::Runtime(err) => write!(f, "Runtime error: {}", err),
+        }
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(err: io::Error) -> Self {
+        CliError::Io(err)
+    }
+}
+
+impl From<ParseError> for CliError {
+    fn from(err: ParseError) -> Self {
+        CliError::Parse(err)
+    }
+}
+
+impl From<LinkError> for CliError {
+    fn from(err: LinkError) -> Self {
+        CliError::Link(err)
+    }
+}
+
+impl From<VerifyError> for CliError {
+    fn from(err: VerifyError) -> Self {
+        CliError::Verify(err)
+    }
+}
+
+impl From<Trap> for CliError {
+    fn from(err: Trap) -> Self {
+        CliError::Runtime(err)
+    }
+}
+
+fn main() {
+    let args: Vec<String> = args().skip(1).collect();
+
+    let result = Command::parse(&args)
+        .map_err(CliError::Usage)
+        .and_then(execute);
+
+    match result {
+        Ok(code) => exit(code),
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(err.exit_code());
+        },
+    }
+}
+
+fn execute(command: Command) -> Result<i32, CliError> {
+    match command {
+        Command::Run { input, options } => run(&input, *options),
+        Command::Check { input } => check(&input),
+        Command::Disasm { input } => disasm(&input),
+        Command::Cfg { input, output } => cfg(&input, output.as_deref()),
+        Command::Fmt { input, output } => fmt(&input, output.as_deref()),
+        Command::Compile { input, output } => compile(&input, &output),
+        Command::Assemble { input, output } => assemble(&input, &output),
+        Command::Link { inputs, output } => link(&inputs, &output),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(0)
+        },
+    }
+}
+
+fn run(path: &str, options: RunOptions) -> Result<i32, CliError> {
+    let (mut program, mut debug_info) = load(path)?;
+
+    if options.optimize {
+        optimize(&mut program, &mut debug_info, options.optimize_report);
+    }
+
+    let verified = Verifier::verify(program.instructions.clone(), &program.exports);
+
+    // Programs the verifier rejects, or that outgrow the operand stack limit, run on the stack VM.
+    if options.engine == Engine::Register {
+        match &verified {
+            Ok(verified) if verified.max_depth() <= options.limits.max_operand_depth => {
+                let mut machine = RegisterMachine::new(options.limits);
+                machine.set_fuel(options.max_steps);
+                machine.set_debug_info(Some(debug_info));
+
+                return Ok(machine.run(&RegisterProgram::translate(verified, &program.exports))?.code());
+            },
+            Ok(_) => eprintln!("Running on the stack engine because the operand stack may exceed max_operand_depth."),
+            Err(err) => eprintln!("Running on the stack engine because the verifier rejected the program: {}", err),
+        }
+    }
+
+    let mut vm = VirtualMachine::new(options.limits);
+    vm.set_tracer(options.trace.map(tracer).transpose()?);
+    vm.set_fuel(options.max_steps);
+    #[cfg(feature = "jit")]
+    vm.set_jit(options.jit.map(Jit::new));
+
+    if options.profile || options.profile_folded.is_some() {
+        vm.set_profiler(Some(Profiler::new()));
+    }
+    if options.coverage || options.coverage_lcov.is_some() {
+        vm.set_coverage(Some(Coverage::new()));
+    }
+
+    vm.set_debug_info(Some(debug_info));
+    let result = match verified {
+        Ok(verified) => vm.run_verified(verified),
+        Err(_) => vm.run(program.instructions),
+    };
+
+    if let Some(profiler) = vm.profiler() {
+        if options.profile {
+            eprint!("{}", profiler.report(vm.program()));
+        }
+        if let Some(output) = options.profile_folded {
+            fs::write(output, profiler.folded())?;
+        }
+    }
+
+    if let (Some(coverage), Some(debug_info)) = (vm.coverage(), vm.debug_info()) {
+        if options.coverage {
+            eprint!("{}", coverage.summary(vm.program(), debug_info));
+        }
+        if let Some(output) = options.coverage_lcov {
+            fs::write(output, coverage.lcov(vm.program(), debug_info, path))?;
+        }
+    }
+
+    Ok(result?.code())
+}
+
+fn optimize(program: &mut Object, debug_info: &mut DebugInfo, print_report: bool) {
+    let instructions = mem::take(&mut program.instructions);
+    let (instructions, relocation, report) = Optimizer::fold_constants(instructions, &mut program.exports);
+
+    if print_report {
+        eprint!("{}", report.summary(debug_info));
+    }
+    debug_info.relocate(&relocation, &program.exports);
+
+    let (instructions, relocation) = Optimizer::peephole(instructions, &mut program.exports);
+    debug_info.relocate(&relocation, &program.exports);
+
+    let (instructions, relocation) = Optimizer::lower(instructions, &mut program.exports);
+    debug_info.relocate(&relocation, &program.exports);
+
+    let (instructions, relocation) = Optimizer::fuse(instructions, &mut program.exports);
+
+    program.instructions = instructions;
+    debug_info.relocate(&relocation, &program.exports);
+}
+
+fn tracer(options: TraceOptions) -> io::Result<Tracer> {
+    let sink: Box<dyn Write> = match options.output {
+        Some(output) => Box::new(BufWriter::new(File::create(output)?)),
+        None => Box::new(io::stderr()),
+    };
+
+    Ok(Tracer::new(sink, options.format).with_filter(options.filter))
+}
+
+fn check(path: &str) -> Result<i32, CliError> {
+    let (program, _) = load(path)?;
+    Verifier::verify(program.instructions, &program.exports)?;
+
+    Ok(0)
+}
+
+fn disasm(path: &str) -> Result<i32, CliError> {
+    let (program, _) = load(path)?;
+
+    for (ip, instruction) in program.instructions.iter().enumerate() {
+        if let Some((func_name, _)) = program.exports.iter().find(|(_, (start_ip, _))| *start_ip == ip) {
+            println!("; FUNC {}", func_name);
+        }
+
+        println!("{:>6}  {}", ip, instruction);
+    }
+
+    Ok(0)
+}
+
+fn cfg(path: &str, output: Option<&str>) -> Result<i32, CliError> {
+    let (program, debug_info) = load(path)?;
+    let graph = ControlFlowGraph::new(&program.instructions, &program.exports, &debug_info.labels);
+
+    if let Some(output) = output {
+        fs::create_dir_all(output)?;
+    }
+
+    for (func_name, _) in graph.functions() {
+        let dot = graph.dot(func_name, &program.instructions).unwrap();
+
+        match output {
+            Some(output) => fs::write(Path::new(output).join(format!("{}.dot", func_name)), dot)?,
+            None => print!("{}", dot),
+        }
+    }
+
+    Ok(0)
+}
+
+fn fmt(path: &str, output: Option<&str>) -> Result<i32, CliError> {
+    let formatted = Formatter::format(&read_source(path)?);
+
+    match output {
+        Some(output) => fs::write(output, formatted)?,
+        None => print!("{}", formatted),
+    }
+
+    Ok(0)
+}
+
+fn compile(path: &str, output: &str) -> Result<i32, CliError> {
+    fs::write(output, load(path)?.0.to_bytes())?;
+
+    Ok(0)
+}
+
+fn assemble(path: &str, output: &str) -> Result<i32, CliError> {
+    fs::write(output, parse(&read_source(path)?)?.to_bytes())?;
+
+    Ok(0)
+}
+
+fn link(paths: &[String], output: &str) -> Result<i32, CliError> {
+    let objects = paths
+        .iter()
+        .map(|path| Ok(Object::from_bytes(&read(path)?)?))
+        .collect::<Result<Vec<_>, CliError>>()?;
+
+    fs::write(output, Linker::link(objects)?.to_bytes())?;
+
+    Ok(0)
+}
+
+fn load(path: &str) -> Result<(Object, DebugInfo), CliError> {
+    let buffer = read(path)?;
+
+    let (object, lines, labels) = if Object::is_object(&buffer) {
+        (Object::from_bytes(&buffer)?, vec![], Labels::new())
+    }
+    else {
+        let buffer = into_source(buffer)?;
+        let object = parse(&buffer)?;
+
+        if let Some((_, func_name)) = object.imports.first() {
+            return Err(ParseError::FunctionNotFound(func_name.to_string()).into());
+        }
+
+        let bytecode = Parser::parse_code(&buffer);
+        let labels = Parser::parse_labels(&bytecode, &object.exports)?;
+
+        (object, Parser::parse_lines(&buffer), labels)
+    };
+
+    let program = Linker::link(vec![object])?;
+    let debug_info = DebugInfo::new(program.exports.clone(), lines).with_labels(labels);
+
+    Ok((program, debug_info))
+}
+
+fn parse(buffer: &str) -> Result<Object, ParseError> {
+    let bytecode = Parser::parse_code(buffer);
+    let functions = Parser::parse_functions(&bytecode)?;
+    let mut variables = Parser::parse_variables(&bytecode)?;
+    let labels = Parser::parse_labels(&bytecode, &functions)?;
+
+    Parser::parse_object(&bytecode, &functions, &mut variables, &labels)
+}
+
+fn read(path: &str) -> io::Result<Vec<u8>> {
+    if path == STDIN_PATH {
+        let mut buffer = vec![];
+        io::stdin().read_to_end(&mut buffer)?;
+
+        Ok(buffer)
+    }
+    else {
+        fs::read(path)
+    }
+}
+
+fn read_source(path: &str) -> Result<String, CliError> {
+    into_source(read(path)?)
+}
 
-    Ok(())
+fn into_source(buffer: Vec<u8>) -> Result<String, CliError> {
+    String::from_utf8(buffer)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err).into())
 }
@@ -0,0 +1,411 @@
+use crate::{instruction::{Instruction, Comparison}, functions::Functions, vm::{Pointer, ExitStatus}, errors::LinkError};
+
+const MAGIC: &[u8; 4] = b"BCO\x01";
+
+pub type Import = (Pointer, String);
+
+#[derive(Debug, PartialEq)]
+pub struct Object {
+    pub instructions: Vec<Instruction>,
+    pub exports: Functions,
+    pub imports: Vec<Import>,
+    pub main_locals: usize,
+}
+
+impl Object {
+    pub fn new(
+        instructions: Vec<Instruction>,
+        exports: Functions,
+        imports: Vec<Import>,
+        main_locals: usize,
+    ) -> Self {
+        Self {
+            instructions,
+            exports,
+            imports,
+            main_locals,
+        }
+    }
+
+    pub fn is_object(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+
+        write_usize(&mut bytes, self.instructions.len());
+        for instruction in self.instructions.iter() {
+            write_instruction(&mut bytes, instruction);
+        }
+
+        let mut exports = self.exports.iter().collect::<Vec<_>>();
+        exports.sort();
+        write_usize(&mut bytes, exports.len());
+        for (func_name, (start_ip, end_ip)) in exports {
+            write_str(&mut bytes, func_name);
+            write_usize(&mut bytes, *start_ip);
+            write_usize(&mut bytes, *end_ip);
+        }
+
+        write_usize(&mut bytes, self.imports.len());
+        for (ip, func_name) in self.imports.iter() {
+            write_usize(&mut bytes, *ip);
+            write_str(&mut bytes, func_name);
+        }
+
+        write_usize(&mut bytes, self.main_locals);
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LinkError> {
+        if !Object::is_object(bytes) {
+            return Err(LinkError::InvalidObject("missing magic number".to_string()));
+        }
+
+        let mut reader = Reader { bytes, pos: MAGIC.len() };
+
+        let instructions = (0..reader.read_usize()?)
+            .map(|_| reader.read_instruction())
+            .collect::<Result<Vec<_>, LinkError>>()?;
+
+        let mut exports = Functions::new();
+        for _ in 0..reader.read_usize()? {
+            let func_name = reader.read_string()?;
+            let (start_ip, end_ip) = (reader.read_usize()?, reader.read_usize()?);
+            exports
+                .insert(&func_name, (start_ip, end_ip))
+                .map_err(|_| LinkError::DuplicatedSymbol(func_name))?;
+        }
+
+        let imports = (0..reader.read_usize()?)
+            .map(|_| Ok((reader.read_usize()?, reader.read_string()?)))
+            .collect::<Result<Vec<_>, LinkError>>()?;
+
+        let main_locals = reader.read_usize()?;
+
+        if reader.pos != bytes.len() {
+            return Err(LinkError::InvalidObject("trailing bytes".to_string()));
+        }
+
+        Ok(Object::new(instructions, exports, imports, main_locals))
+    }
+}
+
+fn write_usize(bytes: &mut Vec<u8>, val: usize) {
+    bytes.extend_from_slice(&(val as u64).to_le_bytes());
+}
+
+fn write_isize(bytes: &mut Vec<u8>, val: isize) {
+    bytes.extend_from_slice(&(val as i64).to_le_bytes());
+}
+
+fn write_str(bytes: &mut Vec<u8>, val: &str) {
+    write_usize(bytes, val.len());
+    bytes.extend_from_slice(val.as_bytes());
+}
+
+fn write_instruction(bytes: &mut Vec<u8>, instruction: &Instruction) {
+    match instruction {
+        Instruction::LoadValue(val) => {
+            bytes.push(0x01);
+            write_isize(bytes, *val);
+        },
+        Instruction::Duplicate => bytes.push(0x2E),
+        Instruction::Drop => bytes.push(0x2F),
+        Instruction::Swap => bytes.push(0x30),
+        Instruction::Over => bytes.push(0x31),
+        Instruction::Rotate => bytes.push(0x32),
+        Instruction::Pick(depth) => {
+            bytes.push(0x33);
+            write_usize(bytes, *depth);
+        },
+        Instruction::WriteVariable(var_idx) => {
+            bytes.push(0x02);
+            write_usize(bytes, *var_idx);
+        },
+        Instruction::ReadVariable(var_idx) => {
+            bytes.push(0x03);
+            write_usize(bytes, *var_idx);
+        },
+        Instruction::Add => bytes.push(0x04),
+        Instruction::Sub => bytes.push(0x05),
+        Instruction::Multiply => bytes.push(0x06),
+        Instruction::Divide => bytes.push(0x07),
+        Instruction::Modulo => bytes.push(0x22),
+        Instruction::Negate => bytes.push(0x23),
+        Instruction::Absolute => bytes.push(0x24),
+        Instruction::Power => bytes.push(0x25),
+        Instruction::Min => bytes.push(0x26),
+        Instruction::Max => bytes.push(0x27),
+        Instruction::ShiftLeft => bytes.push(0x28),
+        Instruction::ShiftRight => bytes.push(0x29),
+        Instruction::BitAnd => bytes.push(0x2A),
+        Instruction::BitOr => bytes.push(0x2B),
+        Instruction::BitXor => bytes.push(0x2C),
+        Instruction::BitNot => bytes.push(0x2D),
+        Instruction::Equal => bytes.push(0x18),
+        Instruction::NotEqual => bytes.push(0x19),
+        Instruction::Smaller => bytes.push(0x1A),
+        Instruction::SmallerEqual => bytes.push(0x1B),
+        Instruction::Greater => bytes.push(0x1C),
+        Instruction::GreaterEqual => bytes.push(0x1D),
+        Instruction::And => bytes.push(0x1E),
+        Instruction::Or => bytes.push(0x1F),
+        Instruction::Xor => bytes.push(0x20),
+        Instruction::Not => bytes.push(0x21),
+        Instruction::Print => bytes.push(0x08),
+        Instruction::PrintVariable(var_name, var_idx) => {
+            bytes.push(0x09);
+            write_str(bytes, var_name);
+            write_usize(bytes, *var_idx);
+        },
+        Instruction::Jump(ip) => {
+            bytes.push(0x0A);
+            write_usize(bytes, *ip);
+        },
+        Instruction::JumpIfEqual(ip) => {
+            bytes.push(0x0B);
+            write_usize(bytes, *ip);
+        },
+        Instruction::JumpIfNotEqual(ip) => {
+            bytes.push(0x0C);
+            write_usize(bytes, *ip);
+        },
+        Instruction::JumpIfGreater(ip) => {
+            bytes.push(0x0D);
+            write_usize(bytes, *ip);
+        },
+        Instruction::JumpIfSmaller(ip) => {
+            bytes.push(0x0E);
+            write_usize(bytes, *ip);
+        },
+        Instruction::JumpIfGreaterEqual(ip) => {
+            bytes.push(0x0F);
+            write_usize(bytes, *ip);
+        },
+        Instruction::JumpIfSmallerEqual(ip) => {
+            bytes.push(0x10);
+            write_usize(bytes, *ip);
+        },
+        Instruction::JumpIfTrue(ip) => {
+            bytes.push(0x15);
+            write_usize(bytes, *ip);
+        },
+        Instruction::JumpIfZero(ip) => {
+            bytes.push(0x16);
+            write_usize(bytes, *ip);
+        },
+        Instruction::JumpTable(ips) => {
+            bytes.push(0x17);
+            write_usize(bytes, ips.len());
+            for ip in ips.iter() {
+                write_usize(bytes, *ip);
+            }
+        },
+        Instruction::CallFunction(ip) => {
+            bytes.push(0x11);
+            write_usize(bytes, *ip);
+        },
+        Instruction::TailCall(ip) => {
+            bytes.push(0x38);
+            write_usize(bytes, *ip);
+        },
+        Instruction::Return => bytes.push(0x12),
+        Instruction::ReturnValue => bytes.push(0x13),
+        Instruction::Ignore => bytes.push(0x14),
+        Instruction::Halt => bytes.push(0x34),
+        Instruction::Exit(code) => {
+            bytes.push(0x35);
+            write_isize(bytes, *code as isize);
+        },
+        Instruction::IncrementVariable(var_idx, val) => {
+            bytes.push(0x36);
+            write_usize(bytes, *var_idx);
+            write_isize(bytes, *val);
+        },
+        Instruction::JumpIfVariable(comparison, var_idx, val, ip) => {
+            bytes.push(0x37);
+            bytes.push(*comparison as u8);
+            write_usize(bytes, *var_idx);
+            write_isize(bytes, *val);
+            write_usize(bytes, *ip);
+        },
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], LinkError> {
+        let bytes = self.bytes
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or(LinkError::InvalidObject("unexpected end of file".to_string()))?;
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, LinkError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_usize(&mut self) -> Result<usize, LinkError> {
+        let bytes = self.read_bytes(8)?.try_into().unwrap();
+        Ok(u64::from_le_bytes(bytes) as usize)
+    }
+
+    fn read_isize(&mut self) -> Result<isize, LinkError> {
+        let bytes = self.read_bytes(8)?.try_into().unwrap();
+        Ok(i64::from_le_bytes(bytes) as isize)
+    }
+
+    fn read_string(&mut self) -> Result<String, LinkError> {
+        let len = self.read_usize()?;
+        String::from_utf8(self.read_bytes(len)?.to_vec())
+            .map_err(|_| LinkError::InvalidObject("symbol is not valid UTF-8".to_string()))
+    }
+
+    fn read_comparison(&mut self) -> Result<Comparison, LinkError> {
+        match self.read_u8()? {
+            0 => Ok(Comparison::Equal),
+            1 => Ok(Comparison::NotEqual),
+            2 => Ok(Comparison::Greater),
+            3 => Ok(Comparison::Smaller),
+            4 => Ok(Comparison::GreaterEqual),
+            5 => Ok(Comparison::SmallerEqual),
+            comparison => Err(LinkError::InvalidObject(format!("unknown comparison {}", comparison))),
+        }
+    }
+
+    fn read_instruction(&mut self) -> Result<Instruction, LinkError> {
+        match self.read_u8()? {
+            0x01 => Ok(Instruction::LoadValue(self.read_isize()?)),
+            0x02 => Ok(Instruction::WriteVariable(self.read_usize()?)),
+            0x03 => Ok(Instruction::ReadVariable(self.read_usize()?)),
+            0x04 => Ok(Instruction::Add),
+            0x05 => Ok(Instruction::Sub),
+            0x06 => Ok(Instruction::Multiply),
+            0x07 => Ok(Instruction::Divide),
+            0x08 => Ok(Instruction::Print),
+            0x09 => Ok(Instruction::PrintVariable(self.read_string()?, self.read_usize()?)),
+            0x0A => Ok(Instruction::Jump(self.read_usize()?)),
+            0x0B => Ok(Instruction::JumpIfEqual(self.read_usize()?)),
+            0x0C => Ok(Instruction::JumpIfNotEqual(self.read_usize()?)),
+            0x0D => Ok(Instruction::JumpIfGreater(self.read_usize()?)),
+            0x0E => Ok(Instruction::JumpIfSmaller(self.read_usize()?)),
+            0x0F => Ok(Instruction::JumpIfGreaterEqual(self.read_usize()?)),
+            0x10 => Ok(Instruction::JumpIfSmallerEqual(self.read_usize()?)),
+            0x11 => Ok(Instruction::CallFunction(self.read_usize()?)),
+            0x12 => Ok(Instruction::Return),
+            0x13 => Ok(Instruction::ReturnValue),
+            0x14 => Ok(Instruction::Ignore),
+            0x15 => Ok(Instruction::JumpIfTrue(self.read_usize()?)),
+            0x16 => Ok(Instruction::JumpIfZero(self.read_usize()?)),
+            0x17 => Ok(Instruction::JumpTable(
+                (0..self.read_usize()?)
+                    .map(|_| self.read_usize())
+                    .collect::<Result<Vec<_>, LinkError>>()?
+            )),
+            0x18 => Ok(Instruction::Equal),
+            0x19 => Ok(Instruction::NotEqual),
+            0x1A => Ok(Instruction::Smaller),
+            0x1B => Ok(Instruction::SmallerEqual),
+            0x1C => Ok(Instruction::Greater),
+            0x1D => Ok(Instruction::GreaterEqual),
+            0x1E => Ok(Instruction::And),
+            0x1F => Ok(Instruction::Or),
+            0x20 => Ok(Instruction::Xor),
+            0x21 => Ok(Instruction::Not),
+            0x22 => Ok(Instruction::Modulo),
+            0x23 => Ok(Instruction::Negate),
+            0x24 => Ok(Instruction::Absolute),
+            0x25 => Ok(Instruction::Power),
+            0x26 => Ok(Instruction::Min),
+            0x27 => Ok(Instruction::Max),
+            0x28 => Ok(Instruction::ShiftLeft),
+            0x29 => Ok(Instruction::ShiftRight),
+            0x2A => Ok(Instruction::BitAnd),
+            0x2B => Ok(Instruction::BitOr),
+            0x2C => Ok(Instruction::BitXor),
+            0x2D => Ok(Instruction::BitNot),
+            0x2E => Ok(Instruction::Duplicate),
+            0x2F => Ok(Instruction::Drop),
+            0x30 => Ok(Instruction::Swap),
+            0x31 => Ok(Instruction::Over),
+            0x32 => Ok(Instruction::Rotate),
+            0x33 => Ok(Instruction::Pick(self.read_usize()?)),
+            0x34 => Ok(Instruction::Halt),
+            0x35 => Ok(Instruction::Exit(
+                i32::try_from(self.read_isize()?)
+                    .ok()
+                    .filter(|code| ExitStatus::is_valid_code(*code))
+                    .ok_or(LinkError::InvalidObject("exit code out of range".to_string()))?
+            )),
+            0x36 => Ok(Instruction::IncrementVariable(self.read_usize()?, self.read_isize()?)),
+            0x37 => Ok(Instruction::JumpIfVariable(
+                self.read_comparison()?,
+                self.read_usize()?,
+                self.read_isize()?,
+                self.read_usize()?,
+            )),
+            0x38 => Ok(Instruction::TailCall(self.read_usize()?)),
+            opcode => Err(LinkError::InvalidObject(format!("unknown opcode {:#04x}", opcode))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn to_bytes_from_bytes() {
+        let mut exports = Functions::new();
+        exports.insert("TEST", (5, 9)).unwrap();
+
+        let object = Object::new(
+            vec![
+                Instruction::LoadValue(-5),
+                Instruction::GreaterEqual,
+                Instruction::Not,
+                Instruction::Pick(2),
+                Instruction::CallFunction(0),
+                Instruction::TailCall(5),
+                Instruction::Jump(6),
+                Instruction::JumpTable(vec![0, 2]),
+                Instruction::ReadVariable(0),
+                Instruction::IncrementVariable(0, -2),
+                Instruction::JumpIfVariable(Comparison::GreaterEqual, 0, 10, 3),
+                Instruction::PrintVariable("'x'".to_string(), 0),
+                Instruction::ReturnValue,
+                Instruction::Exit(3),
+            ],
+            exports,
+            vec![(4, "OTHER".to_string())],
+            1,
+        );
+
+        let bytes = object.to_bytes();
+
+        assert!(Object::is_object(&bytes));
+        assert_eq!(Object::from_bytes(&bytes).unwrap(), object);
+    }
+
+    #[test]
+    fn from_bytes_should_return_error_without_magic() {
+        assert!(Object::from_bytes(b"LOAD_VAL 5").is_err());
+    }
+
+    #[test]
+    fn from_bytes_should_return_error_when_truncated() {
+        let object = Object::new(vec![Instruction::LoadValue(5)], Functions::new(), vec![], 0);
+        let bytes = object.to_bytes();
+
+        assert!(Object::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,1044 @@
+use std::{collections::HashMap, fmt::Write, iter};
+
+use crate::{
+    vm::{VirtualMachine, Pointer, ExitStatus},
+    instruction::{Instruction, Comparison},
+    functions::{Functions, MAIN_FUNCTION},
+    labels::Labels,
+    cfg::{ControlFlowGraph, BasicBlock},
+    backtrace::DebugInfo,
+};
+
+type Constant = Option<(isize, Vec<Pointer>)>;
+
+enum Slot {
+    Instruction(Pointer),
+    Header(Pointer),
+    FallThrough(Pointer, Pointer),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    targets: Vec<Pointer>,
+    sources: Vec<Pointer>,
+}
+
+impl Relocation {
+    pub fn new(kept: Vec<bool>) -> Self {
+        let mut targets = Vec::with_capacity(kept.len() + 1);
+        let mut sources = vec![];
+
+        for (ip, is_kept) in kept.into_iter().enumerate() {
+            targets.push(sources.len());
+
+            if is_kept {
+                sources.push(ip);
+            }
+        }
+        targets.push(sources.len());
+
+        Self { targets, sources }
+    }
+
+    // Pointers to a removed instruction move to the next kept one.
+    pub fn get(&self, ip: Pointer) -> Pointer {
+        match self.targets.get(ip) {
+            Some(target_ip) => *target_ip,
+            None => ip + self.sources.len() + 1 - self.targets.len(),
+        }
+    }
+
+    pub fn source_of(&self, ip: Pointer) -> Option<Pointer> {
+        self.sources.get(ip).copied()
+    }
+
+    pub fn apply(&self, instructions: &[Instruction]) -> Vec<Instruction> {
+        self.sources
+            .iter()
+            .map(|source_ip| {
+                let mut instruction = instructions[*source_ip].clone();
+
+                for ip in instruction.pointers_mut() {
+                    *ip = self.get(*ip);
+                }
+                instruction
+            })
+            .collect()
+    }
+
+    pub fn relocate_functions(&self, functions: &mut Functions) {
+        for (start_ip, end_ip) in functions.ranges_mut() {
+            *start_ip = self.get(*start_ip);
+            *end_ip = self.get(*end_ip + 1).saturating_sub(1).max(*start_ip);
+        }
+    }
+
+    // Values indexed by instruction follow their instruction, values of missing instructions are cut off.
+    pub fn relocate_values<T: Clone>(&self, values: &[T]) -> Vec<T> {
+        self.sources
+            .iter()
+            .map_while(|source_ip| values.get(*source_ip).cloned())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OptimizationReport {
+    pub folded: usize,
+    pub resolved_branches: usize,
+    pub removed_functions: Vec<(String, Pointer)>,
+    pub removed_code: Vec<(Pointer, Pointer)>,
+}
+
+impl OptimizationReport {
+    pub fn summary(&self, debug_info: &DebugInfo) -> String {
+        let mut summary = String::new();
+
+        writeln!(summary, "Folded instructions: {}", self.folded).unwrap();
+        writeln!(summary, "Resolved branches: {}", self.resolved_branches).unwrap();
+
+        for (func_name, start_ip) in self.removed_functions.iter() {
+            writeln!(
+                summary,
+                "Removed uncalled function: {} ({})",
+                func_name,
+                OptimizationReport::location(debug_info, *start_ip, *start_ip)
+            ).unwrap();
+        }
+        for (start_ip, end_ip) in self.removed_code.iter() {
+            writeln!(
+                summary,
+                "Removed unreachable code: {}",
+                OptimizationReport::location(debug_info, *start_ip, *end_ip)
+            ).unwrap();
+        }
+
+        summary
+    }
+
+    fn location(debug_info: &DebugInfo, start_ip: Pointer, end_ip: Pointer) -> String {
+        match (debug_info.line_of(start_ip), debug_info.line_of(end_ip)) {
+            (Some(start_line), Some(end_line)) if start_line == end_line => format!("line {}", start_line),
+            (Some(start_line), Some(end_line)) => format!("lines {}-{}", start_line, end_line),
+            _ if start_ip == end_ip => format!("instruction #{}", start_ip),
+            _ => format!("instructions #{}-#{}", start_ip, end_ip),
+        }
+    }
+}
+
+pub struct Optimizer;
+
+impl Optimizer {
+    pub fn peephole(mut instructions: Vec<Instruction>, functions: &mut Functions) -> (Vec<Instruction>, Relocation) {
+        let mut kept = vec![true; instructions.len()];
+
+        loop {
+            let relocation = Relocation::new(kept.clone());
+            let live = (0..instructions.len())
+                .filter(|ip| kept[*ip])
+                .collect::<Vec<_>>();
+
+            // Rewrites may only span instructions nothing jumps into, calls or returns to.
+            let mut boundaries = vec![false; live.len() + 1];
+            let mut function_starts = vec![false; live.len() + 1];
+
+            for ip in live.iter() {
+                for target_ip in instructions[*ip].pointers() {
+                    if let Some(boundary) = boundaries.get_mut(relocation.get(target_ip)) {
+                        *boundary = true;
+                    }
+                }
+            }
+            for (start_ip, end_ip) in functions.iter().map(|(_, range)| *range) {
+                for ip in [start_ip, start_ip + 1, end_ip + 1] {
+                    if let Some(boundary) = boundaries.get_mut(relocation.get(ip)) {
+                        *boundary = true;
+                    }
+                }
+                function_starts[relocation.get(start_ip)] = true;
+            }
+
+            let mut changed = false;
+            let mut pos = 0;
+
+            while pos < live.len() {
+                let ip = live[pos];
+
+                if let Instruction::Jump(target_ip) = instructions[ip] {
+                    if relocation.get(target_ip) == pos + 1 && !function_starts[pos] {
+                        kept[ip] = false;
+                        changed = true;
+                        pos += 1;
+                        continue;
+                    }
+                }
+
+                let rewrite = live
+                    .get(pos + 1)
+                    .filter(|_| !boundaries[pos + 1])
+                    .and_then(|next_ip| {
+                        Optimizer::rewrite(&instructions[ip], &instructions[*next_ip])
+                            .or_else(|| Optimizer::tail_call(&instructions[ip], &instructions[*next_ip], &instructions, functions))
+                    });
+
+                match rewrite {
+                    Some(replacement) => {
+                        for (offset, window_ip) in live[pos..pos + 2].iter().enumerate() {
+                            match replacement.get(offset) {
+                                Some(instruction) => instructions[*window_ip] = instruction.clone(),
+                                None => kept[*window_ip] = false,
+                            }
+                        }
+
+                        changed = true;
+                        pos += 2;
+                    },
+                    None => pos += 1,
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        let relocation = Relocation::new(kept);
+        relocation.relocate_functions(functions);
+
+        (relocation.apply(&instructions), relocation)
+    }
+
+    pub fn fold_constants(
+        mut instructions: Vec<Instruction>,
+        functions: &mut Functions,
+    ) -> (Vec<Instruction>, Relocation, OptimizationReport) {
+        let mut kept = vec![true; instructions.len()];
+        let mut report = OptimizationReport::default();
+
+        loop {
+            let graph = ControlFlowGraph::new(&instructions, functions, &Labels::new());
+            let mut changed = false;
+
+            for block in graph.blocks() {
+                changed |= Optimizer::fold_block(block, &mut instructions, &mut kept, &mut report);
+            }
+            changed |= Optimizer::propagate_locals(&graph, &mut instructions, &kept, &mut report);
+
+            if !changed {
+                break;
+            }
+        }
+
+        let graph = ControlFlowGraph::new(&instructions, functions, &Labels::new());
+        Optimizer::eliminate_dead_code(&graph, &instructions, &mut kept, functions, &mut report);
+
+        let relocation = Relocation::new(kept);
+        relocation.relocate_functions(functions);
+
+        (relocation.apply(&instructions), relocation, report)
+    }
+
+    // Lays out main code first and every function body after it. Each body keeps one slot in front
+    // of it, so calls still target `start_ip + 1`, but nothing falls or jumps into that slot anymore.
+    pub fn lower(instructions: Vec<Instruction>, functions: &mut Functions) -> (Vec<Instruction>, Relocation) {
+        let mut ranges = functions
+            .iter()
+            .map(|(func_name, range)| (func_name.to_string(), *range))
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|(_, (start_ip, _))| *start_ip);
+
+        let mut function_ends = vec![None; instructions.len()];
+        let mut is_main = vec![true; instructions.len()];
+
+        for (_, (start_ip, end_ip)) in ranges.iter() {
+            function_ends[*start_ip] = Some(*end_ip);
+            is_main[*start_ip..=*end_ip].fill(false);
+        }
+
+        let falls_through = |ip: Pointer| instructions[ip].successors(ip).contains(&(ip + 1));
+        let mut slots = vec![];
+
+        for ip in (0..instructions.len()).filter(|ip| is_main[*ip] && instructions[*ip] != Instruction::Ignore) {
+            slots.push(Slot::Instruction(ip));
+        }
+        if !ranges.is_empty() {
+            match slots.last() {
+                Some(Slot::Instruction(ip)) if !falls_through(*ip) => {},
+                Some(Slot::Instruction(ip)) => slots.push(Slot::FallThrough(instructions.len(), *ip)),
+                _ => slots.push(Slot::FallThrough(instructions.len(), 0)),
+            }
+        }
+
+        let mut lowered_ranges = vec![];
+
+        for (func_name, (start_ip, end_ip)) in ranges {
+            let lowered_start_ip = slots.len();
+            slots.push(Slot::Header(start_ip));
+
+            for ip in (start_ip + 1..=end_ip).filter(|ip| instructions[*ip] != Instruction::Ignore) {
+                slots.push(Slot::Instruction(ip));
+            }
+            match slots.last() {
+                Some(Slot::Instruction(ip)) if !falls_through(*ip) => {},
+                Some(Slot::Instruction(ip)) => slots.push(Slot::FallThrough(end_ip + 1, *ip)),
+                _ => slots.push(Slot::FallThrough(end_ip + 1, start_ip)),
+            }
+
+            lowered_ranges.push((func_name, (lowered_start_ip, slots.len() - 1)));
+        }
+
+        let mut positions = vec![None; instructions.len()];
+        for (lowered_ip, slot) in slots.iter().enumerate() {
+            if let Slot::Instruction(ip) = slot {
+                positions[*ip] = Some(lowered_ip);
+            }
+        }
+
+        // Labels continue with the next instruction and FUNC jumps with the code after the function.
+        let resolve = |mut ip: Pointer| loop {
+            match (positions.get(ip), function_ends.get(ip)) {
+                (Some(Some(lowered_ip)), _) => return *lowered_ip,
+                (Some(None), Some(Some(end_ip))) => ip = end_ip + 1,
+                (Some(None), _) => ip += 1,
+                (None, _) => return ip + slots.len() - instructions.len(),
+            }
+        };
+
+        let lowered = slots
+            .iter()
+            .map(|slot| match slot {
+                Slot::Instruction(ip) => {
+                    let mut instruction = instructions[*ip].clone();
+
+                    for target_ip in instruction.pointers_mut() {
+                        *target_ip = resolve(*target_ip);
+                    }
+                    instruction
+                },
+                Slot::Header(_) => Instruction::Return,
+                Slot::FallThrough(target_ip, _) => Instruction::Jump(resolve(*target_ip)),
+            })
+            .collect();
+        let relocation = Relocation {
+            targets: (0..=instructions.len()).map(resolve).collect(),
+            sources: slots
+                .iter()
+                .map(|slot| match slot {
+                    Slot::Instruction(ip) | Slot::Header(ip) | Slot::FallThrough(_, ip) => *ip,
+                })
+                .collect(),
+        };
+
+        *functions = Functions::new();
+        for (func_name, range) in lowered_ranges {
+            functions.insert(&func_name, range).unwrap();
+        }
+
+        (lowered, relocation)
+    }
+
+    pub fn fuse(mut instructions: Vec<Instruction>, functions: &mut Functions) -> (Vec<Instruction>, Relocation) {
+        let mut kept = vec![true; instructions.len()];
+        let mut boundaries = vec![false; instructions.len() + 1];
+
+        for target_ip in instructions.iter().flat_map(|instruction| instruction.pointers()) {
+            if let Some(boundary) = boundaries.get_mut(target_ip) {
+                *boundary = true;
+            }
+        }
+        for (start_ip, end_ip) in functions.iter().map(|(_, range)| *range) {
+            for ip in [start_ip, start_ip + 1, end_ip + 1] {
+                if let Some(boundary) = boundaries.get_mut(ip) {
+                    *boundary = true;
+                }
+            }
+        }
+
+        let mut ip = 0;
+
+        while ip < instructions.len() {
+            let fused = Optimizer::superinstruction(&instructions[ip..])
+                .filter(|(_, len)| !boundaries[ip + 1..ip + len].contains(&true));
+
+            match fused {
+                Some((instruction, len)) => {
+                    instructions[ip] = instruction;
+                    kept[ip + 1..ip + len].fill(false);
+                    ip += len;
+                },
+                None => ip += 1,
+            }
+        }
+
+        let relocation = Relocation::new(kept);
+        relocation.relocate_functions(functions);
+
+        (relocation.apply(&instructions), relocation)
+    }
+
+    fn superinstruction(window: &[Instruction]) -> Option<(Instruction, usize)> {
+        match window {
+            [
+                Instruction::ReadVariable(read_idx),
+                Instruction::LoadValue(val),
+                Instruction::Add,
+                Instruction::WriteVariable(write_idx),
+                ..
+            ] if read_idx == write_idx => Some((Instruction::IncrementVariable(*read_idx, *val), 4)),
+            [
+                Instruction::ReadVariable(read_idx),
+                Instruction::LoadValue(val),
+                Instruction::Sub,
+                Instruction::WriteVariable(write_idx),
+                ..
+            ] if read_idx == write_idx => Some((Instruction::IncrementVariable(*read_idx, val.checked_neg()?), 4)),
+            [Instruction::ReadVariable(var_idx), Instruction::LoadValue(val), branch, ..] => {
+                let (comparison, label_ip) = match branch {
+                    Instruction::JumpIfEqual(label_ip) => (Comparison::Equal, label_ip),
+                    Instruction::JumpIfNotEqual(label_ip) => (Comparison::NotEqual, label_ip),
+                    Instruction::JumpIfGreater(label_ip) => (Comparison::Greater, label_ip),
+                    Instruction::JumpIfSmaller(label_ip) => (Comparison::Smaller, label_ip),
+                    Instruction::JumpIfGreaterEqual(label_ip) => (Comparison::GreaterEqual, label_ip),
+                    Instruction::JumpIfSmallerEqual(label_ip) => (Comparison::SmallerEqual, label_ip),
+                    _ => return None,
+                };
+
+                Some((Instruction::JumpIfVariable(comparison, *var_idx, *val, *label_ip), 3))
+            },
+            _ => None,
+        }
+    }
+
+    fn fold_block(
+        block: &BasicBlock,
+        instructions: &mut [Instruction],
+        kept: &mut [bool],
+        report: &mut OptimizationReport,
+    ) -> bool {
+        let mut stack: Vec<Constant> = vec![];
+        let mut changed = false;
+
+        for ip in block.start_ip..=block.end_ip {
+            if !kept[ip] {
+                continue;
+            }
+
+            let instruction = instructions[ip].clone();
+            let Some((pops, pushes)) = instruction.stack_effect() else {
+                // Whatever the callee returns lands on an operand stack of unknown depth.
+                stack.clear();
+                continue;
+            };
+            let operands = (0..pops)
+                .map(|_| stack.pop().flatten())
+                .collect::<Option<Vec<_>>>()
+                .map(|mut operands| {
+                    operands.reverse();
+                    operands
+                });
+
+            match (&instruction, operands) {
+                (Instruction::LoadValue(val), _) => stack.push(Some((*val, vec![ip]))),
+                (Instruction::Duplicate, Some(operands)) => {
+                    let (val, producers) = operands[0].clone();
+
+                    instructions[ip] = Instruction::LoadValue(val);
+                    stack.push(Some((val, producers)));
+                    stack.push(Some((val, vec![ip])));
+                    changed = true;
+                },
+                (_, Some(operands)) if Optimizer::is_foldable(&instruction) => {
+                    let values = operands.iter().map(|(val, _)| *val).collect::<Vec<_>>();
+
+                    match Optimizer::evaluate(&values, &instruction) {
+                        Some(val) => {
+                            Optimizer::remove_producers(&operands, instructions, kept);
+                            instructions[ip] = Instruction::LoadValue(val);
+                            stack.push(Some((val, vec![ip])));
+                            report.folded += 1;
+                            changed = true;
+                        },
+                        None => stack.push(None),
+                    }
+                },
+                (_, Some(operands)) if instruction.branch_target().is_some() => {
+                    let values = operands.iter().map(|(val, _)| *val).collect::<Vec<_>>();
+
+                    if let Some(is_taken) = Optimizer::is_taken(&values, &instruction) {
+                        Optimizer::remove_producers(&operands, instructions, kept);
+
+                        match is_taken {
+                            true => instructions[ip] = Instruction::Jump(instruction.branch_target().unwrap()),
+                            false => {
+                                instructions[ip] = Instruction::Ignore;
+                                kept[ip] = false;
+                            },
+                        }
+
+                        report.resolved_branches += 1;
+                        changed = true;
+                    }
+                },
+                _ => stack.extend(iter::repeat_n(None, pushes)),
+            }
+        }
+
+        changed
+    }
+
+    // A local written exactly once with a constant holds it wherever the write dominates the read.
+    fn propagate_locals(
+        graph: &ControlFlowGraph,
+        instructions: &mut [Instruction],
+        kept: &[bool],
+        report: &mut OptimizationReport,
+    ) -> bool {
+        let mut changed = false;
+
+        for (function, _) in graph.functions() {
+            let Some(dominators) = graph.dominators(function) else {
+                continue;
+            };
+            let ips = graph.function_blocks(function)
+                .into_iter()
+                .flat_map(|block_id| graph.block(block_id).start_ip..=graph.block(block_id).end_ip)
+                .filter(|ip| kept[*ip])
+                .collect::<Vec<_>>();
+
+            let mut writes: HashMap<usize, Vec<Pointer>> = HashMap::new();
+            for ip in ips.iter() {
+                if let Instruction::WriteVariable(var_idx) | Instruction::IncrementVariable(var_idx, _) = instructions[*ip] {
+                    writes.entry(var_idx).or_default().push(*ip);
+                }
+            }
+
+            for (var_idx, write_ips) in writes {
+                let [write_ip] = write_ips[..] else {
+                    continue;
+                };
+                if !matches!(instructions[write_ip], Instruction::WriteVariable(_)) {
+                    continue;
+                }
+                let write_block = graph.block_of(write_ip).unwrap();
+                let Some(Instruction::LoadValue(val)) = (graph.block(write_block).start_ip..write_ip)
+                    .rev()
+                    .find(|ip| kept[*ip])
+                    .map(|ip| &instructions[ip]) else {
+                    continue;
+                };
+                let val = *val;
+
+                for ip in ips.iter() {
+                    let read_block = graph.block_of(*ip).unwrap();
+                    let is_dominated = match read_block == write_block {
+                        true => *ip > write_ip,
+                        false => dominators.dominates(write_block, read_block),
+                    };
+
+                    if instructions[*ip] == Instruction::ReadVariable(var_idx) && is_dominated {
+                        instructions[*ip] = Instruction::LoadValue(val);
+                        report.folded += 1;
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        changed
+    }
+
+    fn eliminate_dead_code(
+        graph: &ControlFlowGraph,
+        instructions: &[Instruction],
+        kept: &mut [bool],
+        functions: &mut Functions,
+        report: &mut OptimizationReport,
+    ) {
+        let mut reachable = vec![false; graph.blocks().len()];
+        let mut worklist = graph.entry(MAIN_FUNCTION).into_iter().collect::<Vec<_>>();
+
+        while let Some(block_id) = worklist.pop() {
+            if reachable[block_id] {
+                continue;
+            }
+            reachable[block_id] = true;
+
+            let block = graph.block(block_id);
+            worklist.extend(block.successors.iter());
+
+            for ip in block.start_ip..=block.end_ip {
+                if let (true, Instruction::CallFunction(func_ip) | Instruction::TailCall(func_ip)) = (kept[ip], &instructions[ip]) {
+                    worklist.extend(graph.block_of(*func_ip));
+                }
+            }
+        }
+
+        let was_kept = kept.to_vec();
+        let mut dead_code = vec![false; kept.len()];
+
+        for (block_id, block) in graph.blocks().iter().enumerate() {
+            if !reachable[block_id] {
+                kept[block.start_ip..=block.end_ip].fill(false);
+                dead_code[block.start_ip..=block.end_ip].fill(true);
+            }
+        }
+
+        let mut dead_functions = functions
+            .iter()
+            .filter(|(func_name, _)| graph.entry(func_name).is_some_and(|block_id| !reachable[block_id]))
+            .map(|(func_name, (start_ip, end_ip))| (func_name.to_string(), *start_ip, *end_ip))
+            .collect::<Vec<_>>();
+        dead_functions.sort_by_key(|(_, start_ip, _)| *start_ip);
+
+        for (func_name, start_ip, end_ip) in dead_functions {
+            functions.remove(&func_name);
+            kept[start_ip..=end_ip].fill(false);
+            dead_code[start_ip..=end_ip].fill(false);
+            report.removed_functions.push((func_name, start_ip));
+        }
+
+        // Called functions keep their FUNC jump even when the code around it is dead.
+        for (_, (start_ip, _)) in functions.iter() {
+            kept[*start_ip] = was_kept[*start_ip];
+            dead_code[*start_ip] = false;
+        }
+
+        for ip in (0..kept.len()).filter(|ip| dead_code[*ip] && was_kept[*ip]) {
+            match report.removed_code.last_mut() {
+                Some((_, end_ip)) if (*end_ip + 1..ip).all(|ip| !was_kept[ip]) => *end_ip = ip,
+                _ => report.removed_code.push((ip, ip)),
+            }
+        }
+    }
+
+    fn is_foldable(instruction: &Instruction) -> bool {
+        matches!(
+            instruction,
+            Instruction::Add |
+            Instruction::Sub |
+            Instruction::Multiply |
+            Instruction::Divide |
+            Instruction::Modulo |
+            Instruction::Power |
+            Instruction::Min |
+            Instruction::Max |
+            Instruction::ShiftLeft |
+            Instruction::ShiftRight |
+            Instruction::BitAnd |
+            Instruction::BitOr |
+            Instruction::BitXor |
+            Instruction::Equal |
+            Instruction::NotEqual |
+            Instruction::Smaller |
+            Instruction::SmallerEqual |
+            Instruction::Greater |
+            Instruction::GreaterEqual |
+            Instruction::And |
+            Instruction::Or |
+            Instruction::Xor |
+            Instruction::Negate |
+            Instruction::Absolute |
+            Instruction::BitNot |
+            Instruction::Not
+        )
+    }
+
+    // Running the instruction on the VM keeps folded results identical to run time. Operations
+    // that fail, like division by zero, aren't folded and keep failing when the program runs.
+    fn evaluate(values: &[isize], instruction: &Instruction) -> Option<isize> {
+        let mut program = values
+            .iter()
+            .map(|val| Instruction::LoadValue(*val))
+            .collect::<Vec<_>>();
+        program.push(instruction.clone());
+
+        let mut vm = VirtualMachine::default();
+        vm.run(program).ok()?;
+        vm.pop_value().ok()
+    }
+
+    // The jump either skips the HALT placed right after it or stops on it.
+    fn is_taken(values: &[isize], instruction: &Instruction) -> Option<bool> {
+        let mut program = values
+            .iter()
+            .map(|val| Instruction::LoadValue(*val))
+            .collect::<Vec<_>>();
+        let mut jump = instruction.clone();
+
+        for ip in jump.pointers_mut() {
+            *ip = values.len() + 2;
+        }
+        program.extend([jump, Instruction::Halt]);
+
+        match VirtualMachine::default().run(program).ok()? {
+            ExitStatus::Finished => Some(true),
+            ExitStatus::Halted => Some(false),
+            ExitStatus::Exited(_) => None,
+        }
+    }
+
+    fn remove_producers(operands: &[(isize, Vec<Pointer>)], instructions: &mut [Instruction], kept: &mut [bool]) {
+        for ip in operands.iter().flat_map(|(_, producers)| producers.iter()) {
+            instructions[*ip] = Instruction::Ignore;
+            kept[*ip] = false;
+        }
+    }
+
+    // A call right before the function's return can reuse its frame when both return the same way.
+    fn tail_call(
+        first: &Instruction,
+        second: &Instruction,
+        instructions: &[Instruction],
+        functions: &Functions,
+    ) -> Option<Vec<Instruction>> {
+        let Instruction::CallFunction(func_ip) = first else {
+            return None;
+        };
+        let (_, end_ip) = functions
+            .iter()
+            .map(|(_, range)| *range)
+            .find(|(start_ip, _)| start_ip + 1 == *func_ip)?;
+
+        (matches!(second, Instruction::Return | Instruction::ReturnValue) && instructions[end_ip] == *second)
+            .then(|| vec![Instruction::TailCall(*func_ip), second.clone()])
+    }
+
+    fn rewrite(first: &Instruction, second: &Instruction) -> Option<Vec<Instruction>> {
+        match (first, second) {
+            (Instruction::WriteVariable(write_idx), Instruction::ReadVariable(read_idx)) if write_idx == read_idx => {
+                Some(vec![Instruction::Duplicate, Instruction::WriteVariable(*write_idx)])
+            },
+            (
+                Instruction::LoadValue(0),
+                Instruction::Add |
+                Instruction::Sub |
+                Instruction::BitOr |
+                Instruction::BitXor |
+                Instruction::ShiftLeft |
+                Instruction::ShiftRight
+            ) |
+            (Instruction::LoadValue(1), Instruction::Multiply | Instruction::Divide | Instruction::Power) |
+            (Instruction::LoadValue(_) | Instruction::Duplicate, Instruction::Drop) |
+            (Instruction::Swap, Instruction::Swap) => Some(vec![]),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        parser::Parser,
+        verifier::Verifier,
+        vm::{VirtualMachine, ExitStatus},
+        test_utils::{SharedBuffer, parse},
+    };
+
+    fn run(program: Vec<Instruction>) -> (ExitStatus, String) {
+        let buffer = SharedBuffer::default();
+        let mut vm = VirtualMachine::default();
+        vm.set_output(Some(Box::new(buffer.clone())));
+
+        let status = vm.run(program).unwrap();
+
+        (status, String::from_utf8(buffer.0.take()).unwrap())
+    }
+
+    fn assert_same_output(buffer: &str) -> Vec<Instruction> {
+        let (instructions, mut functions) = parse(buffer);
+        let (optimized, _) = Optimizer::peephole(instructions.clone(), &mut functions);
+
+        assert_eq!(run(optimized.clone()), run(instructions));
+
+        optimized
+    }
+
+    #[test]
+    fn relocation() {
+        let relocation = Relocation::new(vec![true, false, false, true]);
+
+        assert_eq!((0..=5).map(|ip| relocation.get(ip)).collect::<Vec<_>>(), vec![0, 1, 1, 1, 2, 3]);
+        assert_eq!(relocation.relocate_values(&[10, 11, 12, 13]), vec![10, 13]);
+        assert_eq!(relocation.source_of(1), Some(3));
+        assert_eq!(
+            relocation.apply(&[Instruction::Jump(2), Instruction::Add, Instruction::Add, Instruction::Jump(4)]),
+            vec![Instruction::Jump(1), Instruction::Jump(2)]
+        );
+    }
+
+    #[test]
+    fn peephole() {
+        let optimized = assert_same_output(
+            "LOAD_VAL 5\nLOAD_VAL 0\nADD\nLOAD_VAL 1\nMULTIPLY\nWRITE_VAR 'x'\nREAD_VAR 'x'\n\
+            LOAD_VAL 7\nDROP\nREAD_VAR 'x'\nSWAP\nSWAP\nPRINT\nPRINT 'x'"
+        );
+
+        assert_eq!(
+            optimized,
+            vec![
+                Instruction::LoadValue(5),
+                Instruction::Duplicate,
+                Instruction::Duplicate,
+                Instruction::WriteVariable(0),
+                Instruction::Print,
+                Instruction::PrintVariable("x".to_string(), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn peephole_should_relocate_pointers() {
+        let (instructions, mut functions) = parse(
+            "FUNC ONE\nLOAD_VAL 1\nLOAD_VAL 0\nADD\nRETURN_VAL\n\
+            LOAD_VAL 0\nWRITE_VAR 'x'\nLABEL LOOP\nREAD_VAR 'x'\nCALL ONE\nADD\nLOAD_VAL 1\nMULTIPLY\nWRITE_VAR 'x'\n\
+            READ_VAR 'x'\nLOAD_VAL 3\nJUMP_IF_SM LOOP\nJUMP END\nLABEL END\nPRINT 'x'"
+        );
+        let (optimized, _) = Optimizer::peephole(instructions.clone(), &mut functions);
+
+        assert_eq!(optimized[0], Instruction::Jump(3));
+        assert_eq!(optimized[7], Instruction::CallFunction(1));
+        assert_eq!(optimized[12], Instruction::JumpIfSmaller(5));
+        assert_eq!(optimized.len(), 15);
+        assert_eq!(functions.get("ONE").unwrap(), &(0, 2));
+        assert_eq!(run(optimized), run(instructions));
+    }
+
+    #[test]
+    fn peephole_should_emit_tail_calls() {
+        let optimized = assert_same_output(
+            "FUNC THREE\nLOAD_VAL 3\nRETURN_VAL\nFUNC FORWARD\nLOAD_VAL 1\nDROP\nCALL THREE\nRETURN_VAL\n\
+            FUNC NOTHING\nRETURN\nFUNC KEEP\nLOAD_VAL 4\nCALL NOTHING\nRETURN_VAL\n\
+            CALL FORWARD\nPRINT\nCALL KEEP\nPRINT"
+        );
+
+        assert_eq!(optimized[4], Instruction::TailCall(1));
+        assert_eq!(optimized[5], Instruction::ReturnValue);
+        assert_eq!(optimized[10], Instruction::CallFunction(7));
+    }
+
+    #[test]
+    fn peephole_should_keep_jump_targets() {
+        let instructions = vec![
+            Instruction::LoadValue(2),      // LOAD_VAL 2
+            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
+            Instruction::LoadValue(10),     // LOAD_VAL 10
+            Instruction::LoadValue(0),      // LOAD_VAL 0
+            Instruction::Add,               // LABEL AGAIN: ADD
+            Instruction::Print,             // PRINT
+            Instruction::ReadVariable(0),   // READ_VAR 'x'
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::Sub,               // SUB
+            Instruction::Duplicate,         // DUP
+            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
+            Instruction::JumpIfZero(14),    // JUMP_IF_ZERO END
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::Jump(4),           // JUMP AGAIN
+        ];
+        let (optimized, _) = Optimizer::peephole(instructions.clone(), &mut Functions::new());
+
+        assert_eq!(optimized, instructions);
+        assert_eq!(run(instructions).1, "10\n11\n");
+    }
+
+    #[test]
+    fn fold_constants() {
+        let (instructions, mut functions) = parse(
+            "LOAD_VAL 10\nWRITE_VAR 'n'\nLOAD_VAL 2\nLOAD_VAL 3\nADD\nREAD_VAR 'n'\nMULTIPLY\nPRINT\n\
+            LOAD_VAL 1\nLOAD_VAL 0\nDIVIDE"
+        );
+        let (optimized, _, report) = Optimizer::fold_constants(instructions, &mut functions);
+
+        assert_eq!(
+            optimized,
+            vec![
+                Instruction::LoadValue(10),
+                Instruction::WriteVariable(0),
+                Instruction::LoadValue(50),
+                Instruction::Print,
+                Instruction::LoadValue(1),
+                Instruction::LoadValue(0),
+                Instruction::Divide,
+            ]
+        );
+        assert_eq!(report.folded, 3);
+    }
+
+    #[test]
+    fn fold_constants_should_remove_dead_code() {
+        let buffer = "\
+            LOAD_VAL 1\nLOAD_VAL 2\nJUMP_IF_SM SMALLER\nLOAD_VAL 100\nPRINT\n\
+            LABEL SMALLER\nLOAD_VAL 200\nPRINT\n\
+            FUNC UNUSED\nLOAD_VAL 1\nRETURN_VAL";
+        let (instructions, mut functions) = parse(buffer);
+        let debug_info = DebugInfo::new(functions.clone(), Parser::parse_lines(buffer));
+        let (optimized, relocation, report) = Optimizer::fold_constants(instructions, &mut functions);
+
+        assert_eq!(
+            optimized,
+            vec![Instruction::Jump(1), Instruction::Ignore, Instruction::LoadValue(200), Instruction::Print]
+        );
+        assert_eq!(relocation.get(5), 1);
+        assert!(functions.get("UNUSED").is_err());
+        assert_eq!(
+            report.summary(&debug_info),
+            "Folded instructions: 0\n\
+            Resolved branches: 1\n\
+            Removed uncalled function: UNUSED (line 9)\n\
+            Removed unreachable code: lines 4-5\n"
+        );
+    }
+
+    #[test]
+    fn fold_constants_should_keep_output_of_examples() {
+        for buffer in [
+            include_str!("../examples/arithmetic.bytecode"),
+            include_str!("../examples/function.bytecode"),
+            include_str!("../examples/loop.bytecode"),
+            include_str!("../examples/switch.bytecode"),
+            include_str!("../examples/variables.bytecode"),
+        ] {
+            let (instructions, mut functions) = parse(buffer);
+            let (optimized, _, _) = Optimizer::fold_constants(instructions.clone(), &mut functions);
+            let (optimized, _) = Optimizer::peephole(optimized, &mut functions);
+
+            assert_eq!(run(optimized), run(instructions));
+        }
+    }
+
+    #[test]
+    fn peephole_should_keep_output_of_examples() {
+        for buffer in [
+            include_str!("../examples/arithmetic.bytecode"),
+            include_str!("../examples/function.bytecode"),
+            include_str!("../examples/loop.bytecode"),
+            include_str!("../examples/switch.bytecode"),
+            include_str!("../examples/variables.bytecode"),
+        ] {
+            assert_same_output(buffer);
+        }
+    }
+
+    #[test]
+    fn lower() {
+        let (instructions, mut functions) = parse(
+            "FUNC SEVEN\nLOAD_VAL 7\nRETURN_VAL\nLABEL START\nCALL SEVEN\nLOAD_VAL 2\nMULTIPLY\nPRINT"
+        );
+        let (lowered, relocation) = Optimizer::lower(instructions, &mut functions);
+
+        assert_eq!(
+            lowered,
+            vec![
+                Instruction::CallFunction(6),
+                Instruction::LoadValue(2),
+                Instruction::Multiply,
+                Instruction::Print,
+                Instruction::Jump(8),
+                Instruction::Return,
+                Instruction::LoadValue(7),
+                Instruction::ReturnValue,
+            ]
+        );
+        assert_eq!(functions.get("SEVEN").unwrap(), &(5, 7));
+        assert_eq!(relocation.get(0), 0);
+        assert_eq!(relocation.get(3), 0);
+        assert_eq!(relocation.source_of(5), Some(0));
+        assert_eq!(relocation.source_of(6), Some(1));
+        assert_eq!(run(lowered).1, "14\n");
+    }
+
+    #[test]
+    fn lower_should_retarget_jumps_over_functions() {
+        let (instructions, mut functions) = parse(
+            "LOAD_VAL 1\nLOAD_VAL 1\nJUMP_IF_TRUE SKIP\nFUNC EMPTY\nRETURN\nLABEL SKIP\nPRINT\nHALT"
+        );
+        let (lowered, _) = Optimizer::lower(instructions.clone(), &mut functions);
+
+        assert_eq!(
+            lowered,
+            vec![
+                Instruction::LoadValue(1),
+                Instruction::LoadValue(1),
+                Instruction::JumpIfTrue(3),
+                Instruction::Print,
+                Instruction::Halt,
+                Instruction::Return,
+                Instruction::Return,
+            ]
+        );
+        assert_eq!(functions.get("EMPTY").unwrap(), &(5, 6));
+        assert_eq!(run(lowered), run(instructions));
+    }
+
+    #[test]
+    fn lower_should_keep_output_of_examples() {
+        for buffer in [
+            include_str!("../examples/arithmetic.bytecode"),
+            include_str!("../examples/function.bytecode"),
+            include_str!("../examples/loop.bytecode"),
+            include_str!("../examples/switch.bytecode"),
+            include_str!("../examples/variables.bytecode"),
+        ] {
+            let (instructions, mut functions) = parse(buffer);
+            let (lowered, _) = Optimizer::lower(instructions.clone(), &mut functions);
+
+            assert!(!lowered.contains(&Instruction::Ignore));
+            assert_eq!(
+                Verifier::verify(lowered.clone(), &functions).is_ok(),
+                Verifier::verify(instructions.clone(), &parse(buffer).1).is_ok()
+            );
+            assert_eq!(run(lowered), run(instructions));
+        }
+    }
+
+    #[test]
+    fn fuse() {
+        let (instructions, mut functions) = parse(
+            "LOAD_VAL 0\nWRITE_VAR 'x'\nLABEL LOOP\nREAD_VAR 'x'\nLOAD_VAL 3\nSUB\nWRITE_VAR 'x'\n\
+            READ_VAR 'x'\nLOAD_VAL -9\nJUMP_IF_GR LOOP\nPRINT 'x'"
+        );
+        let (fused, relocation) = Optimizer::fuse(instructions.clone(), &mut functions);
+
+        assert_eq!(
+            fused,
+            vec![
+                Instruction::LoadValue(0),
+                Instruction::WriteVariable(0),
+                Instruction::Ignore,
+                Instruction::IncrementVariable(0, -3),
+                Instruction::JumpIfVariable(Comparison::Greater, 0, -9, 2),
+                Instruction::PrintVariable("x".to_string(), 0),
+            ]
+        );
+        assert_eq!(relocation.get(7), 4);
+        assert_eq!(run(fused), run(instructions));
+    }
+
+    #[test]
+    fn fuse_should_keep_jump_targets() {
+        let (instructions, mut functions) = parse(
+            "LOAD_VAL 1\nWRITE_VAR 'x'\nREAD_VAR 'x'\nLABEL INNER\nLOAD_VAL 1\nADD\nWRITE_VAR 'x'\n\
+            READ_VAR 'x'\nDUP\nLOAD_VAL 5\nJUMP_IF_SM INNER\nPRINT 'x'"
+        );
+        let (fused, _) = Optimizer::fuse(instructions.clone(), &mut functions);
+
+        assert!(!fused.contains(&Instruction::IncrementVariable(0, 1)));
+        assert_eq!(run(fused), run(instructions));
+    }
+
+    #[test]
+    fn fuse_should_keep_output_of_examples() {
+        for buffer in [
+            include_str!("../examples/arithmetic.bytecode"),
+            include_str!("../examples/function.bytecode"),
+            include_str!("../examples/loop.bytecode"),
+            include_str!("../examples/switch.bytecode"),
+            include_str!("../examples/variables.bytecode"),
+        ] {
+            let (instructions, mut functions) = parse(buffer);
+            let (fused, _) = Optimizer::fuse(instructions.clone(), &mut functions);
+
+            assert_eq!(run(fused), run(instructions));
+        }
+    }
+}
@@ -1,20 +1,28 @@
-use crate::{instruction::Instruction, vm::Pointer, labels::Labels, variables::Variables, functions::Functions, stack::Stack, errors::ParseError};
+use crate::{instruction::Instruction, vm::Pointer, labels::Labels, variables::Variables, functions::{Functions, MAIN_FUNCTION}, stack::Stack, object::Object, errors::ParseError};
 
 pub type Bytecode<'buf> = Vec<Vec<&'buf str>>;
-pub type Label = (String, Pointer);
+pub type Label = (String, String, Pointer);
 pub type Variable = String;
 pub type Function = String;
 
 pub struct Parser;
 
 impl<'buf> Parser {
-    pub fn parse_code(buffer: &'buf String) -> Bytecode<'buf> {
+    pub fn parse_code(buffer: &'buf str) -> Bytecode<'buf> {
         buffer.split("\n")
             .map(|line| line.trim().split(" ").filter(|token| !token.is_empty()).collect::<Vec<_>>())
             .filter(|line_vec| !line_vec.is_empty())
             .collect::<Vec<_>>()
     }
 
+    pub fn parse_lines(buffer: &'buf str) -> Vec<usize> {
+        buffer.split("\n")
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(line_idx, _)| line_idx + 1)
+            .collect::<Vec<_>>()
+    }
+
     pub fn parse_functions(bytecode: &'buf Bytecode) -> Result<Functions, ParseError> {
         let mut functions = Functions::new();
         let mut ip = 0;
@@ -47,7 +55,7 @@ impl<'buf> Parser {
     pub fn parse_variables(bytecode: &'buf Bytecode) -> Result<Variables<'buf>, ParseError> {
         let mut variables = Variables::new();
         let mut function_names = Stack::new();
-        function_names.push("MAIN");
+        function_names.push(MAIN_FUNCTION);
 
         for (i, line) in bytecode.iter().enumerate() {
             match line.as_slice() {
@@ -62,6 +70,9 @@ impl<'buf> Parser {
                     )
                 },
                 ["FUNC", func_name] => function_names.push(func_name),
+                ["TAIL_CALL", _] if function_names.len() == 1 => {
+                    return Err(ParseError::TailCallOutsideFunction(i.to_string()))
+                },
                 ["RETURN"] | ["RETURN_VAL"] => {
                     if function_names.len() == 1 {
                         return Err(ParseError::ReturnOutsideFunction(i.to_string()))
@@ -78,11 +89,17 @@ impl<'buf> Parser {
         Ok(variables)
     }
 
-    pub fn parse_labels(bytecode: &'buf Bytecode) -> Labels {
-        bytecode.iter()
+    pub fn parse_labels(bytecode: &'buf Bytecode, functions: &Functions) -> Result<Labels, ParseError> {
+        let mut labels = Labels::new();
+        let found_labels = bytecode.iter()
             .enumerate()
-            .filter_map(|(ip, line)| Parser::find_label(line, ip))
-            .collect::<Labels>()
+            .filter_map(|(ip, line)| Parser::find_label(line, ip, functions.scope_of(ip)));
+
+        for (scope, label_name, ip) in found_labels {
+            labels.insert(&scope, &label_name, ip)?;
+        }
+
+        Ok(labels)
     }
 
     pub fn parse_instructions(
@@ -92,16 +109,45 @@ impl<'buf> Parser {
         labels: &Labels,
     ) -> Result<Vec<Instruction>, ParseError> {
         bytecode.iter()
+            .enumerate()
             .map(
-                |line|
-                Instruction::from(line.as_slice(), functions, variables, labels)
+                |(ip, line)|
+                Instruction::from(line.as_slice(), functions.scope_of(ip), functions, variables, labels)
             )
             .collect::<Result<Vec<_>, ParseError>>()
     }
 
-    fn find_label(line: &'buf [&str], ip: Pointer) -> Option<Label> {
+    pub fn parse_object(
+        bytecode: &Bytecode,
+        functions: &Functions,
+        variables: &mut Variables,
+        labels: &Labels,
+    ) -> Result<Object, ParseError> {
+        let mut imports = Vec::new();
+        let mut instructions = Vec::with_capacity(bytecode.len());
+
+        for (ip, line) in bytecode.iter().enumerate() {
+            match line.as_slice() {
+                ["CALL", func_name] if functions.get(func_name).is_err() => {
+                    imports.push((ip, func_name.to_string()));
+                    instructions.push(Instruction::CallFunction(0));
+                },
+                ["TAIL_CALL", func_name] if functions.get(func_name).is_err() => {
+                    imports.push((ip, func_name.to_string()));
+                    instructions.push(Instruction::TailCall(0));
+                },
+                line => instructions.push(
+                    Instruction::from(line, functions.scope_of(ip), functions, variables, labels)?
+                ),
+            }
+        }
+
+        Ok(Object::new(instructions, functions.clone(), imports, variables.locals_count(MAIN_FUNCTION)))
+    }
+
+    fn find_label(line: &'buf [&str], ip: Pointer, scope: &str) -> Option<Label> {
         match line {
-            ["LABEL", label_name] => Some((label_name.to_string(), ip)),
+            ["LABEL", label_name] => Some((scope.to_string(), label_name.to_string(), ip)),
             _ => None,
         }
     }
@@ -126,6 +172,14 @@ mod test {
         
     }
 
+    #[test]
+    fn parse_lines() {
+        let buffer = "LOAD_VAL 5\n\n   \nWRITE_VAR 'x'\n\nREAD_VAR 'x'\n";
+
+        assert_eq!(Parser::parse_lines(buffer), vec![1, 4, 6]);
+        assert_eq!(Parser::parse_lines(buffer).len(), Parser::parse_code(buffer).len());
+    }
+
     #[test]
     fn parse_functions() {
         let bytecode = vec![
@@ -222,6 +276,19 @@ mod test {
         assert!(variables.is_err());
     }
 
+    #[test]
+    fn parse_variables_should_return_error_for_tail_call_outside_function() {
+        let bytecode = vec![
+            vec!["FUNC", "TEST"],
+            vec!["RETURN"],
+            vec!["TAIL_CALL", "TEST"],
+        ];
+
+        let variables = Parser::parse_variables(&bytecode);
+
+        assert!(matches!(variables, Err(ParseError::TailCallOutsideFunction(line)) if line == "2"));
+    }
+
     #[test]
     fn parse_labels() {
         let bytecode = vec![
@@ -237,23 +304,47 @@ mod test {
             vec!["JUMP_IF_SM", "LOOP"],
         ];
 
-        let actual_labels = Parser::parse_labels(&bytecode);
+        let functions = Parser::parse_functions(&bytecode).unwrap();
+        let actual_labels = Parser::parse_labels(&bytecode, &functions).unwrap();
 
         let mut expected_labels = Labels::new();
-        expected_labels.insert("LOOP", 2).unwrap();
+        expected_labels.insert(MAIN_FUNCTION, "LOOP", 2).unwrap();
 
         assert_eq!(actual_labels, expected_labels);
     }
 
     #[test]
-    #[should_panic(expected = "Label 'LOOP' duplicate found during parsing.")]
-    fn parse_labels_should_panic_for_duplicated_labels() {
+    fn parse_labels_should_return_error_for_duplicated_labels() {
         let bytecode = vec![
             vec!["LABEL", "LOOP"],
             vec!["LABEL", "LOOP"],
         ];
 
-        Parser::parse_labels(&bytecode);
+        let functions = Parser::parse_functions(&bytecode).unwrap();
+        let labels = Parser::parse_labels(&bytecode, &functions);
+
+        assert!(labels.is_err());
+    }
+
+    #[test]
+    fn parse_labels_should_scope_labels_to_functions() {
+        let bytecode = vec![
+            vec!["LABEL", "LOOP"],
+            vec!["FUNC", "TEST"],
+            vec!["LABEL", "LOOP"],
+            vec!["LABEL", "@END"],
+            vec!["RETURN"],
+        ];
+
+        let functions = Parser::parse_functions(&bytecode).unwrap();
+        let actual_labels = Parser::parse_labels(&bytecode, &functions).unwrap();
+
+        let mut expected_labels = Labels::new();
+        expected_labels.insert(MAIN_FUNCTION, "LOOP", 0).unwrap();
+        expected_labels.insert("TEST", "LOOP", 2).unwrap();
+        expected_labels.insert("TEST", "@END", 3).unwrap();
+
+        assert_eq!(actual_labels, expected_labels);
     }
 
     #[test]
@@ -273,7 +364,7 @@ mod test {
 
         let functions = Parser::parse_functions(&bytecode).unwrap();
         let mut variables = Parser::parse_variables(&bytecode).unwrap();
-        let labels = Parser::parse_labels(&bytecode);
+        let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
 
         let actual_instructions = Parser::parse_instructions(
             &bytecode,
@@ -298,6 +389,166 @@ mod test {
         assert_eq!(actual_instructions, expected_instructions);
     }
 
+    #[test]
+    fn parse_object() {
+        let bytecode = vec![
+            vec!["LOAD_VAL", "5"],
+            vec!["WRITE_VAR", "'x'"],
+            vec!["CALL", "EXTERNAL"],
+            vec!["CALL", "LOCAL"],
+            vec!["FUNC", "LOCAL"],
+            vec!["LOAD_VAL", "1"],
+            vec!["RETURN_VAL"],
+        ];
+
+        let functions = Parser::parse_functions(&bytecode).unwrap();
+        let mut variables = Parser::parse_variables(&bytecode).unwrap();
+        let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
+
+        let object = Parser::parse_object(
+            &bytecode,
+            &functions,
+            &mut variables,
+            &labels
+        ).unwrap();
+
+        let expected_instructions = vec![
+            Instruction::LoadValue(5),
+            Instruction::WriteVariable(0),
+            Instruction::CallFunction(0),
+            Instruction::CallFunction(5),
+            Instruction::Jump(7),
+            Instruction::LoadValue(1),
+            Instruction::ReturnValue,
+        ];
+
+        assert_eq!(object.instructions, expected_instructions);
+        assert_eq!(object.exports, functions);
+        assert_eq!(object.imports, vec![(2, "EXTERNAL".to_string())]);
+        assert_eq!(object.main_locals, 1);
+    }
+
+    #[test]
+    fn parse_instructions_should_resolve_labels_in_enclosing_function() {
+        let bytecode = vec![
+            vec!["LABEL", "LOOP"],
+            vec!["LOAD_VAL", "1"],
+            vec!["LOAD_VAL", "2"],
+            vec!["JUMP_IF_SM", "LOOP"],
+            vec!["FUNC", "TEST"],
+            vec!["LABEL", "LOOP"],
+            vec!["LOAD_VAL", "1"],
+            vec!["LOAD_VAL", "2"],
+            vec!["JUMP_IF_SM", "LOOP"],
+            vec!["RETURN"],
+        ];
+
+        let functions = Parser::parse_functions(&bytecode).unwrap();
+        let mut variables = Parser::parse_variables(&bytecode).unwrap();
+        let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
+
+        let instructions = Parser::parse_instructions(
+            &bytecode,
+            &functions,
+            &mut variables,
+            &labels
+        ).unwrap();
+
+        assert_eq!(instructions[3], Instruction::JumpIfSmaller(0));
+        assert_eq!(instructions[8], Instruction::JumpIfSmaller(5));
+    }
+
+    #[test]
+    fn parse_instructions_with_unconditional_and_computed_jumps() {
+        let bytecode = vec![
+            vec!["LOAD_VAL", "1"],
+            vec!["JUMP_TABLE", "ZERO", "ONE"],
+            vec!["JUMP", "END"],
+            vec!["LABEL", "ZERO"],
+            vec!["LOAD_VAL", "0"],
+            vec!["JUMP_IF_ZERO", "END"],
+            vec!["LABEL", "ONE"],
+            vec!["LOAD_VAL", "1"],
+            vec!["JUMP_IF_TRUE", "END"],
+            vec!["LABEL", "END"],
+        ];
+
+        let functions = Parser::parse_functions(&bytecode).unwrap();
+        let mut variables = Parser::parse_variables(&bytecode).unwrap();
+        let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
+
+        let actual_instructions = Parser::parse_instructions(
+            &bytecode,
+            &functions,
+            &mut variables,
+            &labels
+        ).unwrap();
+
+        let expected_instructions = vec![
+            Instruction::LoadValue(1),
+            Instruction::JumpTable(vec![3, 6]),
+            Instruction::Jump(9),
+            Instruction::Ignore,
+            Instruction::LoadValue(0),
+            Instruction::JumpIfZero(9),
+            Instruction::Ignore,
+            Instruction::LoadValue(1),
+            Instruction::JumpIfTrue(9),
+            Instruction::Ignore,
+        ];
+
+        assert_eq!(actual_instructions, expected_instructions);
+    }
+
+    #[test]
+    fn parse_instructions_should_return_error_for_jump_across_functions() {
+        let bytecode = vec![
+            vec!["LOAD_VAL", "1"],
+            vec!["LOAD_VAL", "2"],
+            vec!["JUMP_IF_SM", "@INSIDE"],
+            vec!["FUNC", "TEST"],
+            vec!["LABEL", "@INSIDE"],
+            vec!["RETURN"],
+        ];
+
+        let functions = Parser::parse_functions(&bytecode).unwrap();
+        let mut variables = Parser::parse_variables(&bytecode).unwrap();
+        let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
+
+        let instructions = Parser::parse_instructions(
+            &bytecode,
+            &functions,
+            &mut variables,
+            &labels
+        );
+
+        assert!(matches!(instructions, Err(ParseError::JumpOutsideFunction(_))));
+    }
+
+    #[test]
+    fn parse_instructions_should_return_error_for_unconditional_jump_across_functions() {
+        let bytecode = vec![
+            vec!["FUNC", "TEST"],
+            vec!["JUMP", "@END"],
+            vec!["RETURN"],
+            vec!["CALL", "TEST"],
+            vec!["LABEL", "@END"],
+        ];
+
+        let functions = Parser::parse_functions(&bytecode).unwrap();
+        let mut variables = Parser::parse_variables(&bytecode).unwrap();
+        let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
+
+        let instructions = Parser::parse_instructions(
+            &bytecode,
+            &functions,
+            &mut variables,
+            &labels
+        );
+
+        assert!(matches!(instructions, Err(ParseError::JumpOutsideFunction(_))));
+    }
+
     #[test]
     fn parse_instructions_should_return_error_for_non_existent_instruction() {
         let bytecode = vec![
@@ -306,7 +557,7 @@ mod test {
 
         let functions = Parser::parse_functions(&bytecode).unwrap();
         let mut variables = Parser::parse_variables(&bytecode).unwrap();
-        let labels = Parser::parse_labels(&bytecode);
+        let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
 
         let instructions = Parser::parse_instructions(
             &bytecode,
@@ -317,5 +568,48 @@ mod test {
 
         assert!(instructions.is_err());
     }
+    #[test]
+    fn parse_instructions_should_return_error_for_exit_code_out_of_range() {
+        for code in ["-1", "256"] {
+            let bytecode = vec![
+                vec!["EXIT", code],
+            ];
+
+            let functions = Parser::parse_functions(&bytecode).unwrap();
+            let mut variables = Parser::parse_variables(&bytecode).unwrap();
+            let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
+
+            let instructions = Parser::parse_instructions(
+                &bytecode,
+                &functions,
+                &mut variables,
+                &labels
+            );
+
+            assert!(matches!(instructions, Err(ParseError::InvalidInstruction(_))));
+        }
+    }
+
+    #[test]
+    fn parse_instructions_should_return_error_for_reserved_exit_code() {
+        for code in ["64", "65", "70", "74"] {
+            let bytecode = vec![
+                vec!["EXIT", code],
+            ];
+
+            let functions = Parser::parse_functions(&bytecode).unwrap();
+            let mut variables = Parser::parse_variables(&bytecode).unwrap();
+            let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
+
+            let instructions = Parser::parse_instructions(
+                &bytecode,
+                &functions,
+                &mut variables,
+                &labels
+            );
+
+            assert!(matches!(instructions, Err(ParseError::InvalidInstruction(_))));
+        }
+    }
 }
 
@@ -0,0 +1,243 @@
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{vm::Pointer, instruction::Instruction, functions::MAIN_FUNCTION};
+
+const HOT_ADDRESSES_COUNT: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FunctionProfile {
+    pub calls: usize,
+    pub inclusive: usize,
+    pub exclusive: usize,
+    pub max_depth: usize,
+    active: usize,
+    entered_at: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    executed: usize,
+    addresses: Vec<usize>,
+    instructions: HashMap<&'static str, usize>,
+    functions: HashMap<String, FunctionProfile>,
+    folded: HashMap<String, usize>,
+    stack: Vec<(String, usize)>,
+    path: String,
+    max_call_depth: usize,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(&mut self) {
+        while !self.stack.is_empty() {
+            self.exit();
+        }
+
+        self.enter(MAIN_FUNCTION);
+    }
+
+    pub fn enter(&mut self, function: &str) {
+        let path_len = self.path.len();
+
+        if !self.path.is_empty() {
+            self.path.push(';');
+        }
+        self.path.push_str(function);
+        self.stack.push((function.to_string(), path_len));
+        self.max_call_depth = self.max_call_depth.max(self.stack.len() - 1);
+
+        let profile = self.functions
+            .entry(function.to_string())
+            .or_default();
+
+        if profile.active == 0 {
+            profile.entered_at = self.executed;
+        }
+        profile.calls += 1;
+        profile.active += 1;
+        profile.max_depth = profile.max_depth.max(profile.active);
+    }
+
+    pub fn exit(&mut self) {
+        if let Some((function, path_len)) = self.stack.pop() {
+            self.path.truncate(path_len);
+
+            let profile = self.functions.get_mut(&function).unwrap();
+            profile.active -= 1;
+
+            if profile.active == 0 {
+                profile.inclusive += self.executed - profile.entered_at;
+            }
+        }
+    }
+
+    pub fn record(&mut self, ip: Pointer, instruction: &Instruction) {
+        self.executed += 1;
+
+        if ip >= self.addresses.len() {
+            self.addresses.resize(ip + 1, 0);
+        }
+        self.addresses[ip] += 1;
+
+        *self.instructions.entry(instruction.mnemonic()).or_default() += 1;
+
+        if let Some((function, _)) = self.stack.last() {
+            self.functions.get_mut(function).unwrap().exclusive += 1;
+        }
+
+        match self.folded.get_mut(&self.path) {
+            Some(count) => *count += 1,
+            None => {
+                self.folded.insert(self.path.clone(), 1);
+            },
+        }
+    }
+
+    pub fn executed(&self) -> usize {
+        self.executed
+    }
+
+    pub fn max_call_depth(&self) -> usize {
+        self.max_call_depth
+    }
+
+    pub fn address_count(&self, ip: Pointer) -> usize {
+        self.addresses.get(ip).copied().unwrap_or(0)
+    }
+
+    pub fn instruction_count(&self, mnemonic: &str) -> usize {
+        self.instructions.get(mnemonic).copied().unwrap_or(0)
+    }
+
+    pub fn function(&self, function: &str) -> Option<FunctionProfile> {
+        self.functions
+            .get(function)
+            .map(|profile| self.close(profile))
+    }
+
+    pub fn functions(&self) -> Vec<(&str, FunctionProfile)> {
+        let mut functions = self.functions
+            .iter()
+            .map(|(function, profile)| (function.as_str(), self.close(profile)))
+            .collect::<Vec<_>>();
+
+        functions.sort_by(|(lhs_name, lhs), (rhs_name, rhs)| {
+            rhs.exclusive
+                .cmp(&lhs.exclusive)
+                .then(lhs_name.cmp(rhs_name))
+        });
+
+        functions
+    }
+
+    pub fn report(&self, program: &[Instruction]) -> String {
+        let mut report = String::new();
+
+        writeln!(report, "Executed instructions: {}", self.executed).unwrap();
+        writeln!(report, "Max call depth: {}", self.max_call_depth).unwrap();
+
+        writeln!(report, "\n{:<24} {:>8} {:>12} {:>12} {:>10}", "Function", "Calls", "Inclusive", "Exclusive", "Max depth").unwrap();
+        for (function, profile) in self.functions() {
+            writeln!(
+                report,
+                "{:<24} {:>8} {:>12} {:>12} {:>10}",
+                function, profile.calls, profile.inclusive, profile.exclusive, profile.max_depth
+            ).unwrap();
+        }
+
+        let mut instructions = self.instructions.iter().collect::<Vec<_>>();
+        instructions.sort_by(|(lhs_name, lhs), (rhs_name, rhs)| rhs.cmp(lhs).then(lhs_name.cmp(rhs_name)));
+
+        writeln!(report, "\n{:<24} {:>8}", "Instruction", "Count").unwrap();
+        for (mnemonic, count) in instructions {
+            writeln!(report, "{:<24} {:>8}", mnemonic, count).unwrap();
+        }
+
+        let mut addresses = self.addresses
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .collect::<Vec<_>>();
+        addresses.sort_by(|(lhs_ip, lhs), (rhs_ip, rhs)| rhs.cmp(lhs).then(lhs_ip.cmp(rhs_ip)));
+
+        writeln!(report, "\n{:>6}  {:<24} {:>8}", "Ip", "Hot instruction", "Count").unwrap();
+        for (ip, count) in addresses.into_iter().take(HOT_ADDRESSES_COUNT) {
+            let instruction = program
+                .get(ip)
+                .map_or(String::new(), |instruction| instruction.to_string());
+
+            writeln!(report, "{:>6}  {:<24} {:>8}", ip, instruction, count).unwrap();
+        }
+
+        report
+    }
+
+    pub fn folded(&self) -> String {
+        let mut folded = self.folded.iter().collect::<Vec<_>>();
+        folded.sort();
+
+        folded
+            .into_iter()
+            .map(|(path, count)| format!("{} {}\n", path, count))
+            .collect()
+    }
+
+    fn close(&self, profile: &FunctionProfile) -> FunctionProfile {
+        let mut profile = profile.clone();
+
+        if profile.active > 0 {
+            profile.inclusive += self.executed - profile.entered_at;
+        }
+
+        profile
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let mut profiler = Profiler::new();
+        profiler.start();
+
+        profiler.record(0, &Instruction::LoadValue(1));
+        profiler.record(1, &Instruction::CallFunction(3));
+        profiler.enter("FACT");
+        profiler.record(3, &Instruction::CallFunction(3));
+        profiler.enter("FACT");
+        profiler.record(4, &Instruction::Return);
+        profiler.exit();
+        profiler.record(4, &Instruction::Return);
+        profiler.exit();
+        profiler.record(2, &Instruction::Print);
+
+        let fact = profiler.function("FACT").unwrap();
+        let main = profiler.function(MAIN_FUNCTION).unwrap();
+
+        assert_eq!(profiler.executed(), 6);
+        assert_eq!(profiler.max_call_depth(), 2);
+        assert_eq!(profiler.address_count(4), 2);
+        assert_eq!(profiler.instruction_count("RETURN"), 2);
+        assert_eq!((fact.calls, fact.inclusive, fact.exclusive, fact.max_depth), (2, 3, 3, 2));
+        assert_eq!((main.calls, main.inclusive, main.exclusive, main.max_depth), (1, 6, 3, 1));
+    }
+
+    #[test]
+    fn folded() {
+        let mut profiler = Profiler::new();
+        profiler.start();
+
+        profiler.record(0, &Instruction::CallFunction(2));
+        profiler.enter("TEST");
+        profiler.record(2, &Instruction::LoadValue(1));
+        profiler.record(3, &Instruction::ReturnValue);
+        profiler.exit();
+
+        assert_eq!(profiler.folded(), "MAIN 1\nMAIN;TEST 2\n");
+    }
+}
@@ -0,0 +1,645 @@
+use std::{collections::HashMap, io::Write, iter, mem::size_of, time::Instant};
+
+use crate::{
+    vm::{VirtualMachine, Pointer, ExitStatus, Sink, VALUE_SIZE},
+    instruction::{Instruction, Comparison},
+    functions::{Functions, MAIN_FUNCTION},
+    verifier::VerifiedProgram,
+    backtrace::{Backtrace, BacktraceFrame, DebugInfo},
+    limits::Limits,
+    errors::{RuntimeError, Trap},
+};
+
+pub type Register = usize;
+
+const FRAME_SIZE: usize = size_of::<RegisterFrame>();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Absolute,
+    BitNot,
+    Not,
+}
+
+impl UnaryOp {
+    pub fn apply(&self, val: isize) -> Result<isize, RuntimeError> {
+        match self {
+            UnaryOp::Negate => val.checked_neg().ok_or(RuntimeError::ArithmeticOverflow),
+            UnaryOp::Absolute => val.checked_abs().ok_or(RuntimeError::ArithmeticOverflow),
+            UnaryOp::BitNot => Ok(!val),
+            UnaryOp::Not => Ok((val == 0) as isize),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+    Min,
+    Max,
+    ShiftLeft,
+    ShiftRight,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Compare(Comparison),
+    And,
+    Or,
+    Xor,
+}
+
+impl BinaryOp {
+    pub fn apply(&self, lhs: isize, rhs: isize) -> Result<isize, RuntimeError> {
+        match self {
+            BinaryOp::Add => lhs.checked_add(rhs).ok_or(RuntimeError::ArithmeticOverflow),
+            BinaryOp::Sub => lhs.checked_sub(rhs).ok_or(RuntimeError::ArithmeticOverflow),
+            BinaryOp::Multiply => lhs.checked_mul(rhs).ok_or(RuntimeError::ArithmeticOverflow),
+            BinaryOp::Divide | BinaryOp::Modulo if rhs == 0 => Err(RuntimeError::DivisionByZero),
+            BinaryOp::Divide => lhs.checked_div(rhs).ok_or(RuntimeError::ArithmeticOverflow),
+            BinaryOp::Modulo => lhs.checked_rem(rhs).ok_or(RuntimeError::ArithmeticOverflow),
+            BinaryOp::Power if rhs < 0 => Err(RuntimeError::NegativeExponent(rhs)),
+            BinaryOp::Power => match (lhs, u32::try_from(rhs)) {
+                (_, Ok(exp)) => lhs.checked_pow(exp).ok_or(RuntimeError::ArithmeticOverflow),
+                (0 | 1, Err(_)) => Ok(lhs),
+                (-1, Err(_)) => Ok(if rhs % 2 == 0 { 1 } else { -1 }),
+                (_, Err(_)) => Err(RuntimeError::ArithmeticOverflow),
+            },
+            BinaryOp::Min => Ok(lhs.min(rhs)),
+            BinaryOp::Max => Ok(lhs.max(rhs)),
+            BinaryOp::ShiftLeft => Ok(lhs << VirtualMachine::shift_count(rhs)?),
+            BinaryOp::ShiftRight => Ok(lhs >> VirtualMachine::shift_count(rhs)?),
+            BinaryOp::BitAnd => Ok(lhs & rhs),
+            BinaryOp::BitOr => Ok(lhs | rhs),
+            BinaryOp::BitXor => Ok(lhs ^ rhs),
+            BinaryOp::Compare(comparison) => Ok(comparison.holds(lhs, rhs) as isize),
+            BinaryOp::And => Ok((lhs != 0 && rhs != 0) as isize),
+            BinaryOp::Or => Ok((lhs != 0 || rhs != 0) as isize),
+            BinaryOp::Xor => Ok(((lhs != 0) != (rhs != 0)) as isize),
+        }
+    }
+}
+
+// Registers are frame slots: locals first, then one slot per operand stack position.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegisterInstruction {
+    Load(Register, isize),
+    Move(Register, Register),
+    Swap(Register, Register),
+    Rotate(Register),
+    Unary(UnaryOp, Register, Register),
+    Binary(BinaryOp, Register, Register, Register),
+    Increment(Register, isize),
+    Print(Register),
+    PrintVariable(String, Register),
+    Jump(Pointer),
+    Branch(Comparison, Register, Register, Pointer),
+    BranchImmediate(Comparison, Register, isize, Pointer),
+    BranchIfTrue(Register, Pointer),
+    BranchIfZero(Register, Pointer),
+    JumpTable(Register, Vec<Pointer>),
+    Call(Pointer, usize, Register),
+    TailCall(Pointer, usize),
+    Return,
+    ReturnValue(Register),
+    Halt,
+    Exit(i32),
+    Nop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterProgram {
+    instructions: Vec<RegisterInstruction>,
+    frame_size: usize,
+}
+
+impl RegisterProgram {
+    // Every instruction keeps its address, so jump targets and debug info stay valid.
+    pub fn translate(program: &VerifiedProgram, functions: &Functions) -> Self {
+        let instructions = program.instructions();
+        let mut locals: HashMap<&str, usize> = HashMap::new();
+
+        for (ip, instruction) in instructions.iter().enumerate() {
+            let count = locals.entry(functions.scope_of(ip)).or_default();
+
+            if let Some(var_idx) = instruction.variable() {
+                *count = (*count).max(var_idx + 1);
+            }
+        }
+
+        let frame_size = |scope: &str| locals.get(scope).copied().unwrap_or(0) + program.max_depth();
+
+        Self {
+            instructions: instructions
+                .iter()
+                .enumerate()
+                .map(|(ip, instruction)| {
+                    let scope = functions.scope_of(ip);
+                    let base = locals.get(scope).copied().unwrap_or(0);
+
+                    match program.depth_at(ip) {
+                        Some(depth) => RegisterProgram::translate_instruction(instruction, base, depth, |func_ip| {
+                            frame_size(functions.scope_of(func_ip))
+                        }),
+                        None => RegisterInstruction::Nop,
+                    }
+                })
+                .collect(),
+            frame_size: frame_size(MAIN_FUNCTION),
+        }
+    }
+
+    pub fn instructions(&self) -> &[RegisterInstruction] {
+        &self.instructions
+    }
+
+    fn translate_instruction(
+        instruction: &Instruction,
+        base: usize,
+        depth: usize,
+        frame_size: impl Fn(Pointer) -> usize,
+    ) -> RegisterInstruction {
+        // The operand `n` positions below the top of the stack.
+        let top = |n: usize| base + depth - 1 - n;
+        let next = base + depth;
+
+        if let Some(op) = RegisterProgram::unary_op(instruction) {
+            return RegisterInstruction::Unary(op, top(0), top(0));
+        }
+        if let Some(op) = RegisterProgram::binary_op(instruction) {
+            return RegisterInstruction::Binary(op, top(1), top(1), top(0));
+        }
+
+        match instruction {
+            Instruction::LoadValue(val) => RegisterInstruction::Load(next, *val),
+            Instruction::Duplicate => RegisterInstruction::Move(next, top(0)),
+            Instruction::Over => RegisterInstruction::Move(next, top(1)),
+            Instruction::Pick(n) => RegisterInstruction::Move(next, top(*n)),
+            Instruction::Swap => RegisterInstruction::Swap(top(1), top(0)),
+            Instruction::Rotate => RegisterInstruction::Rotate(top(2)),
+            Instruction::WriteVariable(var_idx) => RegisterInstruction::Move(*var_idx, top(0)),
+            Instruction::ReadVariable(var_idx) => RegisterInstruction::Move(next, *var_idx),
+            Instruction::IncrementVariable(var_idx, val) => RegisterInstruction::Increment(*var_idx, *val),
+            Instruction::Print => RegisterInstruction::Print(top(0)),
+            Instruction::PrintVariable(var_name, var_idx) => RegisterInstruction::PrintVariable(var_name.clone(), *var_idx),
+            Instruction::Jump(ip) => RegisterInstruction::Jump(*ip),
+            Instruction::JumpIfEqual(ip) => RegisterInstruction::Branch(Comparison::Equal, top(1), top(0), *ip),
+            Instruction::JumpIfNotEqual(ip) => RegisterInstruction::Branch(Comparison::NotEqual, top(1), top(0), *ip),
+            Instruction::JumpIfGreater(ip) => RegisterInstruction::Branch(Comparison::Greater, top(1), top(0), *ip),
+            Instruction::JumpIfSmaller(ip) => RegisterInstruction::Branch(Comparison::Smaller, top(1), top(0), *ip),
+            Instruction::JumpIfGreaterEqual(ip) => {
+                RegisterInstruction::Branch(Comparison::GreaterEqual, top(1), top(0), *ip)
+            },
+            Instruction::JumpIfSmallerEqual(ip) => {
+                RegisterInstruction::Branch(Comparison::SmallerEqual, top(1), top(0), *ip)
+            },
+            Instruction::JumpIfVariable(comparison, var_idx, val, ip) => {
+                RegisterInstruction::BranchImmediate(*comparison, *var_idx, *val, *ip)
+            },
+            Instruction::JumpIfTrue(ip) => RegisterInstruction::BranchIfTrue(top(0), *ip),
+            Instruction::JumpIfZero(ip) => RegisterInstruction::BranchIfZero(top(0), *ip),
+            Instruction::JumpTable(ips) => RegisterInstruction::JumpTable(top(0), ips.clone()),
+            Instruction::CallFunction(func_ip) => RegisterInstruction::Call(*func_ip, frame_size(*func_ip), next),
+            Instruction::TailCall(func_ip) => RegisterInstruction::TailCall(*func_ip, frame_size(*func_ip)),
+            Instruction::Return => RegisterInstruction::Return,
+            Instruction::ReturnValue => RegisterInstruction::ReturnValue(top(0)),
+            Instruction::Halt => RegisterInstruction::Halt,
+            Instruction::Exit(code) => RegisterInstruction::Exit(*code),
+            _ => RegisterInstruction::Nop,
+        }
+    }
+
+    pub(crate) fn unary_op(instruction: &Instruction) -> Option<UnaryOp> {
+        match instruction {
+            Instruction::Negate => Some(UnaryOp::Negate),
+            Instruction::Absolute => Some(UnaryOp::Absolute),
+            Instruction::BitNot => Some(UnaryOp::BitNot),
+            Instruction::Not => Some(UnaryOp::Not),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn binary_op(instruction: &Instruction) -> Option<BinaryOp> {
+        match instruction {
+            Instruction::Add => Some(BinaryOp::Add),
+            Instruction::Sub => Some(BinaryOp::Sub),
+            Instruction::Multiply => Some(BinaryOp::Multiply),
+            Instruction::Divide => Some(BinaryOp::Divide),
+            Instruction::Modulo => Some(BinaryOp::Modulo),
+            Instruction::Power => Some(BinaryOp::Power),
+            Instruction::Min => Some(BinaryOp::Min),
+            Instruction::Max => Some(BinaryOp::Max),
+            Instruction::ShiftLeft => Some(BinaryOp::ShiftLeft),
+            Instruction::ShiftRight => Some(BinaryOp::ShiftRight),
+            Instruction::BitAnd => Some(BinaryOp::BitAnd),
+            Instruction::BitOr => Some(BinaryOp::BitOr),
+            Instruction::BitXor => Some(BinaryOp::BitXor),
+            Instruction::Equal => Some(BinaryOp::Compare(Comparison::Equal)),
+            Instruction::NotEqual => Some(BinaryOp::Compare(Comparison::NotEqual)),
+            Instruction::Smaller => Some(BinaryOp::Compare(Comparison::Smaller)),
+            Instruction::SmallerEqual => Some(BinaryOp::Compare(Comparison::SmallerEqual)),
+            Instruction::Greater => Some(BinaryOp::Compare(Comparison::Greater)),
+            Instruction::GreaterEqual => Some(BinaryOp::Compare(Comparison::GreaterEqual)),
+            Instruction::And => Some(BinaryOp::And),
+            Instruction::Or => Some(BinaryOp::Or),
+            Instruction::Xor => Some(BinaryOp::Xor),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RegisterFrame {
+    return_ip: Pointer,
+    result: Register,
+    base: usize,
+}
+
+#[derive(Debug)]
+pub struct RegisterMachine {
+    ip: Pointer,
+    frames: Vec<RegisterFrame>,
+    registers: Vec<isize>,
+    fuel: Option<usize>,
+    limits: Limits,
+    memory: usize,
+    output: usize,
+    sink: Option<Sink>,
+    started: Option<Instant>,
+    debug_info: Option<DebugInfo>,
+}
+
+impl RegisterMachine {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            ip: 0,
+            frames: vec![],
+            registers: vec![],
+            fuel: None,
+            limits,
+            memory: 0,
+            output: 0,
+            sink: None,
+            started: None,
+            debug_info: None,
+        }
+    }
+
+    pub fn set_output(&mut self, sink: Option<Box<dyn Write>>) {
+        self.sink = sink.map(Sink);
+    }
+
+    pub fn set_fuel(&mut self, fuel: Option<usize>) {
+        self.fuel = fuel;
+    }
+
+    pub fn set_debug_info(&mut self, debug_info: Option<DebugInfo>) {
+        self.debug_info = debug_info;
+    }
+
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    pub fn run(&mut self, program: &RegisterProgram) -> Result<ExitStatus, Trap> {
+        self.ip = 0;
+        self.frames = vec![];
+        self.registers = vec![];
+        self.memory = 0;
+        self.output = 0;
+        self.started = Some(Instant::now());
+
+        let result = self
+            .enter(program.instructions.len(), program.frame_size, 0)
+            .and_then(|_| self.execute(&program.instructions));
+
+        result.map_err(|error| {
+            let ip = match error {
+                RuntimeError::OutOfFuel(_) | RuntimeError::TimeLimitExceeded(_) => self.ip,
+                _ => self.ip.saturating_sub(1),
+            };
+
+            Trap { backtrace: self.backtrace(ip), error }
+        })
+    }
+
+    pub fn backtrace(&self, ip: Pointer) -> Backtrace {
+        let call_ips = self.frames
+            .iter()
+            .skip(1)
+            .rev()
+            .map(|frame| frame.return_ip.saturating_sub(1));
+
+        Backtrace::new(
+            iter::once(ip)
+                .chain(call_ips)
+                .map(|ip| BacktraceFrame::new(ip, self.debug_info.as_ref()))
+                .collect()
+        )
+    }
+
+    fn execute(&mut self, program: &[RegisterInstruction]) -> Result<ExitStatus, RuntimeError> {
+        let mut base = 0;
+
+        while let Some(instruction) = program.get(self.ip) {
+            self.consume_budget()?;
+
+            self.ip += 1;
+
+            let registers = &mut self.registers[base..];
+
+            match instruction {
+                RegisterInstruction::Load(dst, val) => registers[*dst] = *val,
+                RegisterInstruction::Move(dst, src) => registers[*dst] = registers[*src],
+                RegisterInstruction::Swap(lhs, rhs) => registers.swap(*lhs, *rhs),
+                RegisterInstruction::Rotate(first) => registers[*first..*first + 3].rotate_left(1),
+                RegisterInstruction::Unary(op, dst, src) => registers[*dst] = op.apply(registers[*src])?,
+                RegisterInstruction::Binary(op, dst, lhs, rhs) => {
+                    registers[*dst] = op.apply(registers[*lhs], registers[*rhs])?;
+                },
+                RegisterInstruction::Increment(dst, val) => {
+                    registers[*dst] = registers[*dst].checked_add(*val).ok_or(RuntimeError::ArithmeticOverflow)?;
+                },
+                RegisterInstruction::Print(src) => {
+                    let line = registers[*src].to_string();
+                    self.write_line(&line)?;
+                },
+                RegisterInstruction::PrintVariable(var_name, src) => {
+                    let line = format!("{} = {}", var_name, registers[*src]);
+                    self.write_line(&line)?;
+                },
+                RegisterInstruction::Jump(ip) => self.ip = *ip,
+                RegisterInstruction::Branch(comparison, lhs, rhs, label_ip) => {
+                    if comparison.holds(registers[*lhs], registers[*rhs]) {
+                        self.ip = *label_ip;
+                    }
+                },
+                RegisterInstruction::BranchImmediate(comparison, lhs, val, label_ip) => {
+                    if comparison.holds(registers[*lhs], *val) {
+                        self.ip = *label_ip;
+                    }
+                },
+                RegisterInstruction::BranchIfTrue(src, label_ip) => {
+                    if registers[*src] != 0 {
+                        self.ip = *label_ip;
+                    }
+                },
+                RegisterInstruction::BranchIfZero(src, label_ip) => {
+                    if registers[*src] == 0 {
+                        self.ip = *label_ip;
+                    }
+                },
+                RegisterInstruction::JumpTable(src, label_ips) => {
+                    if let Some(label_ip) = usize::try_from(registers[*src]).ok().and_then(|idx| label_ips.get(idx)) {
+                        self.ip = *label_ip;
+                    }
+                },
+                RegisterInstruction::Call(func_ip, frame_size, result) => {
+                    if self.frames.len() > self.limits.max_call_depth {
+                        return Err(RuntimeError::StackOverflow(self.ip - 1));
+                    }
+
+                    let return_ip = self.ip;
+                    self.ip = *func_ip;
+                    base = self.enter(return_ip, *frame_size, base + result)?;
+                },
+                RegisterInstruction::TailCall(func_ip, frame_size) => {
+                    self.ip = *func_ip;
+                    self.reenter(base, *frame_size)?;
+                },
+                RegisterInstruction::Return => match self.leave() {
+                    Some(caller_base) => base = caller_base,
+                    None => return Ok(ExitStatus::Finished),
+                },
+                RegisterInstruction::ReturnValue(src) => {
+                    let val = registers[*src];
+                    let result = self.frames.last().unwrap().result;
+
+                    match self.leave() {
+                        Some(caller_base) => {
+                            self.registers[result] = val;
+                            base = caller_base;
+                        },
+                        None => return Ok(ExitStatus::Finished),
+                    }
+                },
+                RegisterInstruction::Halt => return Ok(ExitStatus::Halted),
+                RegisterInstruction::Exit(code) => return Ok(ExitStatus::Exited(*code)),
+                RegisterInstruction::Nop => {},
+            }
+        }
+
+        Ok(ExitStatus::Finished)
+    }
+
+    fn enter(&mut self, return_ip: Pointer, frame_size: usize, result: Register) -> Result<usize, RuntimeError> {
+        self.allocate(FRAME_SIZE + frame_size * VALUE_SIZE)?;
+
+        let base = self.registers.len();
+        self.registers.resize(base + frame_size, 0);
+        self.frames.push(RegisterFrame { return_ip, result, base });
+
+        Ok(base)
+    }
+
+    // Reuses the current frame, so the caller's return address and result register stay in place.
+    fn reenter(&mut self, base: usize, frame_size: usize) -> Result<(), RuntimeError> {
+        self.memory = self.memory.saturating_sub((self.registers.len() - base) * VALUE_SIZE);
+        self.registers.truncate(base);
+        self.allocate(frame_size * VALUE_SIZE)?;
+        self.registers.resize(base + frame_size, 0);
+
+        Ok(())
+    }
+
+    fn leave(&mut self) -> Option<usize> {
+        let frame = self.frames.pop().unwrap();
+
+        self.memory = self.memory.saturating_sub(FRAME_SIZE + (self.registers.len() - frame.base) * VALUE_SIZE);
+        self.registers.truncate(frame.base);
+        self.ip = frame.return_ip;
+
+        self.frames.last().map(|frame| frame.base)
+    }
+
+    fn consume_budget(&mut self) -> Result<(), RuntimeError> {
+        match self.fuel.as_mut() {
+            Some(0) => return Err(RuntimeError::OutOfFuel(self.ip)),
+            Some(fuel) => *fuel -= 1,
+            None => {},
+        }
+
+        if let (Some(max_time), Some(started)) = (self.limits.max_time, self.started) {
+            if started.elapsed() > max_time {
+                return Err(RuntimeError::TimeLimitExceeded(max_time));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn allocate(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        let memory = self.memory + bytes;
+
+        if let Some(max_memory) = self.limits.max_memory.filter(|max_memory| memory > *max_memory) {
+            return Err(RuntimeError::MemoryLimitExceeded(max_memory));
+        }
+
+        self.memory = memory;
+
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), RuntimeError> {
+        let output = self.output + line.len() + 1;
+
+        if let Some(max_output) = self.limits.max_output.filter(|max_output| output > *max_output) {
+            return Err(RuntimeError::OutputLimitExceeded(max_output));
+        }
+
+        self.output = output;
+
+        match self.sink.as_mut() {
+            Some(Sink(sink)) => writeln!(sink, "{}", line).map_err(|err| RuntimeError::OutputFailed(err.to_string())),
+            None => {
+                println!("{}", line);
+                Ok(())
+            },
+        }
+    }
+}
+
+impl Default for RegisterMachine {
+    fn default() -> Self {
+        Self::new(Limits::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{verifier::Verifier, test_utils::{SharedBuffer, parse}};
+
+    fn translate(buffer: &str) -> (Vec<Instruction>, RegisterProgram) {
+        let (instructions, functions) = parse(buffer);
+        let verified = Verifier::verify(instructions.clone(), &functions).unwrap();
+
+        (instructions, RegisterProgram::translate(&verified, &functions))
+    }
+
+    fn run_both(buffer: &str) -> (Result<ExitStatus, Trap>, String) {
+        let (instructions, program) = translate(buffer);
+
+        let stack_output = SharedBuffer::default();
+        let mut vm = VirtualMachine::default();
+        vm.set_output(Some(Box::new(stack_output.clone())));
+        let stack_result = vm.run(instructions);
+
+        let register_output = SharedBuffer::default();
+        let mut machine = RegisterMachine::default();
+        machine.set_output(Some(Box::new(register_output.clone())));
+        let register_result = machine.run(&program);
+
+        assert_eq!(register_output.0.borrow().as_slice(), stack_output.0.borrow().as_slice());
+        assert_eq!(
+            register_result.as_ref().map_err(|trap| (trap.error.to_string(), trap.backtrace.ips())),
+            stack_result.as_ref().map_err(|trap| (trap.error.to_string(), trap.backtrace.ips())),
+        );
+
+        (register_result, String::from_utf8(register_output.0.take()).unwrap())
+    }
+
+    #[test]
+    fn translate_program() {
+        let (_, program) = translate(
+            "FUNC DOUBLE\nLOAD_VAL 2\nDUP\nADD\nRETURN_VAL\n\
+            LOAD_VAL 3\nWRITE_VAR 'x'\nREAD_VAR 'x'\nCALL DOUBLE\nMULTIPLY\nPRINT"
+        );
+
+        assert_eq!(
+            program.instructions(),
+            &[
+                RegisterInstruction::Jump(5),
+                RegisterInstruction::Load(0, 2),
+                RegisterInstruction::Move(1, 0),
+                RegisterInstruction::Binary(BinaryOp::Add, 0, 0, 1),
+                RegisterInstruction::ReturnValue(0),
+                RegisterInstruction::Load(1, 3),
+                RegisterInstruction::Move(0, 1),
+                RegisterInstruction::Move(1, 0),
+                RegisterInstruction::Call(1, 2, 2),
+                RegisterInstruction::Binary(BinaryOp::Multiply, 1, 1, 2),
+                RegisterInstruction::Print(1),
+            ]
+        );
+        assert_eq!(program.frame_size, 3);
+    }
+
+    #[test]
+    fn run() {
+        let (result, output) = run_both(
+            "LOAD_VAL 7\nLOAD_VAL 2\nLOAD_VAL 5\nROT\nSWAP\nOVER\nPICK 2\nSUB\nPOW\nMAX\nNEG\nABS\nPRINT\n\
+            LOAD_VAL 3\nSHL\nLOAD_VAL 6\nMOD\nLOAD_VAL 1\nJUMP_TABLE SKIP DONE\nLABEL SKIP\nPRINT\nLABEL DONE\n\
+            LOAD_VAL 4\nGE\nNOT\nWRITE_VAR 'x'\nPRINT 'x'\nEXIT 3"
+        );
+
+        assert!(matches!(result, Ok(ExitStatus::Exited(3))));
+        assert_eq!(output, "7\nx = 1\n");
+    }
+
+    #[test]
+    fn run_examples() {
+        for buffer in [
+            include_str!("../examples/arithmetic.bytecode"),
+            include_str!("../examples/function.bytecode"),
+            include_str!("../examples/loop.bytecode"),
+            include_str!("../examples/switch.bytecode"),
+            include_str!("../examples/variables.bytecode"),
+        ] {
+            assert!(run_both(buffer).0.is_ok());
+        }
+    }
+
+    #[test]
+    fn run_should_return_error_with_backtrace() {
+        let (result, _) = run_both(
+            "FUNC DIVIDE\nLOAD_VAL 1\nLOAD_VAL 0\nDIVIDE\nRETURN_VAL\nLOAD_VAL 5\nPRINT\nCALL DIVIDE"
+        );
+
+        assert!(matches!(result, Err(Trap { error: RuntimeError::DivisionByZero, .. })));
+    }
+
+    #[test]
+    fn run_tail_call() {
+        let (result, output) = run_both(
+            "FUNC THREE\nLOAD_VAL 3\nRETURN_VAL\nFUNC FORWARD\nLOAD_VAL 2\nWRITE_VAR 'x'\nTAIL_CALL THREE\nRETURN_VAL\n\
+            CALL FORWARD\nCALL FORWARD\nADD\nPRINT"
+        );
+
+        assert!(matches!(result, Ok(ExitStatus::Finished)));
+        assert_eq!(output, "6\n");
+    }
+
+    #[test]
+    fn run_locals_written_out_of_order() {
+        let (result, output) = run_both(
+            "LOAD_VAL 1\nLOAD_VAL 1\nJUMP_IF_EQ SKIP\nLOAD_VAL 5\nWRITE_VAR 'x'\nLABEL SKIP\n\
+            LOAD_VAL 7\nWRITE_VAR 'y'\nPRINT 'y'"
+        );
+
+        assert!(matches!(result, Ok(ExitStatus::Finished)));
+        assert_eq!(output, "y = 7\n");
+    }
+
+    #[test]
+    fn run_should_return_error_for_call_depth() {
+        let (result, _) = run_both("FUNC RECURSE\nCALL RECURSE\nRETURN\nCALL RECURSE");
+
+        assert!(matches!(result, Err(Trap { error: RuntimeError::StackOverflow(1), .. })));
+    }
+}
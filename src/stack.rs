@@ -1,4 +1,4 @@
-use crate::errors::RuntimeError;
+use std::slice::Iter;
 
 #[derive(Debug, PartialEq)]
 pub struct Stack<T>(Vec<T>);
@@ -16,24 +16,24 @@ impl<T> Stack<T> {
         self.0.push(val);
     }
 
-    pub fn pop(&mut self) -> Result<T, RuntimeError> {
-        self.0.pop().ok_or(RuntimeError::EmptyStack)
+    pub fn pop(&mut self) -> Option<T> {
+        self.0.pop()
     }
 
-    pub fn peek(&self) -> Result<&T, RuntimeError> {
-        self.0.last().ok_or(RuntimeError::EmptyStack)
+    pub fn peek(&self) -> Option<&T> {
+        self.0.last()
     }
 
-    pub fn peek_mut(&mut self) -> Result<&mut T, RuntimeError> {
-        self.0.last_mut().ok_or(RuntimeError::EmptyStack)
+    pub fn peek_mut(&mut self) -> Option<&mut T> {
+        self.0.last_mut()
     }
 
-    pub fn get(&self, idx: usize) -> Result<&T, RuntimeError> {
-        self.0.get(idx).ok_or(RuntimeError::WrongStackIndex)
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        self.0.get(idx)
     }
 
-    pub fn get_mut(&mut self, idx: usize) -> Result<&mut T, RuntimeError> {
-        self.0.get_mut(idx).ok_or(RuntimeError::WrongStackIndex)
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        self.0.get_mut(idx)
     }
 
     pub fn len(&self) -> usize {
@@ -43,6 +43,16 @@ impl<T> Stack<T> {
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.0.iter()
+    }
+}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
@@ -84,10 +94,10 @@ mod test {
     }
 
     #[test]
-    fn pop_should_return_error_when_stack_is_empty() {
+    fn pop_should_return_none_when_stack_is_empty() {
         let mut stack: Stack<isize> = Stack::new();
 
-        assert!(stack.pop().is_err());
+        assert!(stack.pop().is_none());
     }
 
     #[test]
@@ -100,10 +110,10 @@ mod test {
     }
 
     #[test]
-    fn peek_should_return_error_when_stack_is_empty() {
+    fn peek_should_return_none_when_stack_is_empty() {
         let stack: Stack<isize> = Stack::new();
 
-        assert!(stack.peek().is_err());
+        assert!(stack.peek().is_none());
     }
 
     #[test]
@@ -117,10 +127,10 @@ mod test {
     }
 
     #[test]
-    fn peek_mut_should_return_error_when_stack_is_empty() {
+    fn peek_mut_should_return_none_when_stack_is_empty() {
         let mut stack: Stack<isize> = Stack::new();
 
-        assert!(stack.peek_mut().is_err());
+        assert!(stack.peek_mut().is_none());
     }
 
     #[test]
@@ -135,10 +145,10 @@ mod test {
     }
 
     #[test]
-    fn get_should_return_error_when_wrong_index() {
+    fn get_should_return_none_when_wrong_index() {
         let stack: Stack<isize> = Stack::new();
 
-        assert!(stack.get(10).is_err());
+        assert!(stack.get(10).is_none());
     }
 
     #[test]
@@ -154,10 +164,10 @@ mod test {
     }
 
     #[test]
-    fn get_mut_should_return_error_when_wrong_index() {
+    fn get_mut_should_return_none_when_wrong_index() {
         let mut stack: Stack<isize> = Stack::new();
 
-        assert!(stack.get_mut(10).is_err());
+        assert!(stack.get_mut(10).is_none());
     }
 
     #[test]
@@ -0,0 +1,27 @@
+use std::{io::{self, Write}, rc::Rc, cell::RefCell};
+
+use crate::{parser::Parser, instruction::Instruction, functions::Functions};
+
+// Output sink whose clones share the written bytes, so tests can read what the VM printed.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(pub Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn parse(buffer: &str) -> (Vec<Instruction>, Functions) {
+    let bytecode = Parser::parse_code(buffer);
+    let functions = Parser::parse_functions(&bytecode).unwrap();
+    let mut variables = Parser::parse_variables(&bytecode).unwrap();
+    let labels = Parser::parse_labels(&bytecode, &functions).unwrap();
+    let instructions = Parser::parse_instructions(&bytecode, &functions, &mut variables, &labels).unwrap();
+
+    (instructions, functions)
+}
@@ -0,0 +1,167 @@
+use std::{fmt::{Debug, Formatter, Result as FmtResult}, io::{self, Write}};
+
+use crate::{vm::Pointer, instruction::Instruction};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    JsonLines,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TraceFilter {
+    pub function: Option<String>,
+    pub range: Option<(Pointer, Pointer)>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, ip: Pointer, function: Option<&str>) -> bool {
+        let function_matches = self.function
+            .as_deref()
+            .is_none_or(|filter| function == Some(filter));
+        let range_matches = self.range
+            .is_none_or(|(start_ip, end_ip)| start_ip <= ip && ip <= end_ip);
+
+        function_matches && range_matches
+    }
+}
+
+pub struct TraceEvent<'a> {
+    pub ip: Pointer,
+    pub instruction: &'a Instruction,
+    pub function: Option<&'a str>,
+    pub call_depth: usize,
+    pub stack_before: &'a [isize],
+    pub stack_after: &'a [isize],
+}
+
+pub struct Tracer {
+    sink: Box<dyn Write>,
+    format: TraceFormat,
+    filter: TraceFilter,
+}
+
+impl Tracer {
+    pub fn new(sink: Box<dyn Write>, format: TraceFormat) -> Self {
+        Self {
+            sink,
+            format,
+            filter: TraceFilter::default(),
+        }
+    }
+
+    pub fn with_filter(mut self, filter: TraceFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn is_traced(&self, ip: Pointer, function: Option<&str>) -> bool {
+        self.filter.matches(ip, function)
+    }
+
+    pub fn trace(&mut self, event: &TraceEvent) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(
+                self.sink,
+                "{:>6}  {:<3} {:<24} {:?} -> {:?}",
+                event.ip,
+                event.call_depth,
+                event.instruction.to_string(),
+                event.stack_before,
+                event.stack_after,
+            ),
+            TraceFormat::JsonLines => writeln!(
+                self.sink,
+                "{{\"ip\":{},\"instruction\":{},\"function\":{},\"depth\":{},\"before\":{:?},\"after\":{:?}}}",
+                event.ip,
+                json_string(&event.instruction.to_string()),
+                event.function.map_or("null".to_string(), json_string),
+                event.call_depth,
+                event.stack_before,
+                event.stack_after,
+            ),
+        }
+    }
+}
+
+impl Debug for Tracer {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Tracer")
+            .field("format", &self.format)
+            .field("filter", &self.filter)
+            .finish()
+    }
+}
+
+pub fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+
+    for ch in value.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            ch if ch.is_control() => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::SharedBuffer;
+
+    fn event(instruction: &Instruction) -> TraceEvent<'_> {
+        TraceEvent {
+            ip: 3,
+            instruction,
+            function: Some("MAIN"),
+            call_depth: 0,
+            stack_before: &[1, 2],
+            stack_after: &[3],
+        }
+    }
+
+    #[test]
+    fn trace_text() {
+        let buffer = SharedBuffer::default();
+        let mut tracer = Tracer::new(Box::new(buffer.clone()), TraceFormat::Text);
+
+        tracer.trace(&event(&Instruction::Add)).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buffer.0.take()).unwrap(),
+            "     3  0   ADD                      [1, 2] -> [3]\n"
+        );
+    }
+
+    #[test]
+    fn trace_json_lines() {
+        let buffer = SharedBuffer::default();
+        let mut tracer = Tracer::new(Box::new(buffer.clone()), TraceFormat::JsonLines);
+
+        tracer.trace(&event(&Instruction::PrintVariable("\"x\"".to_string(), 0))).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buffer.0.take()).unwrap(),
+            "{\"ip\":3,\"instruction\":\"PRINT \\\"x\\\" #0\",\"function\":\"MAIN\",\"depth\":0,\"before\":[1, 2],\"after\":[3]}\n"
+        );
+    }
+
+    #[test]
+    fn filter_matches() {
+        let filter = TraceFilter { function: Some("TEST".to_string()), range: Some((2, 5)) };
+
+        assert!(filter.matches(2, Some("TEST")));
+        assert!(!filter.matches(6, Some("TEST")));
+        assert!(!filter.matches(3, Some("MAIN")));
+        assert!(!filter.matches(3, None));
+        assert!(TraceFilter::default().matches(3, None));
+    }
+}
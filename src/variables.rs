@@ -36,6 +36,18 @@ impl<'buf> Variables<'buf> {
     pub fn queue_pop_front(&mut self) -> Result<VariableAddress, ParseError> {
         self.queue.pop_front().ok_or(ParseError::VariableNotFound)
     }
+
+    pub fn locals_count(&self, func_name: &str) -> usize {
+        self.functions_locals
+            .get(func_name)
+            .map_or(0, |locals_map| locals_map.len())
+    }
+}
+
+impl<'buf> Default for Variables<'buf> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
@@ -86,6 +98,18 @@ mod test {
         assert_eq!(variables.queue, expected_queue);
     }
 
+    #[test]
+    fn locals_count() {
+        let mut variables = Variables::new();
+
+        variables.insert_local("MAIN", "x");
+        variables.insert_local("MAIN", "y");
+        variables.insert_local("MAIN", "x");
+
+        assert_eq!(variables.locals_count("MAIN"), 2);
+        assert_eq!(variables.locals_count("FUNC"), 0);
+    }
+
     #[test]
     fn queue_pop_front_should_return_error_when_queue_empty() {
         let mut variables = Variables::new();
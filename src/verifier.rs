@@ -0,0 +1,270 @@
+use crate::{vm::Pointer, instruction::Instruction, functions::Functions, errors::VerifyError};
+
+// Locals may be written in any order, so a state knows which ones every path has written.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct State {
+    depth: usize,
+    written: Vec<bool>,
+}
+
+impl State {
+    fn is_written(&self, var_idx: usize) -> bool {
+        self.written.get(var_idx).copied().unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedProgram {
+    instructions: Vec<Instruction>,
+    depths: Vec<Option<usize>>,
+    max_depth: usize,
+}
+
+impl VerifiedProgram {
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    pub fn into_instructions(self) -> Vec<Instruction> {
+        self.instructions
+    }
+
+    pub fn depth_at(&self, ip: Pointer) -> Option<usize> {
+        self.depths.get(ip).copied().flatten()
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+}
+
+pub struct Verifier;
+
+impl Verifier {
+    pub fn verify(instructions: Vec<Instruction>, functions: &Functions) -> Result<VerifiedProgram, VerifyError> {
+        let mut states: Vec<Option<State>> = vec![None; instructions.len()];
+        let mut worklist = vec![];
+        let mut max_depth = 0;
+
+        Verifier::merge(&mut states, &mut worklist, 0, State::default())?;
+
+        while let Some(ip) = worklist.pop() {
+            let state = states[ip].clone().unwrap();
+            let instruction = &instructions[ip];
+
+            let (pops, pushes) = Verifier::stack_effect(ip, instruction, &instructions, functions)?;
+            let depth = state.depth
+                .checked_sub(pops)
+                .ok_or(VerifyError::StackUnderflow(ip))?
+                + pushes;
+            let mut written = state.written.clone();
+
+            match instruction {
+                Instruction::ReadVariable(var_idx) |
+                Instruction::IncrementVariable(var_idx, _) |
+                Instruction::JumpIfVariable(_, var_idx, _, _) |
+                Instruction::PrintVariable(_, var_idx) if !state.is_written(*var_idx) => {
+                    return Err(VerifyError::UnsetVariable(ip));
+                },
+                Instruction::WriteVariable(var_idx) => {
+                    if *var_idx >= written.len() {
+                        written.resize(var_idx + 1, false);
+                    }
+                    written[*var_idx] = true;
+                },
+                _ => {},
+            }
+            max_depth = max_depth.max(state.depth).max(depth);
+
+            let next_state = State { depth, written };
+
+            if let Instruction::CallFunction(func_ip) | Instruction::TailCall(func_ip) = instruction {
+                Verifier::merge(&mut states, &mut worklist, *func_ip, State::default())?;
+            }
+
+            for target_ip in instruction.successors(ip) {
+                if target_ip > instructions.len() {
+                    return Err(VerifyError::JumpOutOfRange(ip, target_ip));
+                }
+
+                Verifier::merge(&mut states, &mut worklist, target_ip, next_state.clone())?;
+            }
+        }
+
+        Ok(VerifiedProgram {
+            instructions,
+            depths: states
+                .into_iter()
+                .map(|state| state.map(|state| state.depth))
+                .collect(),
+            max_depth,
+        })
+    }
+
+    fn merge(
+        states: &mut [Option<State>],
+        worklist: &mut Vec<Pointer>,
+        ip: Pointer,
+        state: State,
+    ) -> Result<(), VerifyError> {
+        let Some(actual_state) = states.get_mut(ip) else {
+            return Ok(());
+        };
+
+        match actual_state {
+            None => {
+                *actual_state = Some(state);
+                worklist.push(ip);
+            },
+            Some(actual) if actual.depth != state.depth => {
+                return Err(VerifyError::InconsistentStackDepth(ip, actual.depth, state.depth));
+            },
+            Some(actual) => {
+                let mut narrowed = false;
+
+                for (var_idx, written) in actual.written.iter_mut().enumerate() {
+                    if *written && !state.is_written(var_idx) {
+                        *written = false;
+                        narrowed = true;
+                    }
+                }
+                if narrowed {
+                    worklist.push(ip);
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn stack_effect(
+        ip: Pointer,
+        instruction: &Instruction,
+        instructions: &[Instruction],
+        functions: &Functions,
+    ) -> Result<(usize, usize), VerifyError> {
+        let (Instruction::CallFunction(func_ip) | Instruction::TailCall(func_ip)) = instruction else {
+            return Ok(instruction.stack_effect().unwrap());
+        };
+
+        let (_, end_ip) = functions
+            .iter()
+            .map(|(_, range)| *range)
+            .find(|(start_ip, _)| start_ip + 1 == *func_ip)
+            .ok_or(VerifyError::InvalidCallTarget(ip, *func_ip))?;
+        let returns_value = matches!(instructions.get(end_ip), Some(Instruction::ReturnValue));
+
+        if let Instruction::TailCall(_) = instruction {
+            // The called function returns in place of the calling one, so both have to return the same way.
+            let caller_returns_value = functions
+                .iter()
+                .map(|(_, range)| *range)
+                .find(|(start_ip, end_ip)| (*start_ip..=*end_ip).contains(&ip))
+                .map(|(_, end_ip)| matches!(instructions.get(end_ip), Some(Instruction::ReturnValue)));
+
+            return match caller_returns_value {
+                Some(caller_returns_value) if caller_returns_value == returns_value => Ok((0, 0)),
+                _ => Err(VerifyError::InvalidTailCall(ip)),
+            };
+        }
+
+        match returns_value {
+            true => Ok((0, 1)),
+            false => Ok((0, 0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::test_utils::parse;
+
+    fn verify(buffer: &str) -> Result<VerifiedProgram, VerifyError> {
+        let (instructions, functions) = parse(buffer);
+
+        Verifier::verify(instructions, &functions)
+    }
+
+    #[test]
+    fn verify_program() {
+        let program = verify(
+            "FUNC DOUBLE\nLOAD_VAL 2\nLOAD_VAL 2\nMULTIPLY\nRETURN_VAL\n\
+            LOAD_VAL 0\nWRITE_VAR 'x'\nLABEL LOOP\nREAD_VAR 'x'\nCALL DOUBLE\nADD\nWRITE_VAR 'x'\n\
+            READ_VAR 'x'\nLOAD_VAL 20\nJUMP_IF_SM LOOP\nPRINT 'x'"
+        ).unwrap();
+
+        assert_eq!(program.depth_at(1), Some(0));
+        assert_eq!(program.depth_at(3), Some(2));
+        assert_eq!(program.depth_at(9), Some(1));
+        assert_eq!(program.depth_at(10), Some(2));
+        assert_eq!(program.max_depth(), 2);
+    }
+
+    #[test]
+    fn verify_program_with_tail_call() {
+        let program = verify(
+            "FUNC ONE\nLOAD_VAL 1\nRETURN_VAL\nFUNC NEXT\nTAIL_CALL ONE\nRETURN_VAL\nCALL NEXT\nPRINT"
+        ).unwrap();
+
+        assert_eq!(program.depth_at(4), Some(0));
+        assert_eq!(program.depth_at(5), None);
+        assert_eq!(program.depth_at(7), Some(1));
+    }
+
+    #[test]
+    fn verify_examples() {
+        for entry in fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+
+            if let Err(error) = verify(&fs::read_to_string(&path).unwrap()) {
+                panic!("Expected {} to verify, got {}.", path.display(), error);
+            }
+        }
+    }
+
+    #[test]
+    fn verify_should_return_error_for_stack_underflow() {
+        assert!(matches!(verify("LOAD_VAL 1\nADD"), Err(VerifyError::StackUnderflow(1))));
+        assert!(matches!(verify("FUNC TEST\nRETURN_VAL\nCALL TEST"), Err(VerifyError::StackUnderflow(1))));
+    }
+
+    #[test]
+    fn verify_should_return_error_for_inconsistent_depths() {
+        let result = verify("LOAD_VAL 1\nJUMP_IF_TRUE END\nLOAD_VAL 2\nLABEL END");
+
+        assert!(matches!(result, Err(VerifyError::InconsistentStackDepth(3, _, _))));
+    }
+
+    #[test]
+    fn verify_should_return_error_for_possibly_unset_variable() {
+        let result = verify("LOAD_VAL 1\nJUMP_IF_TRUE SKIP\nLOAD_VAL 2\nWRITE_VAR 'x'\nLABEL SKIP\nREAD_VAR 'x'");
+
+        assert!(matches!(result, Err(VerifyError::UnsetVariable(5))));
+    }
+
+    #[test]
+    fn verify_should_track_locals_written_out_of_order() {
+        let buffer = "LOAD_VAL 1\nLOAD_VAL 1\nJUMP_IF_EQ SKIP\nLOAD_VAL 5\nWRITE_VAR 'x'\nLABEL SKIP\n\
+            LOAD_VAL 7\nWRITE_VAR 'y'\nPRINT 'y'";
+
+        assert!(verify(buffer).is_ok());
+        assert!(matches!(verify(&format!("{}\nPRINT 'x'", buffer)), Err(VerifyError::UnsetVariable(9))));
+    }
+
+    #[test]
+    fn verify_should_return_error_for_jump_out_of_range() {
+        let result = Verifier::verify(vec![Instruction::Jump(5)], &Functions::new());
+
+        assert!(matches!(result, Err(VerifyError::JumpOutOfRange(0, 5))));
+    }
+
+    #[test]
+    fn verify_should_return_error_for_invalid_tail_call() {
+        let result = verify("FUNC VOID\nRETURN\nFUNC NEXT\nTAIL_CALL VOID\nRETURN_VAL\nCALL NEXT");
+
+        assert!(matches!(result, Err(VerifyError::InvalidTailCall(3))));
+    }
+}
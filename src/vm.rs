@@ -1,406 +1,2328 @@
-use crate::{stack::Stack, frame::Frame, instruction::Instruction};
+use std::{cmp::Ordering, fmt::{Debug, Formatter, Result as FmtResult}, io::Write, iter, mem::{self, size_of}, time::{Duration, Instant}};
+
+#[cfg(feature = "jit")]
+use crate::jit::{Jit, Exit};
+use crate::{stack::Stack, frame::Frame, instruction::{Instruction, Comparison}, backtrace::{Backtrace, BacktraceFrame, DebugInfo}, limits::Limits, tracer::{Tracer, TraceEvent}, profiler::Profiler, coverage::Coverage, verifier::VerifiedProgram, errors::{RuntimeError, Trap}};
 
 pub type Pointer = usize;
 
-const CALL_STACK_DEFAULT_CAPACITY: usize = 20;
+pub const USAGE_ERROR_EXIT_CODE: i32 = 64;
+pub const PARSE_ERROR_EXIT_CODE: i32 = 65;
+pub const RUNTIME_ERROR_EXIT_CODE: i32 = 70;
+pub const IO_ERROR_EXIT_CODE: i32 = 74;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExitStatus {
+    Finished,
+    Halted,
+    Exited(i32),
+}
+
+impl ExitStatus {
+    pub fn code(&self) -> i32 {
+        match self {
+            ExitStatus::Finished | ExitStatus::Halted => 0,
+            ExitStatus::Exited(code) => *code,
+        }
+    }
+
+    // Processes can only report 0..=255, and the codes of the command line tool's own errors
+    // would make an exiting program look like it failed.
+    pub fn is_valid_code(code: i32) -> bool {
+        (0..=255).contains(&code) && ![
+            USAGE_ERROR_EXIT_CODE,
+            PARSE_ERROR_EXIT_CODE,
+            RUNTIME_ERROR_EXIT_CODE,
+            IO_ERROR_EXIT_CODE,
+        ].contains(&code)
+    }
+}
+
+pub(crate) const VALUE_SIZE: usize = size_of::<isize>();
+const FRAME_SIZE: usize = size_of::<Frame>();
+
+pub(crate) struct Sink(pub(crate) Box<dyn Write>);
+
+impl Debug for Sink {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "Sink")
+    }
+}
+
+#[derive(Debug)]
+pub struct VirtualMachine {
+    ip: Pointer,
+    frame: Frame,
+    call_stack: Stack<Frame>,
+    values: Vec<isize>,
+    written: Vec<bool>,
+    windows: Vec<usize>,
+    program: Vec<Instruction>,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    #[cfg(feature = "jit")]
+    jit: Option<Jit>,
+    fuel: Option<usize>,
+    limits: Limits,
+    memory: usize,
+    output: usize,
+    sink: Option<Sink>,
+    started: Option<Instant>,
+    elapsed: Duration,
+    debug_info: Option<DebugInfo>,
+    verified_depth: Option<usize>,
+}
+
+impl VirtualMachine {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            ip: 0,
+            frame: Frame::new(0, 0, 0),
+            call_stack: Stack::new(),
+            values: vec![],
+            written: vec![],
+            windows: vec![],
+            program: vec![],
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            #[cfg(feature = "jit")]
+            jit: None,
+            fuel: None,
+            limits,
+            memory: 0,
+            output: 0,
+            sink: None,
+            started: None,
+            elapsed: Duration::ZERO,
+            debug_info: None,
+            verified_depth: None,
+        }
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn set_output(&mut self, sink: Option<Box<dyn Write>>) {
+        self.sink = sink.map(Sink);
+    }
+
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage;
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, jit: Option<Jit>) {
+        self.jit = jit;
+    }
+
+    #[cfg(feature = "jit")]
+    pub fn jit(&self) -> Option<&Jit> {
+        self.jit.as_ref()
+    }
+
+    pub fn program(&self) -> &[Instruction] {
+        &self.program
+    }
+
+    pub fn fuel(&self) -> Option<usize> {
+        self.fuel
+    }
+
+    pub fn set_fuel(&mut self, fuel: Option<usize>) {
+        self.fuel = fuel;
+    }
+
+    pub fn add_fuel(&mut self, fuel: usize) {
+        if let Some(remaining) = self.fuel.as_mut() {
+            *remaining = remaining.saturating_add(fuel);
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    pub fn set_debug_info(&mut self, debug_info: Option<DebugInfo>) {
+        self.debug_info = debug_info;
+    }
+
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug_info.as_ref()
+    }
+
+    pub fn backtrace(&self, ip: Pointer) -> Backtrace {
+        let call_ips = iter::once(&self.frame)
+            .chain(self.call_stack.iter().rev())
+            .take(self.call_stack.len())
+            .map(|frame| frame.ip.saturating_sub(1));
+
+        Backtrace::new(
+            iter::once(ip)
+                .chain(call_ips)
+                .map(|ip| BacktraceFrame::new(ip, self.debug_info.as_ref()))
+                .collect()
+        )
+    }
+
+    pub fn run(&mut self, program: Vec<Instruction>) -> Result<ExitStatus, Trap> {
+        self.load(program, None);
+        self.resume()
+    }
+
+    pub fn run_verified(&mut self, program: VerifiedProgram) -> Result<ExitStatus, Trap> {
+        let max_depth = program.max_depth();
+
+        self.load(program.into_instructions(), Some(max_depth));
+        self.resume()
+    }
+
+    fn load(&mut self, program: Vec<Instruction>, verified_depth: Option<usize>) {
+        self.ip = 0;
+        self.call_stack = Stack::new();
+        self.output = 0;
+        self.elapsed = Duration::ZERO;
+
+        self.windows = VirtualMachine::windows(&program);
+        self.memory = FRAME_SIZE;
+        self.frame = Frame::new(program.len(), 0, self.windows[0]);
+        self.values = vec![0; self.frame.window];
+        self.written = vec![false; self.frame.window];
+        self.program = program;
+
+        self.verified_depth = verified_depth;
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.start();
+        }
+    }
+
+    // Only the time spent in here counts against max_time, not the pauses between fuel refills.
+    pub fn resume(&mut self) -> Result<ExitStatus, Trap> {
+        let program = mem::take(&mut self.program);
+        self.started = Some(Instant::now());
+        let result = match self.is_fast_path() {
+            true => self.execute_verified(&program),
+            false => self.execute(&program),
+        };
+        self.elapsed += self.started.take().map_or(Duration::ZERO, |started| started.elapsed());
+        self.program = program;
+
+        result.map_err(|error| {
+            let ip = match error {
+                RuntimeError::OutOfFuel(_) | RuntimeError::TimeLimitExceeded(_) => self.ip,
+                _ => self.current_ip(),
+            };
+
+            Trap { backtrace: self.backtrace(ip), error }
+        })
+    }
+
+    // Reserves a slot for every local a function may write, so its operands never have to move.
+    fn windows(program: &[Instruction]) -> Vec<usize> {
+        let mut windows = vec![0; program.len() + 1];
+        let entries = iter::once(0).chain(program.iter().filter_map(|instruction| match instruction {
+            Instruction::CallFunction(func_ip) | Instruction::TailCall(func_ip) => Some(*func_ip),
+            _ => None,
+        }));
+
+        // Each walk marks what it visited with its own entry, so one buffer serves all of them.
+        let mut visited_from = vec![usize::MAX; program.len()];
+
+        for entry_ip in entries {
+            if entry_ip >= program.len() || visited_from[entry_ip] == entry_ip {
+                continue;
+            }
+
+            let mut worklist = vec![entry_ip];
+            let mut window = 0;
+
+            while let Some(ip) = worklist.pop() {
+                let Some(instruction) = program.get(ip).filter(|_| visited_from[ip] != entry_ip) else {
+                    continue;
+                };
+                visited_from[ip] = entry_ip;
+
+                if let Some(var_idx) = instruction.variable() {
+                    window = window.max(var_idx + 1);
+                }
+                worklist.extend(instruction.successors(ip));
+            }
+
+            windows[entry_ip] = window;
+        }
+
+        windows
+    }
+
+    // Compiled code neither consumes fuel nor checks the time limit, so it only runs without them.
+    #[cfg(feature = "jit")]
+    fn call_compiled(&mut self, func_ip: Pointer, program: &[Instruction]) -> bool {
+        if self.fuel.is_some()
+            || self.limits.max_time.is_some()
+            || self.call_stack.len() + 1 > self.limits.max_call_depth {
+            return false;
+        }
+
+        let Some(jit) = self.jit.as_mut() else {
+            return false;
+        };
+
+        match jit.call(func_ip, program) {
+            Exit::Returned => true,
+            Exit::ReturnedValue(val) => {
+                self.values.push(val);
+                true
+            },
+            Exit::Interpret => false,
+        }
+    }
+
+    fn is_fast_path(&self) -> bool {
+        self.verified_depth.is_some_and(|max_depth| max_depth <= self.limits.max_operand_depth)
+            && self.limits.max_memory.is_none()
+            && self.tracer.is_none()
+            && self.profiler.is_none()
+            && self.coverage.is_none()
+    }
+
+    fn consume_budget(&mut self) -> Result<(), RuntimeError> {
+        match self.fuel.as_mut() {
+            Some(0) => return Err(RuntimeError::OutOfFuel(self.ip)),
+            Some(fuel) => *fuel -= 1,
+            None => {},
+        }
+
+        if let (Some(max_time), Some(started)) = (self.limits.max_time, self.started) {
+            if self.elapsed + started.elapsed() > max_time {
+                return Err(RuntimeError::TimeLimitExceeded(max_time));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute(&mut self, program: &[Instruction]) -> Result<ExitStatus, RuntimeError> {
+        while let Some(instruction) = program.get(self.ip) {
+            self.consume_budget()?;
+
+            let ip = self.ip;
+            let call_depth = self.call_stack.len();
+            let stack_before = self.is_traced(ip).then(|| self.operand_stack());
+
+            self.ip += 1;
+            let result = self.step(instruction);
+
+            if let Some(stack_before) = stack_before {
+                self.trace(ip, instruction, call_depth, &stack_before)?;
+            }
+
+            if self.profiler.is_some() {
+                self.profile(ip, instruction, call_depth);
+            }
+
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.record(ip);
+
+                if let (Some(target_ip), Ok(_)) = (instruction.branch_target(), &result) {
+                    coverage.record_branch(ip, self.ip == target_ip);
+                }
+            }
+
+            if let Some(status) = result? {
+                return Ok(status);
+            }
+        }
+
+        Ok(ExitStatus::Finished)
+    }
+
+    fn execute_verified(&mut self, program: &[Instruction]) -> Result<ExitStatus, RuntimeError> {
+        while let Some(instruction) = program.get(self.ip) {
+            self.consume_budget()?;
+
+            self.ip += 1;
+
+            // The verifier proved that every operand and local used below is present
+            // and that no frame outgrows the operand stack limit.
+            let base = self.frame.base;
+
+            match instruction {
+                Instruction::LoadValue(val) => self.values.push(*val),
+                Instruction::ReadVariable(var_idx) => self.values.push(self.values[base + var_idx]),
+                Instruction::WriteVariable(var_idx) => {
+                    let val = self.values.pop().unwrap();
+                    self.set_local(*var_idx, val);
+                },
+                Instruction::Add => {
+                    let (lhs, rhs) = VirtualMachine::pop_operands(&mut self.values);
+                    self.values.push(lhs.checked_add(rhs).ok_or(RuntimeError::ArithmeticOverflow)?);
+                },
+                Instruction::Sub => {
+                    let (lhs, rhs) = VirtualMachine::pop_operands(&mut self.values);
+                    self.values.push(lhs.checked_sub(rhs).ok_or(RuntimeError::ArithmeticOverflow)?);
+                },
+                Instruction::IncrementVariable(var_idx, val) => {
+                    let local = &mut self.values[base + var_idx];
+                    *local = local.checked_add(*val).ok_or(RuntimeError::ArithmeticOverflow)?;
+                },
+                Instruction::Jump(ip) => self.ip = *ip,
+                Instruction::JumpIfVariable(comparison, var_idx, val, label_ip) => {
+                    if comparison.holds(self.values[base + var_idx], *val) {
+                        self.ip = *label_ip;
+                    }
+                },
+                Instruction::JumpIfEqual(label_ip) => {
+                    let (lhs, rhs) = VirtualMachine::pop_operands(&mut self.values);
+                    if lhs == rhs {
+                        self.ip = *label_ip;
+                    }
+                },
+                Instruction::JumpIfNotEqual(label_ip) => {
+                    let (lhs, rhs) = VirtualMachine::pop_operands(&mut self.values);
+                    if lhs != rhs {
+                        self.ip = *label_ip;
+                    }
+                },
+                Instruction::JumpIfGreater(label_ip) => {
+                    let (lhs, rhs) = VirtualMachine::pop_operands(&mut self.values);
+                    if lhs > rhs {
+                        self.ip = *label_ip;
+                    }
+                },
+                Instruction::JumpIfSmaller(label_ip) => {
+                    let (lhs, rhs) = VirtualMachine::pop_operands(&mut self.values);
+                    if lhs < rhs {
+                        self.ip = *label_ip;
+                    }
+                },
+                Instruction::JumpIfGreaterEqual(label_ip) => {
+                    let (lhs, rhs) = VirtualMachine::pop_operands(&mut self.values);
+                    if lhs >= rhs {
+                        self.ip = *label_ip;
+                    }
+                },
+                Instruction::JumpIfSmallerEqual(label_ip) => {
+                    let (lhs, rhs) = VirtualMachine::pop_operands(&mut self.values);
+                    if lhs <= rhs {
+                        self.ip = *label_ip;
+                    }
+                },
+                Instruction::CallFunction(func_ip) => {
+                    #[cfg(feature = "jit")]
+                    if self.call_compiled(*func_ip, program) {
+                        continue;
+                    }
+                    self.call_function(*func_ip)?
+                },
+                Instruction::TailCall(func_ip) => self.tail_call(*func_ip)?,
+                Instruction::Return => self.return_void()?,
+                Instruction::Ignore => {},
+                _ => {
+                    if let Some(status) = self.step(instruction)? {
+                        return Ok(status);
+                    }
+                },
+            }
+        }
+
+        Ok(ExitStatus::Finished)
+    }
+
+    fn pop_operands(values: &mut Vec<isize>) -> (isize, isize) {
+        let rhs = values.pop().unwrap();
+        let lhs = values.pop().unwrap();
+
+        (lhs, rhs)
+    }
+
+    fn is_traced(&self, ip: Pointer) -> bool {
+        let function = self.debug_info
+            .as_ref()
+            .map(|debug_info| debug_info.function_of(ip));
+
+        self.tracer
+            .as_ref()
+            .is_some_and(|tracer| tracer.is_traced(ip, function))
+    }
+
+    fn trace(
+        &mut self,
+        ip: Pointer,
+        instruction: &Instruction,
+        call_depth: usize,
+        stack_before: &[isize],
+    ) -> Result<(), RuntimeError> {
+        let stack_after = self.operand_stack();
+        let event = TraceEvent {
+            ip,
+            instruction,
+            function: self.debug_info
+                .as_ref()
+                .map(|debug_info| debug_info.function_of(ip)),
+            call_depth,
+            stack_before,
+            stack_after: &stack_after,
+        };
+
+        self.tracer
+            .as_mut()
+            .unwrap()
+            .trace(&event)
+            .map_err(|err| RuntimeError::TraceFailed(err.to_string()))
+    }
+
+    fn profile(&mut self, ip: Pointer, instruction: &Instruction, call_depth: usize) {
+        let profiler = self.profiler.as_mut().unwrap();
+        profiler.record(ip, instruction);
+
+        let function = || self.debug_info
+            .as_ref()
+            .map_or(format!("<{}>", self.ip), |debug_info| debug_info.function_of(self.ip).to_string());
+
+        // A tail call replaces the actual function without changing the call depth.
+        match (self.call_stack.len().cmp(&call_depth), instruction) {
+            (Ordering::Greater, _) => profiler.enter(&function()),
+            (Ordering::Equal, Instruction::TailCall(_)) => {
+                profiler.exit();
+                profiler.enter(&function());
+            },
+            (Ordering::Less, _) => profiler.exit(),
+            (Ordering::Equal, _) => {},
+        }
+    }
+
+    fn operand_stack(&self) -> Vec<isize> {
+        self.values[self.frame.operands_base()..].to_vec()
+    }
+
+    fn operand_depth(&self) -> usize {
+        self.values.len() - self.frame.operands_base()
+    }
+
+    fn local(&self, var_idx: usize) -> Option<isize> {
+        (var_idx < self.frame.locals && self.written[self.frame.base + var_idx])
+            .then(|| self.values[self.frame.base + var_idx])
+    }
+
+    // Paths may write locals in a different order, so writing past the last local reserves every
+    // local up to it; the skipped ones stay unset until written. A local outside of the reserved
+    // window moves the operands up.
+    fn set_local(&mut self, var_idx: usize, val: isize) {
+        if var_idx >= self.frame.locals {
+            if var_idx >= self.frame.window {
+                let operands_base = self.frame.operands_base();
+                let missing = var_idx + 1 - self.frame.window;
+
+                self.values.splice(operands_base..operands_base, iter::repeat_n(0, missing));
+                self.frame.window += missing;
+                self.reset_written(operands_base);
+            }
+            self.frame.locals = var_idx + 1;
+        }
+
+        self.values[self.frame.base + var_idx] = val;
+        self.written[self.frame.base + var_idx] = true;
+    }
+
+    // Flags above the actual frame's window are left over from returned frames, so every slot
+    // of the window from `from_idx` on starts unset.
+    fn reset_written(&mut self, from_idx: usize) {
+        self.written.truncate(from_idx);
+        self.written.resize(self.frame.operands_base(), false);
+    }
+
+    fn step(&mut self, instruction: &Instruction) -> Result<Option<ExitStatus>, RuntimeError> {
+        match instruction {
+            Instruction::LoadValue(val) => self.push_value(*val)?,
+            Instruction::Duplicate => self.duplicate()?,
+            Instruction::Drop => self.drop()?,
+            Instruction::Swap => self.swap()?,
+            Instruction::Over => self.over()?,
+            Instruction::Rotate => self.rotate()?,
+            Instruction::Pick(depth) => self.pick(*depth)?,
+            Instruction::WriteVariable(var_idx) => self.write_variable(*var_idx)?,
+            Instruction::ReadVariable(var_idx) => self.read_variable(*var_idx)?,
+            Instruction::IncrementVariable(var_idx, val) => self.increment_variable(*var_idx, *val)?,
+            Instruction::Add => self.add()?,
+            Instruction::Sub => self.sub()?,
+            Instruction::Multiply => self.multiply()?,
+            Instruction::Divide => self.divide()?,
+            Instruction::Modulo => self.modulo()?,
+            Instruction::Negate => self.negate()?,
+            Instruction::Absolute => self.absolute()?,
+            Instruction::Power => self.power()?,
+            Instruction::Min => self.min()?,
+            Instruction::Max => self.max()?,
+            Instruction::ShiftLeft => self.shift_left()?,
+            Instruction::ShiftRight => self.shift_right()?,
+            Instruction::BitAnd => self.bit_and()?,
+            Instruction::BitOr => self.bit_or()?,
+            Instruction::BitXor => self.bit_xor()?,
+            Instruction::BitNot => self.bit_not()?,
+            Instruction::Equal => self.equal()?,
+            Instruction::NotEqual => self.not_equal()?,
+            Instruction::Smaller => self.smaller()?,
+            Instruction::SmallerEqual => self.smaller_equal()?,
+            Instruction::Greater => self.greater()?,
+            Instruction::GreaterEqual => self.greater_equal()?,
+            Instruction::And => self.and()?,
+            Instruction::Or => self.or()?,
+            Instruction::Xor => self.xor()?,
+            Instruction::Not => self.not()?,
+            Instruction::Print => self.print()?,
+            Instruction::PrintVariable(var_name, var_idx) => self.print_variable(var_name, *var_idx)?,
+            Instruction::CallFunction(func_ip) => self.call_function(*func_ip)?,
+            Instruction::TailCall(func_ip) => self.tail_call(*func_ip)?,
+            Instruction::Jump(ip) => self.jump(*ip),
+            Instruction::JumpIfEqual(label_ip) => self.jie(*label_ip)?,
+            Instruction::JumpIfNotEqual(label_ip) => self.jine(*label_ip)?,
+            Instruction::JumpIfGreater(label_ip) => self.jilg(*label_ip)?,
+            Instruction::JumpIfSmaller(label_ip) => self.jils(*label_ip)?,
+            Instruction::JumpIfGreaterEqual(label_ip) => self.jilge(*label_ip)?,
+            Instruction::JumpIfSmallerEqual(label_ip) => self.jilse(*label_ip)?,
+            Instruction::JumpIfTrue(label_ip) => self.jump_if_true(*label_ip)?,
+            Instruction::JumpIfZero(label_ip) => self.jump_if_zero(*label_ip)?,
+            Instruction::JumpTable(label_ips) => self.jump_table(label_ips)?,
+            Instruction::JumpIfVariable(comparison, var_idx, val, label_ip) => {
+                self.jump_if_variable(*comparison, *var_idx, *val, *label_ip)?
+            },
+            Instruction::Return => self.return_void()?,
+            Instruction::ReturnValue => self.return_value()?,
+            Instruction::Halt => return Ok(Some(ExitStatus::Halted)),
+            Instruction::Exit(code) => return Ok(Some(ExitStatus::Exited(*code))),
+            Instruction::Ignore => {},
+        }
+
+        Ok(None)
+    }
+
+    pub fn push_value(&mut self, value: isize) -> Result<(), RuntimeError> {
+        if self.operand_depth() >= self.limits.max_operand_depth {
+            return Err(RuntimeError::StackOverflow(self.current_ip()));
+        }
+
+        self.allocate(VALUE_SIZE)?;
+        self.values.push(value);
+
+        Ok(())
+    }
+
+    pub fn pop_value(&mut self) -> Result<isize, RuntimeError> {
+        if self.operand_depth() == 0 {
+            return Err(RuntimeError::EmptyStack(self.current_ip()));
+        }
+
+        let val = self.values.pop().unwrap();
+        self.release(VALUE_SIZE);
+
+        Ok(val)
+    }
+
+    pub fn peek_value(&self) -> Result<&isize, RuntimeError> {
+        match self.operand_depth() {
+            0 => Err(RuntimeError::EmptyStack(self.current_ip())),
+            _ => Ok(self.values.last().unwrap()),
+        }
+    }
+
+    pub fn duplicate(&mut self) -> Result<(), RuntimeError> {
+        self.pick(0)
+    }
+
+    pub fn drop(&mut self) -> Result<(), RuntimeError> {
+        self.pop_value()?;
+
+        Ok(())
+    }
+
+    pub fn swap(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value(rhs)?;
+        self.push_value(lhs)?;
+
+        Ok(())
+    }
+
+    pub fn over(&mut self) -> Result<(), RuntimeError> {
+        self.pick(1)
+    }
+
+    pub fn rotate(&mut self) -> Result<(), RuntimeError> {
+        let (third, second, first) = (
+            self.pop_value()?,
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value(second)?;
+        self.push_value(third)?;
+        self.push_value(first)?;
+
+        Ok(())
+    }
+
+    pub fn pick(&mut self, depth: usize) -> Result<(), RuntimeError> {
+        if depth >= self.operand_depth() {
+            return Err(RuntimeError::EmptyStack(self.current_ip()));
+        }
+        self.push_value(self.values[self.values.len() - 1 - depth])?;
+
+        Ok(())
+    }
+
+    pub fn write_variable(&mut self, var_idx: usize) -> Result<(), RuntimeError> {
+        let val = self.pop_value()?;
+
+        if var_idx >= self.frame.locals {
+            self.allocate((var_idx + 1 - self.frame.locals) * VALUE_SIZE)?;
+        }
+        self.set_local(var_idx, val);
+
+        Ok(())
+    }
+
+    pub fn read_variable(&mut self, var_idx: usize) -> Result<(), RuntimeError> {
+        self.push_value(
+            self.local(var_idx)
+                .ok_or(RuntimeError::UninitializedVariable(self.current_ip()))?
+        )?;
+
+        Ok(())
+    }
+
+    pub fn increment_variable(&mut self, var_idx: usize, val: isize) -> Result<(), RuntimeError> {
+        let local = self.local(var_idx)
+            .ok_or(RuntimeError::UninitializedVariable(self.current_ip()))?;
+
+        self.set_local(var_idx, local.checked_add(val).ok_or(RuntimeError::ArithmeticOverflow)?);
+
+        Ok(())
+    }
+
+    pub fn add(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value(lhs.checked_add(rhs).ok_or(RuntimeError::ArithmeticOverflow)?)?;
+
+        Ok(())
+    }
+
+    pub fn sub(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value(lhs.checked_sub(rhs).ok_or(RuntimeError::ArithmeticOverflow)?)?;
+
+        Ok(())
+    }
+
+    pub fn multiply(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value(lhs.checked_mul(rhs).ok_or(RuntimeError::ArithmeticOverflow)?)?;
+
+        Ok(())
+    }
+
+    pub fn divide(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+
+        if rhs == 0 {
+            return Err(RuntimeError::DivisionByZero);
+        }
+        self.push_value(lhs.checked_div(rhs).ok_or(RuntimeError::ArithmeticOverflow)?)?;
+
+        Ok(())
+    }
+
+    pub fn modulo(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+
+        if rhs == 0 {
+            return Err(RuntimeError::DivisionByZero);
+        }
+        self.push_value(lhs.checked_rem(rhs).ok_or(RuntimeError::ArithmeticOverflow)?)?;
+
+        Ok(())
+    }
+
+    pub fn power(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+
+        if rhs < 0 {
+            return Err(RuntimeError::NegativeExponent(rhs));
+        }
+        let val = match (lhs, u32::try_from(rhs)) {
+            (_, Ok(exp)) => lhs.checked_pow(exp).ok_or(RuntimeError::ArithmeticOverflow)?,
+            (0 | 1, Err(_)) => lhs,
+            (-1, Err(_)) => if rhs % 2 == 0 { 1 } else { -1 },
+            (_, Err(_)) => return Err(RuntimeError::ArithmeticOverflow),
+        };
+        self.push_value(val)?;
+
+        Ok(())
+    }
+
+    pub fn min(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value(lhs.min(rhs))?;
+
+        Ok(())
+    }
+
+    pub fn max(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value(lhs.max(rhs))?;
+
+        Ok(())
+    }
+
+    pub fn shift_left(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value(lhs << VirtualMachine::shift_count(rhs)?)?;
+
+        Ok(())
+    }
+
+    pub fn shift_right(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value(lhs >> VirtualMachine::shift_count(rhs)?)?;
+
+        Ok(())
+    }
+
+    pub fn bit_and(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value(lhs & rhs)?;
+
+        Ok(())
+    }
+
+    pub fn bit_or(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value(lhs | rhs)?;
+
+        Ok(())
+    }
+
+    pub fn bit_xor(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value(lhs ^ rhs)?;
+
+        Ok(())
+    }
+
+    pub fn negate(&mut self) -> Result<(), RuntimeError> {
+        let val = self.pop_value()?;
+        self.push_value(val.checked_neg().ok_or(RuntimeError::ArithmeticOverflow)?)?;
+
+        Ok(())
+    }
+
+    pub fn absolute(&mut self) -> Result<(), RuntimeError> {
+        let val = self.pop_value()?;
+        self.push_value(val.checked_abs().ok_or(RuntimeError::ArithmeticOverflow)?)?;
+
+        Ok(())
+    }
+
+    pub fn bit_not(&mut self) -> Result<(), RuntimeError> {
+        let val = self.pop_value()?;
+        self.push_value(!val)?;
+
+        Ok(())
+    }
+
+    fn current_ip(&self) -> Pointer {
+        self.ip.saturating_sub(1)
+    }
+
+    pub(crate) fn shift_count(count: isize) -> Result<u32, RuntimeError> {
+        u32::try_from(count)
+            .ok()
+            .filter(|count| *count < isize::BITS)
+            .ok_or(RuntimeError::InvalidShiftCount(count))
+    }
+
+    pub fn equal(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value((lhs == rhs) as isize)?;
+
+        Ok(())
+    }
+
+    pub fn not_equal(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value((lhs != rhs) as isize)?;
+
+        Ok(())
+    }
+
+    pub fn smaller(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value((lhs < rhs) as isize)?;
+
+        Ok(())
+    }
+
+    pub fn smaller_equal(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value((lhs <= rhs) as isize)?;
+
+        Ok(())
+    }
+
+    pub fn greater(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value((lhs > rhs) as isize)?;
+
+        Ok(())
+    }
+
+    pub fn greater_equal(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+        self.push_value((lhs >= rhs) as isize)?;
+
+        Ok(())
+    }
+
+    pub fn and(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()? != 0,
+            self.pop_value()? != 0,
+        );
+        self.push_value((lhs && rhs) as isize)?;
+
+        Ok(())
+    }
+
+    pub fn or(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()? != 0,
+            self.pop_value()? != 0,
+        );
+        self.push_value((lhs || rhs) as isize)?;
+
+        Ok(())
+    }
+
+    pub fn xor(&mut self) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()? != 0,
+            self.pop_value()? != 0,
+        );
+        self.push_value((lhs != rhs) as isize)?;
+
+        Ok(())
+    }
+
+    pub fn not(&mut self) -> Result<(), RuntimeError> {
+        let val = self.pop_value()?;
+        self.push_value((val == 0) as isize)?;
+
+        Ok(())
+    }
+
+    pub fn print(&mut self) -> Result<(), RuntimeError> {
+        let line = self.peek_value()?.to_string();
+
+        self.write_line(&line)
+    }
+
+    pub fn print_variable(&mut self, var_name: &str, var_idx: usize) -> Result<(), RuntimeError> {
+        let val = self.local(var_idx)
+            .ok_or(RuntimeError::UninitializedVariable(self.current_ip()))?;
+
+        let line = format!("{} = {}", var_name, val);
+
+        self.write_line(&line)
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), RuntimeError> {
+        let output = self.output + line.len() + 1;
+
+        if let Some(max_output) = self.limits.max_output.filter(|max_output| output > *max_output) {
+            return Err(RuntimeError::OutputLimitExceeded(max_output));
+        }
+
+        self.output = output;
+
+        match self.sink.as_mut() {
+            Some(Sink(sink)) => writeln!(sink, "{}", line).map_err(|err| RuntimeError::OutputFailed(err.to_string())),
+            None => {
+                println!("{}", line);
+                Ok(())
+            },
+        }
+    }
+
+    fn allocate(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        let memory = self.memory + bytes;
+
+        if let Some(max_memory) = self.limits.max_memory.filter(|max_memory| memory > *max_memory) {
+            return Err(RuntimeError::MemoryLimitExceeded(max_memory));
+        }
+
+        self.memory = memory;
+
+        Ok(())
+    }
+
+    fn release(&mut self, bytes: usize) {
+        self.memory = self.memory.saturating_sub(bytes);
+    }
+
+    pub fn jump(&mut self, ip: Pointer) {
+        self.ip = ip;
+    }
+
+    pub fn jie(&mut self, label_ip: Pointer) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+
+        if lhs == rhs {
+            self.ip = label_ip;
+        }
+
+        Ok(())
+    }
+
+    pub fn jine(&mut self, label_ip: Pointer) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+
+        if lhs != rhs {
+            self.ip = label_ip;
+        }
+
+        Ok(())
+    }
+
+    pub fn jilg(&mut self, label_ip: Pointer) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+
+        if lhs > rhs {
+            self.ip = label_ip;
+        }
+
+        Ok(())
+    }
+
+    pub fn jils(&mut self, label_ip: Pointer) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+
+        if lhs < rhs {
+            self.ip = label_ip;
+        }
+
+        Ok(())
+    }
+
+    pub fn jilge(&mut self, label_ip: Pointer) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+
+        if lhs >= rhs {
+            self.ip = label_ip;
+        }
+
+        Ok(())
+    }
+
+    pub fn jilse(&mut self, label_ip: Pointer) -> Result<(), RuntimeError> {
+        let (rhs, lhs) = (
+            self.pop_value()?,
+            self.pop_value()?,
+        );
+
+        if lhs <= rhs {
+            self.ip = label_ip;
+        }
+
+        Ok(())
+    }
+
+    pub fn jump_if_variable(
+        &mut self,
+        comparison: Comparison,
+        var_idx: usize,
+        val: isize,
+        label_ip: Pointer,
+    ) -> Result<(), RuntimeError> {
+        let local = self.local(var_idx)
+            .ok_or(RuntimeError::UninitializedVariable(self.current_ip()))?;
+
+        if comparison.holds(local, val) {
+            self.ip = label_ip;
+        }
+
+        Ok(())
+    }
+
+    pub fn jump_if_true(&mut self, label_ip: Pointer) -> Result<(), RuntimeError> {
+        if self.pop_value()? != 0 {
+            self.ip = label_ip;
+        }
+
+        Ok(())
+    }
+
+    pub fn jump_if_zero(&mut self, label_ip: Pointer) -> Result<(), RuntimeError> {
+        if self.pop_value()? == 0 {
+            self.ip = label_ip;
+        }
+
+        Ok(())
+    }
+
+    pub fn jump_table(&mut self, label_ips: &[Pointer]) -> Result<(), RuntimeError> {
+        let idx = self.pop_value()?;
+
+        if let Some(label_ip) = usize::try_from(idx).ok().and_then(|idx| label_ips.get(idx)) {
+            self.ip = *label_ip;
+        }
+
+        Ok(())
+    }
+
+    pub fn call_function(&mut self, start_ip: Pointer) -> Result<(), RuntimeError> {
+        if self.call_stack.len() + 1 > self.limits.max_call_depth {
+            return Err(RuntimeError::StackOverflow(self.current_ip()));
+        }
+
+        self.allocate(FRAME_SIZE)?;
+
+        let window = self.windows.get(start_ip).copied().unwrap_or(0);
+        let base = self.values.len();
+        self.values.resize(base + window, 0);
+
+        let caller = mem::replace(&mut self.frame, Frame::new(self.ip, base, window));
+        self.call_stack.push(caller);
+        self.reset_written(base);
+
+        self.ip = start_ip;
+
+        Ok(())
+    }
+
+    // The called function takes over the actual frame, so it returns straight to the caller.
+    pub fn tail_call(&mut self, start_ip: Pointer) -> Result<(), RuntimeError> {
+        let values = self.operand_depth() + self.frame.locals;
+        self.release(values * VALUE_SIZE);
+
+        let window = self.windows.get(start_ip).copied().unwrap_or(0);
+        self.values.truncate(self.frame.base);
+        self.values.resize(self.frame.base + window, 0);
+        self.frame = Frame::new(self.frame.ip, self.frame.base, window);
+        self.reset_written(self.frame.base);
+
+        self.ip = start_ip;
+
+        Ok(())
+    }
+
+    // Returning from the outermost frame leaves an empty frame behind it.
+    pub fn return_void(&mut self) -> Result<(), RuntimeError> {
+        let values = self.operand_depth() + self.frame.locals;
+        self.release(FRAME_SIZE + values * VALUE_SIZE);
+
+        self.values.truncate(self.frame.base);
+        self.ip = self.frame.ip;
+        self.frame = self.call_stack
+            .pop()
+            .unwrap_or(Frame::new(self.ip, self.values.len(), 0));
+
+        Ok(())
+    }
+
+    pub fn return_value(&mut self) -> Result<(), RuntimeError> {
+        let val = self.pop_value()?;
+        self.return_void()?;
+        self.push_value(val)?;
+
+        Ok(())
+    }
+}
+
+impl Default for VirtualMachine {
+    fn default() -> Self {
+        Self::new(Limits::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Duration};
+
+    use super::*;
+    use crate::{functions::Functions, verifier::Verifier};
+
+    #[test]
+    fn new() {
+        let vm = VirtualMachine::default();
+
+        assert_eq!(vm.ip, 0);
+        assert!(vm.call_stack.is_empty());
+        assert!(vm.values.is_empty());
+    }
+
+    #[test]
+    fn run() {
+        let mut vm = VirtualMachine::default();
+
+        let program = vec![
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
+            Instruction::Ignore,            // LABEL LOOP
+            Instruction::ReadVariable(0),   // READ_VAR 'x'
+            Instruction::CallFunction(12),  // CALL TEST
+            Instruction::Add,               // ADD
+            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
+            Instruction::ReadVariable(0),   // READ_VAR 'x'
+            Instruction::LoadValue(10),     // LOAD_VAL 10
+            Instruction::JumpIfSmaller(2),  // JUMP_IF_SM LOOP
+            Instruction::ReadVariable(0),   // READ_VAR 'x'
+            Instruction::Jump(18),          // FUNC TEST
+            Instruction::LoadValue(5),      // LOAD_VAL 5
+            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
+            Instruction::ReadVariable(0),   // READ_VAR 'x'
+            Instruction::LoadValue(5),      // LOAD_VAL 5
+            Instruction::Divide,            // DIVIDE
+            Instruction::ReturnValue,       // RETURN_VAL
+        ];
+
+        assert_eq!(vm.run(program).unwrap(), ExitStatus::Finished);
+
+        assert_eq!(vm.pop_value().unwrap(), 10);
+    }
+
+    #[test]
+    fn run_verified() {
+        let program = vec![
+            Instruction::Jump(4),           // FUNC INC
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::Add,               // ADD
+            Instruction::ReturnValue,       // RETURN_VAL
+            Instruction::LoadValue(0),      // LOAD_VAL 0
+            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
+            Instruction::Ignore,            // LABEL LOOP
+            Instruction::ReadVariable(0),   // READ_VAR 'x'
+            Instruction::LoadValue(2),      // LOAD_VAL 2
+            Instruction::Add,               // ADD
+            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
+            Instruction::ReadVariable(0),   // READ_VAR 'x'
+            Instruction::LoadValue(10),     // LOAD_VAL 10
+            Instruction::JumpIfSmaller(6),  // JUMP_IF_SM LOOP
+            Instruction::ReadVariable(0),   // READ_VAR 'x'
+            Instruction::Halt,              // HALT
+        ];
+
+        let mut functions = Functions::new();
+        functions.insert("INC", (0, 3)).unwrap();
+        let verified = Verifier::verify(program, &functions).unwrap();
+
+        let mut vm = VirtualMachine::default();
+
+        assert_eq!(vm.run_verified(verified).unwrap(), ExitStatus::Halted);
+        assert!(vm.is_fast_path());
+        assert_eq!(vm.pop_value().unwrap(), 10);
+    }
+
+    #[test]
+    fn run_verified_should_return_error_on_overflow() {
+        let program = vec![
+            Instruction::LoadValue(isize::MAX), // LOAD_VAL MAX
+            Instruction::LoadValue(1),          // LOAD_VAL 1
+            Instruction::Add,                   // ADD
+        ];
+
+        let verified = Verifier::verify(program, &Functions::new()).unwrap();
+
+        let mut vm = VirtualMachine::default();
+        let trap = vm.run_verified(verified).unwrap_err();
+
+        assert!(matches!(trap.error, RuntimeError::ArithmeticOverflow));
+        assert_eq!(trap.backtrace.ips(), vec![2]);
+    }
+
+    #[test]
+    fn run_should_stop_on_halt() {
+        let mut vm = VirtualMachine::default();
+
+        let program = vec![
+            Instruction::LoadValue(1),  // LOAD_VAL 1
+            Instruction::Halt,          // HALT
+            Instruction::LoadValue(2),  // LOAD_VAL 2
+        ];
+
+        assert_eq!(vm.run(program).unwrap(), ExitStatus::Halted);
+        assert_eq!(vm.pop_value().unwrap(), 1);
+        assert!(vm.pop_value().is_err());
+    }
+
+    #[test]
+    fn run_should_stop_on_exit() {
+        let mut vm = VirtualMachine::default();
+
+        let program = vec![
+            Instruction::Exit(3),       // EXIT 3
+            Instruction::LoadValue(2),  // LOAD_VAL 2
+        ];
+
+        let status = vm.run(program).unwrap();
+
+        assert_eq!(status, ExitStatus::Exited(3));
+        assert_eq!(status.code(), 3);
+        assert!(vm.pop_value().is_err());
+    }
+
+    #[test]
+    fn run_should_return_error_when_out_of_fuel() {
+        let mut vm = VirtualMachine::default();
+        vm.set_fuel(Some(100));
+
+        let program = vec![
+            Instruction::Ignore,   // LABEL LOOP
+            Instruction::Jump(0),  // JUMP LOOP
+        ];
+
+        assert!(matches!(vm.run(program), Err(Trap { error: RuntimeError::OutOfFuel(0), .. })));
+        assert_eq!(vm.fuel(), Some(0));
+    }
+
+    #[test]
+    fn resume_should_continue_after_adding_fuel() {
+        let mut vm = VirtualMachine::default();
+        vm.set_fuel(Some(4));
+
+        let program = vec![
+            Instruction::LoadValue(0),          // LOAD_VAL 0
+            Instruction::WriteVariable(0),      // WRITE_VAR 'x'
+            Instruction::Ignore,                // LABEL LOOP
+            Instruction::ReadVariable(0),       // READ_VAR 'x'
+            Instruction::LoadValue(1),          // LOAD_VAL 1
+            Instruction::Add,                   // ADD
+            Instruction::WriteVariable(0),      // WRITE_VAR 'x'
+            Instruction::ReadVariable(0),       // READ_VAR 'x'
+            Instruction::LoadValue(5),          // LOAD_VAL 5
+            Instruction::JumpIfSmaller(2),      // JUMP_IF_SM LOOP
+        ];
+
+        let mut result = vm.run(program);
+        let mut refuels = 0;
+
+        while let Err(Trap { error: RuntimeError::OutOfFuel(_), .. }) = result {
+            vm.add_fuel(4);
+            refuels += 1;
+            result = vm.resume();
+        }
+
+        assert_eq!(result.unwrap(), ExitStatus::Finished);
+        assert_eq!(refuels, 10);
+        assert_eq!(vm.local(0), Some(5));
+    }
+
+    #[test]
+    fn run_should_return_stack_overflow_for_unbounded_recursion() {
+        let mut vm = VirtualMachine::new(Limits { max_call_depth: 3, ..Limits::default() });
+
+        let program = vec![
+            Instruction::Jump(3),           // FUNC RECURSE
+            Instruction::CallFunction(1),   // CALL RECURSE
+            Instruction::Return,            // RETURN
+            Instruction::CallFunction(1),   // CALL RECURSE
+        ];
+
+        match vm.run(program) {
+            Err(Trap { error: RuntimeError::StackOverflow(1), backtrace }) => {
+                assert_eq!(backtrace.ips(), vec![1, 1, 1, 3]);
+            },
+            result => panic!("Expected stack overflow, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn run_should_keep_call_depth_constant_for_tail_recursion() {
+        let mut vm = VirtualMachine::new(Limits { max_call_depth: 3, ..Limits::default() });
+        vm.set_fuel(Some(10_000));
+
+        let program = vec![
+            Instruction::Jump(5),           // FUNC RECURSE
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
+            Instruction::TailCall(1),       // TAIL_CALL RECURSE
+            Instruction::Return,            // RETURN
+            Instruction::CallFunction(1),   // CALL RECURSE
+        ];
+
+        assert!(matches!(vm.run(program), Err(Trap { error: RuntimeError::OutOfFuel(_), .. })));
+        assert_eq!(vm.call_stack.len(), 1);
+        assert!(vm.memory() <= 2 * FRAME_SIZE + VALUE_SIZE);
+    }
+
+    #[test]
+    fn run_should_return_from_tail_call_to_caller() {
+        let mut vm = VirtualMachine::default();
+
+        let program = vec![
+            Instruction::Jump(3),           // FUNC THREE
+            Instruction::LoadValue(3),      // LOAD_VAL 3
+            Instruction::ReturnValue,       // RETURN_VAL
+            Instruction::Jump(6),           // FUNC FORWARD
+            Instruction::TailCall(1),       // TAIL_CALL THREE
+            Instruction::ReturnValue,       // RETURN_VAL
+            Instruction::CallFunction(4),   // CALL FORWARD
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::Add,               // ADD
+        ];
+
+        assert_eq!(vm.run(program).unwrap(), ExitStatus::Finished);
+        assert_eq!(vm.memory(), FRAME_SIZE + VALUE_SIZE);
+        assert!(vm.call_stack.is_empty());
+        assert_eq!(vm.pop_value().unwrap(), 4);
+    }
+
+    #[test]
+    fn run_should_return_backtrace_with_debug_info() {
+        let mut functions = Functions::new();
+        functions.insert("DIV", (0, 3)).unwrap();
+
+        let mut vm = VirtualMachine::default();
+        vm.set_debug_info(Some(DebugInfo::new(functions, vec![1, 2, 3, 4, 6, 7, 8])));
+
+        let program = vec![
+            Instruction::Jump(4),           // FUNC DIV
+            Instruction::LoadValue(0),      // LOAD_VAL 0
+            Instruction::Divide,            // DIVIDE
+            Instruction::ReturnValue,       // RETURN_VAL
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::CallFunction(1),   // CALL DIV
+            Instruction::Print,             // PRINT
+        ];
+
+        let trap = vm.run(program).unwrap_err();
+
+        assert!(matches!(trap.error, RuntimeError::EmptyStack(2)));
+        assert_eq!(
+            trap.backtrace.frames(),
+            &[
+                BacktraceFrame { ip: 2, function: Some("DIV".to_string()), line: Some(3) },
+                BacktraceFrame { ip: 5, function: Some("MAIN".to_string()), line: Some(7) },
+            ]
+        );
+    }
+
+    #[test]
+    fn run_with_profiler() {
+        let mut functions = Functions::new();
+        functions.insert("TEST", (0, 2)).unwrap();
+
+        let mut vm = VirtualMachine::default();
+        vm.set_debug_info(Some(DebugInfo::new(functions, vec![])));
+        vm.set_profiler(Some(Profiler::new()));
+
+        let program = vec![
+            Instruction::Jump(3),           // FUNC TEST
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::ReturnValue,       // RETURN_VAL
+            Instruction::CallFunction(1),   // CALL TEST
+            Instruction::CallFunction(1),   // CALL TEST
+        ];
+
+        vm.run(program).unwrap();
+
+        let profiler = vm.profiler().unwrap();
+        let test = profiler.function("TEST").unwrap();
+
+        assert_eq!(profiler.executed(), 7);
+        assert_eq!((test.calls, test.inclusive, test.exclusive), (2, 4, 4));
+        assert_eq!(profiler.folded(), "MAIN 3\nMAIN;TEST 4\n");
+    }
+
+    #[test]
+    fn run_with_coverage() {
+        let mut vm = VirtualMachine::default();
+        vm.set_coverage(Some(Coverage::new()));
+
+        let program = vec![
+            Instruction::LoadValue(0),      // LOAD_VAL 0
+            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
+            Instruction::Ignore,            // LABEL LOOP
+            Instruction::ReadVariable(0),   // READ_VAR 'x'
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::Add,               // ADD
+            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
+            Instruction::ReadVariable(0),   // READ_VAR 'x'
+            Instruction::LoadValue(3),      // LOAD_VAL 3
+            Instruction::JumpIfSmaller(2),  // JUMP_IF_SM LOOP
+        ];
+
+        vm.run(program).unwrap();
+
+        let coverage = vm.coverage().unwrap();
+
+        assert_eq!(coverage.hits(0), 1);
+        assert_eq!(coverage.hits(9), 3);
+        assert_eq!(coverage.branch(9), Some((2, 1)));
+    }
+
+    #[test]
+    fn push_value_should_return_stack_overflow_when_operand_stack_is_full() {
+        let mut vm = VirtualMachine::new(Limits { max_operand_depth: 2, ..Limits::default() });
+
+        vm.push_value(1).unwrap();
+        vm.push_value(2).unwrap();
+
+        assert!(matches!(vm.push_value(3), Err(RuntimeError::StackOverflow(_))));
+    }
+
+    #[test]
+    fn run_should_return_error_when_memory_limit_exceeded() {
+        let mut vm = VirtualMachine::new(Limits { max_memory: Some(FRAME_SIZE + 2 * VALUE_SIZE), ..Limits::default() });
+
+        let program = vec![
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
+            Instruction::LoadValue(2),      // LOAD_VAL 2
+            Instruction::LoadValue(3),      // LOAD_VAL 3
+        ];
+
+        assert!(matches!(vm.run(program), Err(Trap { error: RuntimeError::MemoryLimitExceeded(_), .. })));
+        assert_eq!(vm.memory(), FRAME_SIZE + 2 * VALUE_SIZE);
+    }
+
+    #[test]
+    fn run_should_release_memory_of_returned_frames() {
+        let mut vm = VirtualMachine::default();
+
+        let program = vec![
+            Instruction::Jump(5),           // FUNC TEST
+            Instruction::LoadValue(1),      // LOAD_VAL 1
+            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
+            Instruction::ReadVariable(0),   // READ_VAR 'x'
+            Instruction::ReturnValue,       // RETURN_VAL
+            Instruction::CallFunction(1),   // CALL TEST
+        ];
+
+        vm.run(program).unwrap();
+
+        assert_eq!(vm.memory(), FRAME_SIZE + VALUE_SIZE);
+    }
+
+    #[test]
+    fn run_should_accept_locals_written_out_of_order() {
+        let program = vec![
+            Instruction::LoadValue(1),                          // LOAD_VAL 1
+            Instruction::LoadValue(1),                          // LOAD_VAL 1
+            Instruction::JumpIfEqual(5),                        // JUMP_IF_EQ SKIP
+            Instruction::LoadValue(5),                          // LOAD_VAL 5
+            Instruction::WriteVariable(0),                      // WRITE_VAR 'x'
+            Instruction::Ignore,                                // LABEL SKIP
+            Instruction::LoadValue(7),                          // LOAD_VAL 7
+            Instruction::WriteVariable(1),                      // WRITE_VAR 'y'
+            Instruction::PrintVariable("y".to_string(), 1),     // PRINT 'y'
+        ];
+
+        let mut vm = VirtualMachine::default();
+        vm.run(program.clone()).unwrap();
+
+        assert_eq!(vm.local(0), None);
+        assert_eq!(vm.local(1), Some(7));
+        assert_eq!(vm.memory(), FRAME_SIZE + 2 * VALUE_SIZE);
+
+        let mut vm = VirtualMachine::default();
+        vm.run_verified(Verifier::verify(program, &Functions::new()).unwrap()).unwrap();
+
+        assert_eq!(vm.local(1), Some(7));
+    }
+
+    #[test]
+    fn run_should_return_error_when_reading_skipped_local() {
+        let mut vm = VirtualMachine::default();
+
+        let program = vec![
+            Instruction::LoadValue(7),                          // LOAD_VAL 7
+            Instruction::WriteVariable(1),                      // WRITE_VAR 'y'
+            Instruction::PrintVariable("x".to_string(), 0),     // PRINT 'x'
+        ];
+
+        assert!(matches!(vm.run(program), Err(Trap { error: RuntimeError::UninitializedVariable(2), .. })));
+        assert_eq!(vm.local(0), None);
+        assert_eq!(vm.local(1), Some(7));
+    }
+
+    #[test]
+    fn run_should_not_keep_locals_of_returned_frames() {
+        let mut vm = VirtualMachine::default();
+
+        let program = vec![
+            Instruction::Jump(4),                               // FUNC WRITE
+            Instruction::LoadValue(5),                          // LOAD_VAL 5
+            Instruction::WriteVariable(0),                      // WRITE_VAR 'x'
+            Instruction::Return,                                // RETURN
+            Instruction::Jump(7),                               // FUNC READ
+            Instruction::PrintVariable("x".to_string(), 0),     // PRINT 'x'
+            Instruction::Return,                                // RETURN
+            Instruction::CallFunction(1),                       // CALL WRITE
+            Instruction::CallFunction(5),                       // CALL READ
+        ];
+
+        assert!(matches!(vm.run(program), Err(Trap { error: RuntimeError::UninitializedVariable(5), .. })));
+    }
+
+    #[test]
+    fn run_should_return_error_when_output_limit_exceeded() {
+        let mut vm = VirtualMachine::new(Limits { max_output: Some(4), ..Limits::default() });
+
+        let program = vec![
+            Instruction::LoadValue(10),     // LOAD_VAL 10
+            Instruction::Print,             // PRINT
+            Instruction::Print,             // PRINT
+        ];
+
+        assert!(matches!(vm.run(program), Err(Trap { error: RuntimeError::OutputLimitExceeded(4), .. })));
+        assert_eq!(vm.output, 3);
+    }
+
+    #[test]
+    fn run_should_return_error_when_time_limit_exceeded() {
+        let mut vm = VirtualMachine::new(Limits { max_time: Some(Duration::from_millis(10)), ..Limits::default() });
+
+        let program = vec![
+            Instruction::Ignore,   // LABEL LOOP
+            Instruction::Jump(0),  // JUMP LOOP
+        ];
+
+        assert!(matches!(vm.run(program), Err(Trap { error: RuntimeError::TimeLimitExceeded(_), .. })));
+    }
+
+    #[test]
+    fn resume_should_not_count_time_paused_out_of_fuel() {
+        let mut vm = VirtualMachine::new(Limits { max_time: Some(Duration::from_millis(10)), ..Limits::default() });
+        vm.set_fuel(Some(2));
+
+        let program = vec![
+            Instruction::LoadValue(1),  // LOAD_VAL 1
+            Instruction::Print,         // PRINT
+            Instruction::LoadValue(2),  // LOAD_VAL 2
+            Instruction::Print,         // PRINT
+        ];
+
+        assert!(matches!(vm.run(program), Err(Trap { error: RuntimeError::OutOfFuel(_), .. })));
+
+        thread::sleep(Duration::from_millis(20));
+        vm.add_fuel(2);
+
+        assert_eq!(vm.resume().unwrap(), ExitStatus::Finished);
+    }
+
+    #[test]
+    fn push_value() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(10).unwrap();
+
+        assert_eq!(vm.values, vec![10]);
+        assert_eq!(vm.operand_stack(), vec![10]);
+    }
+
+    #[test]
+    fn pop_value() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(10).unwrap();
+        let val = vm.pop_value().unwrap();
+
+        assert_eq!(val, 10);
+    }
+
+    #[test]
+    fn peek_value() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(10).unwrap();
+        let val = vm.peek_value().unwrap();
+
+        assert_eq!(val, &10);
+    }
+
+    #[test]
+    fn duplicate() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(1).unwrap();
+        vm.push_value(2).unwrap();
+        vm.duplicate().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 2);
+        assert_eq!(vm.pop_value().unwrap(), 2);
+        assert_eq!(vm.pop_value().unwrap(), 1);
+        assert!(vm.pop_value().is_err());
+    }
+
+    #[test]
+    fn drop() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(1).unwrap();
+        vm.push_value(2).unwrap();
+        vm.drop().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 1);
+        assert!(vm.pop_value().is_err());
+    }
+
+    #[test]
+    fn swap() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(1).unwrap();
+        vm.push_value(2).unwrap();
+        vm.swap().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 1);
+        assert_eq!(vm.pop_value().unwrap(), 2);
+        assert!(vm.pop_value().is_err());
+    }
+
+    #[test]
+    fn over() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(1).unwrap();
+        vm.push_value(2).unwrap();
+        vm.over().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 1);
+        assert_eq!(vm.pop_value().unwrap(), 2);
+        assert_eq!(vm.pop_value().unwrap(), 1);
+        assert!(vm.pop_value().is_err());
+    }
+
+    #[test]
+    fn rotate() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(1).unwrap();
+        vm.push_value(2).unwrap();
+        vm.push_value(3).unwrap();
+        vm.rotate().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 1);
+        assert_eq!(vm.pop_value().unwrap(), 3);
+        assert_eq!(vm.pop_value().unwrap(), 2);
+        assert!(vm.pop_value().is_err());
+    }
+
+    #[test]
+    fn pick() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(1).unwrap();
+        vm.push_value(2).unwrap();
+        vm.push_value(3).unwrap();
+        vm.pick(2).unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 1);
+        assert_eq!(vm.pop_value().unwrap(), 3);
+        assert_eq!(vm.pop_value().unwrap(), 2);
+        assert_eq!(vm.pop_value().unwrap(), 1);
+        assert!(vm.pop_value().is_err());
+    }
 
-#[derive(Debug, PartialEq)]
-pub struct VirtualMachine {
-    ip: Pointer,
-    call_stack: Stack<Frame<isize>>,
-}
+    #[test]
+    fn pick_should_return_error_on_underflow() {
+        let mut vm = VirtualMachine::default();
 
-impl VirtualMachine {
-    pub fn new() -> Self {
-        Self {
-            ip: 0,
-            call_stack: Stack::with_capacity(CALL_STACK_DEFAULT_CAPACITY),
-        }
+        vm.push_value(1).unwrap();
+        vm.ip = 8;
+
+        assert!(matches!(vm.pick(1), Err(RuntimeError::EmptyStack(7))));
     }
 
-    pub fn run(&mut self, program: Vec<Instruction>) {
-        let main_frame = Frame::new(program.len());
-        self.call_stack.push(main_frame);
+    #[test]
+    fn run_should_return_error_with_instruction_pointer_on_underflow() {
+        let mut vm = VirtualMachine::default();
 
-        while let Some(instruction) = program.get(self.ip) {
-            match instruction {
-                Instruction::LoadValue(val) => self.push_value(*val),
-                Instruction::WriteVariable(var_idx) => self.write_variable(*var_idx),
-                Instruction::ReadVariable(var_idx) => self.read_variable(*var_idx),
-                Instruction::Add => self.add(),
-                Instruction::Sub => self.sub(),
-                Instruction::Multiply => self.multiply(),
-                Instruction::Divide => self.divide(),
-                Instruction::Print => self.print(),
-                Instruction::PrintVariable(var_name, var_idx) => self.print_variable(var_name, *var_idx),
-                Instruction::CallFunction(func_ip) => self.call_function(*func_ip),
-                Instruction::Jump(ip) => self.jump(*ip),
-                Instruction::JumpIfEqual(label_ip) => self.jie(*label_ip),
-                Instruction::JumpIfNotEqual(label_ip) => self.jine(*label_ip),
-                Instruction::JumpIfGreater(label_ip) => self.jilg(*label_ip),
-                Instruction::JumpIfSmaller(label_ip) => self.jils(*label_ip),
-                Instruction::JumpIfGreaterEqual(label_ip) => self.jilge(*label_ip),
-                Instruction::JumpIfSmallerEqual(label_ip) => self.jilse(*label_ip),
-                Instruction::Return => self.return_void(),
-                Instruction::ReturnValue => self.return_value(),
-                Instruction::Ignore => {},
-            }
+        let program = vec![
+            Instruction::LoadValue(1),  // LOAD_VAL 1
+            Instruction::Duplicate,     // DUP
+            Instruction::Add,           // ADD
+            Instruction::Swap,          // SWAP
+        ];
 
-            self.ip += 1;
-        }
+        assert!(matches!(vm.run(program), Err(Trap { error: RuntimeError::EmptyStack(3), .. })));
     }
 
-    pub fn push_value(&mut self, value: isize) {
-        self.call_stack
-            .peek_mut()
-            .unwrap()
-            .push_value(value);
+    #[test]
+    fn write_variable() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(10).unwrap();
+        vm.write_variable(0).unwrap();
+
+        assert_eq!(vm.local(0), Some(10));
+        assert!(vm.operand_stack().is_empty());
     }
 
-    pub fn pop_value(&mut self) -> isize {
-        self.call_stack
-            .peek_mut()
-            .unwrap()
-            .pop_value()
-            .unwrap()
+    #[test]
+    fn read_variable() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(10).unwrap();
+        vm.write_variable(0).unwrap();
+        vm.read_variable(0).unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 10);
     }
 
-    pub fn peek_value(&self) -> &isize {
-        self.call_stack
-            .peek()
-            .unwrap()
-            .peek_value()
-            .unwrap()
+    #[test]
+    fn add() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(5).unwrap();
+        vm.push_value(10).unwrap();
+        vm.add().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 15);
     }
 
-    pub fn write_variable(&mut self, var_idx: usize) {
-        let val = self.pop_value();
+    #[test]
+    fn sub() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(10).unwrap();
+        vm.push_value(3).unwrap();
+        vm.sub().unwrap();
 
-        self.call_stack
-            .peek_mut()
-            .unwrap()
-            .set_local(var_idx, val);
+        assert_eq!(vm.pop_value().unwrap(), 7);
     }
 
-    pub fn read_variable(&mut self, var_idx: usize) {
-        self.push_value(
-            *self.call_stack
-                .peek()
-                .unwrap()
-                .get_local(var_idx)
-                .unwrap()
-        );
+    #[test]
+    fn multiply() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(5).unwrap();
+        vm.push_value(10).unwrap();
+        vm.multiply().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 50);
     }
 
-    pub fn add(&mut self) {
-        let (rhs, lhs) = (
-            self.pop_value(),
-            self.pop_value(),
-        );
-        self.push_value(lhs + rhs);
+    #[test]
+    fn divide() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(10).unwrap();
+        vm.push_value(5).unwrap();
+        vm.divide().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 2);
     }
 
-    pub fn sub(&mut self) {
-        let (rhs, lhs) = (
-            self.pop_value(),
-            self.pop_value(),
-        );
-        self.push_value(lhs - rhs);
+    #[test]
+    fn add_should_return_error_on_overflow() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(isize::MAX).unwrap();
+        vm.push_value(1).unwrap();
+
+        assert!(matches!(vm.add(), Err(RuntimeError::ArithmeticOverflow)));
     }
 
-    pub fn multiply(&mut self) {
-        let (rhs, lhs) = (
-            self.pop_value(),
-            self.pop_value(),
-        );
-        self.push_value(lhs * rhs);
+    #[test]
+    fn divide_should_return_error_on_division_by_zero() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(10).unwrap();
+        vm.push_value(0).unwrap();
+
+        assert!(matches!(vm.divide(), Err(RuntimeError::DivisionByZero)));
     }
 
-    pub fn divide(&mut self) {
-        let (rhs, lhs) = (
-            self.pop_value(),
-            self.pop_value(),
-        );
-        self.push_value(lhs / rhs);
+    #[test]
+    fn modulo() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(-7).unwrap();
+        vm.push_value(3).unwrap();
+        vm.modulo().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), -1);
     }
 
-    pub fn print(&self) {
-        println!("{}", self.peek_value());
+    #[test]
+    fn modulo_should_return_error_on_division_by_zero() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(10).unwrap();
+        vm.push_value(0).unwrap();
+
+        assert!(matches!(vm.modulo(), Err(RuntimeError::DivisionByZero)));
     }
 
-    pub fn print_variable(&self, var_name: &str, var_idx: usize) {
-        let val = self.call_stack
-            .peek()
-            .unwrap()
-            .get_local(var_idx)
-            .unwrap();
+    #[test]
+    fn modulo_should_return_error_on_overflow() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(isize::MIN).unwrap();
+        vm.push_value(-1).unwrap();
 
-        println!("{} = {}", var_name, val);
+        assert!(matches!(vm.modulo(), Err(RuntimeError::ArithmeticOverflow)));
     }
 
-    pub fn jump(&mut self, ip: Pointer) {
-        self.ip = ip;
+    #[test]
+    fn negate() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(5).unwrap();
+        vm.negate().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), -5);
     }
 
-    pub fn jie(&mut self, label_ip: Pointer) {
-        let (rhs, lhs) = (
-            self.pop_value(),
-            self.pop_value(),
-        );
+    #[test]
+    fn negate_should_return_error_on_overflow() {
+        let mut vm = VirtualMachine::default();
 
-        if lhs == rhs {
-            self.ip = label_ip;
-        }
+        vm.push_value(isize::MIN).unwrap();
+
+        assert!(matches!(vm.negate(), Err(RuntimeError::ArithmeticOverflow)));
     }
 
-    pub fn jine(&mut self, label_ip: Pointer) {
-        let (rhs, lhs) = (
-            self.pop_value(),
-            self.pop_value(),
-        );
+    #[test]
+    fn absolute() {
+        let mut vm = VirtualMachine::default();
 
-        if lhs != rhs {
-            self.ip = label_ip;
-        }
+        vm.push_value(-5).unwrap();
+        vm.absolute().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 5);
     }
 
-    pub fn jilg(&mut self, label_ip: Pointer) {
-        let (rhs, lhs) = (
-            self.pop_value(),
-            self.pop_value(),
-        );
+    #[test]
+    fn absolute_should_return_error_on_overflow() {
+        let mut vm = VirtualMachine::default();
 
-        if lhs > rhs {
-            self.ip = label_ip;
-        }
+        vm.push_value(isize::MIN).unwrap();
+
+        assert!(matches!(vm.absolute(), Err(RuntimeError::ArithmeticOverflow)));
     }
 
-    pub fn jils(&mut self, label_ip: Pointer) {
-        let (rhs, lhs) = (
-            self.pop_value(),
-            self.pop_value(),
-        );
+    #[test]
+    fn power() {
+        let mut vm = VirtualMachine::default();
 
-        if lhs < rhs {
-            self.ip = label_ip;
-        }
+        vm.push_value(-3).unwrap();
+        vm.push_value(3).unwrap();
+        vm.power().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), -27);
+
+        vm.push_value(7).unwrap();
+        vm.push_value(0).unwrap();
+        vm.power().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 1);
+
+        vm.push_value(-1).unwrap();
+        vm.push_value(isize::MAX).unwrap();
+        vm.power().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), -1);
     }
 
-    pub fn jilge(&mut self, label_ip: Pointer) {
-        let (rhs, lhs) = (
-            self.pop_value(),
-            self.pop_value(),
-        );
+    #[test]
+    fn power_should_return_error_on_negative_exponent() {
+        let mut vm = VirtualMachine::default();
 
-        if lhs >= rhs {
-            self.ip = label_ip;
-        }
+        vm.push_value(2).unwrap();
+        vm.push_value(-1).unwrap();
+
+        assert!(matches!(vm.power(), Err(RuntimeError::NegativeExponent(-1))));
     }
 
-    pub fn jilse(&mut self, label_ip: Pointer) {
-        let (rhs, lhs) = (
-            self.pop_value(),
-            self.pop_value(),
-        );
+    #[test]
+    fn power_should_return_error_on_overflow() {
+        let mut vm = VirtualMachine::default();
 
-        if lhs <= rhs {
-            self.ip = label_ip;
-        }
+        vm.push_value(2).unwrap();
+        vm.push_value(64).unwrap();
+
+        assert!(matches!(vm.power(), Err(RuntimeError::ArithmeticOverflow)));
     }
 
-    pub fn call_function(&mut self, start_ip: Pointer) {
-        self.call_stack.push(
-            Frame::new(
-                self.ip,
-            )
-        );
+    #[test]
+    fn min() {
+        let mut vm = VirtualMachine::default();
 
-        self.ip = start_ip;
+        vm.push_value(-3).unwrap();
+        vm.push_value(3).unwrap();
+        vm.min().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), -3);
     }
 
-    pub fn return_void(&mut self) {
-        self.ip = self.call_stack.pop().unwrap().ip;
+    #[test]
+    fn max() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(-3).unwrap();
+        vm.push_value(3).unwrap();
+        vm.max().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 3);
     }
 
-    pub fn return_value(&mut self) {
-        let val = self.pop_value();
-        self.ip = self.call_stack.pop().unwrap().ip;
-        self.push_value(val);
+    #[test]
+    fn shift_left() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(3).unwrap();
+        vm.push_value(2).unwrap();
+        vm.shift_left().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 12);
+
+        vm.push_value(-1).unwrap();
+        vm.push_value(isize::BITS as isize - 1).unwrap();
+        vm.shift_left().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), isize::MIN);
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    #[test]
+    fn shift_right() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(12).unwrap();
+        vm.push_value(2).unwrap();
+        vm.shift_right().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 3);
+
+        vm.push_value(-8).unwrap();
+        vm.push_value(1).unwrap();
+        vm.shift_right().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), -4);
+    }
 
     #[test]
-    fn new() {
-        let vm = VirtualMachine::new();
+    fn shift_should_return_error_on_invalid_count() {
+        let mut vm = VirtualMachine::default();
 
-        assert_eq!(vm.ip, 0);
-        assert!(vm.call_stack.is_empty());
+        vm.push_value(1).unwrap();
+        vm.push_value(-1).unwrap();
+
+        assert!(matches!(vm.shift_left(), Err(RuntimeError::InvalidShiftCount(-1))));
+
+        vm.push_value(1).unwrap();
+        vm.push_value(isize::BITS as isize).unwrap();
+
+        assert!(matches!(vm.shift_right(), Err(RuntimeError::InvalidShiftCount(_))));
     }
 
     #[test]
-    fn run() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+    fn bit_and() {
+        let mut vm = VirtualMachine::default();
 
-        let program = vec![
-            Instruction::LoadValue(1),      // LOAD_VAL 1
-            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
-            Instruction::Ignore,            // LABEL LOOP
-            Instruction::ReadVariable(0),   // READ_VAR 'x'
-            Instruction::CallFunction(11),  // CALL TEST
-            Instruction::Add,               // ADD
-            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
-            Instruction::ReadVariable(0),   // READ_VAR 'x'
-            Instruction::LoadValue(10),     // LOAD_VAL 10
-            Instruction::JumpIfSmaller(2),  // JUMP_IF_SM LOOP
-            Instruction::ReadVariable(0),   // READ_VAR 'x'
-            Instruction::Jump(17),          // FUNC TEST
-            Instruction::LoadValue(5),      // LOAD_VAL 5
-            Instruction::WriteVariable(0),  // WRITE_VAR 'x'
-            Instruction::ReadVariable(0),   // READ_VAR 'x'
-            Instruction::LoadValue(5),      // LOAD_VAL 5
-            Instruction::Divide,            // DIVIDE
-            Instruction::ReturnValue,       // RETURN_VAL
-        ];
+        vm.push_value(0b1100).unwrap();
+        vm.push_value(0b1010).unwrap();
+        vm.bit_and().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 0b1000);
+    }
 
-        vm.run(program);
+    #[test]
+    fn bit_or() {
+        let mut vm = VirtualMachine::default();
 
-        let actual_frame = vm.call_stack.peek_mut().unwrap();
+        vm.push_value(0b1100).unwrap();
+        vm.push_value(0b1010).unwrap();
+        vm.bit_or().unwrap();
 
-        assert_eq!(actual_frame.pop_value().unwrap(), 10);
+        assert_eq!(vm.pop_value().unwrap(), 0b1110);
     }
 
     #[test]
-    fn push_value() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+    fn bit_xor() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(0b1100).unwrap();
+        vm.push_value(0b1010).unwrap();
+        vm.bit_xor().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 0b0110);
+    }
 
-        vm.push_value(10);
+    #[test]
+    fn bit_not() {
+        let mut vm = VirtualMachine::default();
 
-        let mut expected_call_stack = Stack::new();
-        let mut expected_frame = Frame::new(0);
-        expected_frame.get_operand_stack_mut().push(10);
-        expected_call_stack.push(expected_frame);
+        vm.push_value(0).unwrap();
+        vm.bit_not().unwrap();
 
-        assert_eq!(vm.call_stack, expected_call_stack);
+        assert_eq!(vm.pop_value().unwrap(), -1);
     }
 
     #[test]
-    fn pop_value() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+    fn equal() {
+        let mut vm = VirtualMachine::default();
 
-        vm.push_value(10);
-        let val = vm.pop_value();
+        vm.push_value(5).unwrap();
+        vm.push_value(5).unwrap();
+        vm.equal().unwrap();
 
-        assert_eq!(val, 10);
+        assert_eq!(vm.pop_value().unwrap(), 1);
+
+        vm.push_value(5).unwrap();
+        vm.push_value(10).unwrap();
+        vm.equal().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 0);
     }
 
     #[test]
-    fn peek_value() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+    fn not_equal() {
+        let mut vm = VirtualMachine::default();
 
-        vm.push_value(10);
-        let val = vm.peek_value();
+        vm.push_value(5).unwrap();
+        vm.push_value(10).unwrap();
+        vm.not_equal().unwrap();
 
-        assert_eq!(val, &10);
+        assert_eq!(vm.pop_value().unwrap(), 1);
+
+        vm.push_value(5).unwrap();
+        vm.push_value(5).unwrap();
+        vm.not_equal().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 0);
     }
 
     #[test]
-    fn write_variable() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+    fn smaller() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(5).unwrap();
+        vm.push_value(10).unwrap();
+        vm.smaller().unwrap();
 
-        vm.push_value(10);
-        vm.write_variable(0);
+        assert_eq!(vm.pop_value().unwrap(), 1);
 
-        let actual_frame = vm.call_stack.peek().unwrap();
+        vm.push_value(10).unwrap();
+        vm.push_value(5).unwrap();
+        vm.smaller().unwrap();
 
-        assert_eq!(actual_frame.get_local(0).unwrap(), &10);
+        assert_eq!(vm.pop_value().unwrap(), 0);
     }
 
     #[test]
-    fn read_variable() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+    fn smaller_equal() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(5).unwrap();
+        vm.push_value(5).unwrap();
+        vm.smaller_equal().unwrap();
 
-        vm.push_value(10);
-        vm.write_variable(0);
-        vm.read_variable(0);
+        assert_eq!(vm.pop_value().unwrap(), 1);
 
-        let actual_frame = vm.call_stack.peek_mut().unwrap();
+        vm.push_value(10).unwrap();
+        vm.push_value(5).unwrap();
+        vm.smaller_equal().unwrap();
 
-        assert_eq!(actual_frame.pop_value().unwrap(), 10);
+        assert_eq!(vm.pop_value().unwrap(), 0);
     }
 
     #[test]
-    fn add() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+    fn greater() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(10).unwrap();
+        vm.push_value(5).unwrap();
+        vm.greater().unwrap();
 
-        vm.push_value(5);
-        vm.push_value(10);
-        vm.add();
+        assert_eq!(vm.pop_value().unwrap(), 1);
 
-        assert_eq!(vm.pop_value(), 15);
+        vm.push_value(5).unwrap();
+        vm.push_value(10).unwrap();
+        vm.greater().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 0);
     }
 
     #[test]
-    fn sub() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+    fn greater_equal() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(5).unwrap();
+        vm.push_value(5).unwrap();
+        vm.greater_equal().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 1);
 
-        vm.push_value(10);
-        vm.push_value(3);
-        vm.sub();
+        vm.push_value(5).unwrap();
+        vm.push_value(10).unwrap();
+        vm.greater_equal().unwrap();
 
-        assert_eq!(vm.pop_value(), 7);
+        assert_eq!(vm.pop_value().unwrap(), 0);
     }
 
     #[test]
-    fn multiply() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+    fn and() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(3).unwrap();
+        vm.push_value(-1).unwrap();
+        vm.and().unwrap();
 
-        vm.push_value(5);
-        vm.push_value(10);
-        vm.multiply();
+        assert_eq!(vm.pop_value().unwrap(), 1);
 
-        assert_eq!(vm.pop_value(), 50);
+        vm.push_value(3).unwrap();
+        vm.push_value(0).unwrap();
+        vm.and().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 0);
     }
 
     #[test]
-    fn divide() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+    fn or() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(0).unwrap();
+        vm.push_value(7).unwrap();
+        vm.or().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 1);
+
+        vm.push_value(0).unwrap();
+        vm.push_value(0).unwrap();
+        vm.or().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 0);
+    }
+
+    #[test]
+    fn xor() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(2).unwrap();
+        vm.push_value(0).unwrap();
+        vm.xor().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 1);
+
+        vm.push_value(2).unwrap();
+        vm.push_value(5).unwrap();
+        vm.xor().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 0);
+    }
 
-        vm.push_value(10);
-        vm.push_value(5);
-        vm.divide();
+    #[test]
+    fn not() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(0).unwrap();
+        vm.not().unwrap();
+
+        assert_eq!(vm.pop_value().unwrap(), 1);
+
+        vm.push_value(-4).unwrap();
+        vm.not().unwrap();
 
-        assert_eq!(vm.pop_value(), 2);
+        assert_eq!(vm.pop_value().unwrap(), 0);
     }
 
     #[test]
     fn jump() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+        let mut vm = VirtualMachine::default();
 
         vm.jump(10);
 
@@ -409,136 +2331,172 @@ mod test {
 
     #[test]
     fn jie() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+        let mut vm = VirtualMachine::default();
 
-        vm.push_value(5);
-        vm.push_value(5);
-        vm.jie(10);
+        vm.push_value(5).unwrap();
+        vm.push_value(5).unwrap();
+        vm.jie(10).unwrap();
 
         assert_eq!(vm.ip, 10);
     }
 
     #[test]
     fn jine() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+        let mut vm = VirtualMachine::default();
 
-        vm.push_value(10);
-        vm.push_value(5);
-        vm.jine(10);
+        vm.push_value(10).unwrap();
+        vm.push_value(5).unwrap();
+        vm.jine(10).unwrap();
 
         assert_eq!(vm.ip, 10);
     }
 
     #[test]
     fn jilg() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+        let mut vm = VirtualMachine::default();
 
-        vm.push_value(10);
-        vm.push_value(5);
-        vm.jilg(10);
+        vm.push_value(10).unwrap();
+        vm.push_value(5).unwrap();
+        vm.jilg(10).unwrap();
 
         assert_eq!(vm.ip, 10);
     }
 
     #[test]
     fn jils() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+        let mut vm = VirtualMachine::default();
 
-        vm.push_value(5);
-        vm.push_value(10);
-        vm.jils(10);
+        vm.push_value(5).unwrap();
+        vm.push_value(10).unwrap();
+        vm.jils(10).unwrap();
 
         assert_eq!(vm.ip, 10);
     }
 
     #[test]
     fn jilge() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+        let mut vm = VirtualMachine::default();
 
-        vm.push_value(10);
-        vm.push_value(5);
-        vm.jilge(5);
+        vm.push_value(10).unwrap();
+        vm.push_value(5).unwrap();
+        vm.jilge(5).unwrap();
 
         assert_eq!(vm.ip, 5);
 
-        vm.push_value(5);
-        vm.push_value(5);
-        vm.jilge(10);
+        vm.push_value(5).unwrap();
+        vm.push_value(5).unwrap();
+        vm.jilge(10).unwrap();
 
         assert_eq!(vm.ip, 10);
     }
 
     #[test]
     fn jilse() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
+        let mut vm = VirtualMachine::default();
 
-        vm.push_value(5);
-        vm.push_value(10);
-        vm.jilse(5);
+        vm.push_value(5).unwrap();
+        vm.push_value(10).unwrap();
+        vm.jilse(5).unwrap();
 
         assert_eq!(vm.ip, 5);
 
-        vm.push_value(5);
-        vm.push_value(5);
-        vm.jilse(10);
+        vm.push_value(5).unwrap();
+        vm.push_value(5).unwrap();
+        vm.jilse(10).unwrap();
 
         assert_eq!(vm.ip, 10);
     }
 
     #[test]
-    fn call_function() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(5);
-        vm.call_stack.push(frame);
-        vm.ip = 5;
+    fn jump_if_true() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(0).unwrap();
+        vm.jump_if_true(5).unwrap();
+
+        assert_eq!(vm.ip, 0);
 
-        vm.call_function(10);
+        vm.push_value(-3).unwrap();
+        vm.jump_if_true(10).unwrap();
 
-        let actual_frame = vm.call_stack.peek().unwrap();
+        assert_eq!(vm.ip, 10);
+    }
+
+    #[test]
+    fn jump_if_zero() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(1).unwrap();
+        vm.jump_if_zero(5).unwrap();
+
+        assert_eq!(vm.ip, 0);
+
+        vm.push_value(0).unwrap();
+        vm.jump_if_zero(10).unwrap();
+
+        assert_eq!(vm.ip, 10);
+    }
+
+    #[test]
+    fn jump_table() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(1).unwrap();
+        vm.jump_table(&[5, 10, 15]).unwrap();
+
+        assert_eq!(vm.ip, 10);
+    }
+
+    #[test]
+    fn jump_table_should_fall_through_when_index_out_of_range() {
+        let mut vm = VirtualMachine::default();
+
+        vm.push_value(3).unwrap();
+        vm.jump_table(&[5, 10, 15]).unwrap();
+
+        assert_eq!(vm.ip, 0);
+
+        vm.push_value(-1).unwrap();
+        vm.jump_table(&[5, 10, 15]).unwrap();
+
+        assert_eq!(vm.ip, 0);
+    }
+
+    #[test]
+    fn call_function() {
+        let mut vm = VirtualMachine { ip: 5, ..VirtualMachine::default() };
+        vm.push_value(1).unwrap();
+
+        vm.call_function(10).unwrap();
 
-        assert_eq!(actual_frame.ip, 5);
+        assert_eq!(vm.frame.ip, 5);
+        assert_eq!(vm.frame.base, 1);
+        assert!(vm.operand_stack().is_empty());
         assert_eq!(vm.ip, 10);
     }
 
     #[test]
     fn return_void() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
-        let frame: Frame<isize> = Frame::new(5);
-        vm.call_stack.push(frame);
+        let mut vm = VirtualMachine { ip: 5, ..VirtualMachine::default() };
+        vm.call_function(10).unwrap();
+        vm.push_value(1).unwrap();
 
-        vm.return_void();
+        vm.return_void().unwrap();
 
         assert_eq!(vm.ip, 5);
+        assert!(vm.call_stack.is_empty());
+        assert!(vm.values.is_empty());
     }
 
     #[test]
     fn return_value() {
-        let mut vm = VirtualMachine::new();
-        let frame: Frame<isize> = Frame::new(0);
-        vm.call_stack.push(frame);
-        let frame: Frame<isize> = Frame::new(5);
-        vm.call_stack.push(frame);
-
-        vm.push_value(10);
-        vm.return_value();
+        let mut vm = VirtualMachine { ip: 5, ..VirtualMachine::default() };
+        vm.call_function(10).unwrap();
 
-        let actual_frame = vm.call_stack.peek_mut().unwrap();
+        vm.push_value(10).unwrap();
+        vm.return_value().unwrap();
 
-        assert_eq!(actual_frame.pop_value().unwrap(), 10);
+        assert_eq!(vm.pop_value().unwrap(), 10);
         assert_eq!(vm.ip, 5);
     }
 }
\ No newline at end of file